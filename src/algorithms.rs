@@ -1,10 +1,25 @@
 //! Rate-limiting algorithms.
 
+pub mod adaptive;
+pub mod composite;
+pub mod export;
+pub mod fixed_window;
 pub mod gcra;
 pub mod leaky_bucket;
+pub mod reconfigurable;
+pub mod sliding_window_counter;
+pub mod sliding_window_log;
 
-pub use self::gcra::*;
-pub use self::leaky_bucket::*;
+pub use self::adaptive::Adaptive;
+pub use self::composite::Composite;
+pub use self::export::{Exportable, ExportedState, WallClockAnchor};
+#[cfg(feature = "std")]
+pub use self::fixed_window::{FixedWindow, UntilNextWindow};
+pub use self::gcra::{AtomicGCRA, NotUntil, Schedule, GCRA};
+pub use self::leaky_bucket::{AtomicLeakyBucket, LeakyBucket, TooEarly};
+pub use self::reconfigurable::Reconfigurable;
+pub use self::sliding_window_counter::{SlidingWindowCounter, WindowEstimateFull};
+pub use self::sliding_window_log::{SlidingWindowLog, WindowFull};
 
 use crate::{clock, InconsistentCapacity, NegativeMultiDecision};
 
@@ -43,6 +58,55 @@ pub trait NonConformance<P: clock::Reference = <clock::DefaultClock as clock::Cl
     }
 }
 
+/// A point-in-time view of how much capacity a rate limiter state has
+/// left, e.g. for reporting `X-RateLimit-*` headers to clients.
+///
+/// Snapshots are taken with
+/// [`Algorithm::snapshot`](trait.Algorithm.html#tymethod.snapshot)
+/// and don't change the state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StateSnapshot<P: clock::Reference = <clock::DefaultClock as clock::Clock>::Instant> {
+    limit: u32,
+    remaining: u32,
+    reset_at: P,
+}
+
+impl<P: clock::Reference> StateSnapshot<P> {
+    pub(crate) fn new(limit: u32, remaining: u32, reset_at: P) -> Self {
+        StateSnapshot {
+            limit,
+            remaining,
+            reset_at,
+        }
+    }
+
+    /// Returns the number of cells that an unused rate limiter lets
+    /// through at once.
+    pub fn limit(&self) -> u32 {
+        self.limit
+    }
+
+    /// Returns the number of cells that could be let through at the
+    /// instant the snapshot was taken.
+    pub fn remaining(&self) -> u32 {
+        self.remaining
+    }
+
+    /// Returns the instant at which the rate limiter will have
+    /// replenished all of its capacity, if no further cells are let
+    /// through.
+    pub fn reset_at(&self) -> P {
+        self.reset_at
+    }
+
+    /// Returns the amount of time from `from` until the rate limiter
+    /// will have replenished all of its capacity. A zero duration is
+    /// returned if that is already the case at `from`.
+    pub fn reset_after(&self, from: P) -> Duration {
+        self.reset_at.duration_since(cmp::min(self.reset_at, from))
+    }
+}
+
 /// The trait that implementations of metered rate-limiter algorithms
 /// have to implement.
 ///
@@ -72,12 +136,40 @@ pub trait Algorithm<P: clock::Reference = <clock::DefaultClock as clock::Clock>:
     /// Constructs a rate limiter with the given parameters:
     /// `capacity` is the number of cells to allow, weighing
     /// `cell_weight`, every `per_time_unit`.
+    ///
+    /// Returns an error if the parameters don't work together, or if
+    /// the algorithm can't represent the rate they describe (e.g. a
+    /// zero `per_time_unit`).
     fn construct(
         capacity: NonZeroU32,
         cell_weight: NonZeroU32,
         per_time_unit: Duration,
     ) -> Result<Self, InconsistentCapacity>;
 
+    /// Constructs a rate limiter that lets through `capacity` cells,
+    /// weighing `cell_weight`, every `per_time_unit` on average, but
+    /// that accommodates bursts of at most `burst` units of weight at
+    /// once (rather than `capacity` units, as
+    /// [`construct`](#tymethod.construct) does).
+    ///
+    /// This allows, e.g., a sustained rate of 10 cells per second with
+    /// bursts of up to 500 cells.
+    ///
+    /// The default implementation is for algorithms that can't
+    /// separate the burst size from the rate: it only succeeds if
+    /// `burst` equals `capacity`.
+    fn construct_with_burst(
+        capacity: NonZeroU32,
+        cell_weight: NonZeroU32,
+        per_time_unit: Duration,
+        burst: NonZeroU32,
+    ) -> Result<Self, InconsistentCapacity> {
+        if burst != capacity {
+            return Err(InconsistentCapacity::burst_unsupported(capacity, burst));
+        }
+        Self::construct(capacity, cell_weight, per_time_unit)
+    }
+
     /// Tests if `n` cells can be accommodated in the rate limiter at
     /// the instant `at` and updates the rate-limiter state to account
     /// for the weight of the cells and updates the ratelimiter state.
@@ -112,6 +204,315 @@ pub trait Algorithm<P: clock::Reference = <clock::DefaultClock as clock::Clock>:
             ),
         }
     }
+
+    /// Tests if `n` cells can be accommodated in the rate limiter at
+    /// the instant `at` and updates the rate limiter state, like
+    /// [`test_n_and_update`](#tymethod.test_n_and_update) does. If
+    /// the cells conform, returns a snapshot of the capacity that is
+    /// left after accommodating them.
+    ///
+    /// The default implementation takes the snapshot after updating
+    /// the state, so decisions made in between can be reflected in
+    /// it. Algorithms that can, take the snapshot as part of the
+    /// update instead.
+    fn test_n_and_update_with_info(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        at: P,
+    ) -> Result<StateSnapshot<P>, NegativeMultiDecision<Self::NegativeDecision>> {
+        self.test_n_and_update(state, n, at)?;
+        Ok(self.snapshot(state, at))
+    }
+
+    /// Tests if a single cell can be accommodated in the rate limiter
+    /// at the instant `at` and updates the rate limiter state. If the
+    /// cell conforms, returns a snapshot of the capacity that is left
+    /// after accommodating it.
+    ///
+    /// This method is provided by default, using the `n`
+    /// test&update-with-info method.
+    fn test_and_update_with_info(
+        &self,
+        state: &Self::BucketState,
+        at: P,
+    ) -> Result<StateSnapshot<P>, Self::NegativeDecision> {
+        match self.test_n_and_update_with_info(state, 1, at) {
+            Ok(snapshot) => Ok(snapshot),
+            Err(NegativeMultiDecision::BatchNonConforming(1, nc)) => Err(nc),
+            Err(other) => unreachable!(
+                "BUG: measuring a batch of size 1 reported insufficient capacity: {:?}",
+                other
+            ),
+        }
+    }
+
+    /// Reserves capacity for `n` cells at the earliest instant, no
+    /// earlier than `at`, at which they conform, and updates the rate
+    /// limiter state to account for them. Returns that instant.
+    ///
+    /// If `n` exceeds the capacity of the rate limiter, returns
+    /// [`NegativeMultiDecision::InsufficientCapacity`](../enum.NegativeMultiDecision.html#variant.InsufficientCapacity).
+    ///
+    /// The default implementation checks the cells at the instants
+    /// that negative decisions report, until they conform.
+    fn reserve_n(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        at: P,
+    ) -> Result<P, NegativeMultiDecision<Self::NegativeDecision>>
+    where
+        Self::NegativeDecision: NonConformance<P>,
+    {
+        let mut at = at;
+        loop {
+            match self.test_n_and_update(state, n, at) {
+                Ok(()) => return Ok(at),
+                Err(NegativeMultiDecision::BatchNonConforming(n, nc)) => {
+                    if nc.earliest_possible() <= at {
+                        return Err(NegativeMultiDecision::BatchNonConforming(n, nc));
+                    }
+                    at = nc.earliest_possible();
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Lets through as many of `n` cells as can be accommodated in
+    /// the rate limiter at the instant `at`, and updates the rate
+    /// limiter state to account for them. Returns the number of cells
+    /// that were let through, and, if that is fewer than `n`, the
+    /// negative decision for the next cell of the remainder.
+    ///
+    /// Unlike [`test_n_and_update`](#tymethod.test_n_and_update),
+    /// this never reports insufficient capacity: Batches larger than
+    /// the rate limiter's capacity are let through up to that
+    /// capacity.
+    ///
+    /// The default implementation tests ever smaller parts of the
+    /// remaining cells, until a single cell does not conform.
+    fn test_up_to_n_and_update(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        at: P,
+    ) -> (u32, Option<Self::NegativeDecision>) {
+        let mut accepted = 0;
+        let mut batch = n;
+        while accepted < n {
+            batch = cmp::min(batch, n - accepted);
+            match self.test_n_and_update(state, batch, at) {
+                Ok(()) => accepted += batch,
+                Err(NegativeMultiDecision::BatchNonConforming(1, nc)) => {
+                    return (accepted, Some(nc))
+                }
+                Err(other) if batch == 1 => unreachable!(
+                    "BUG: measuring a batch of size 1 reported insufficient capacity: {:?}",
+                    other
+                ),
+                Err(_) => batch /= 2,
+            }
+        }
+        (accepted, None)
+    }
+
+    /// Tests if `n` cells could be accommodated in the rate limiter
+    /// at the instant `at`, without updating the rate limiter state.
+    ///
+    /// The result is the same that
+    /// [`test_n_and_update`](#tymethod.test_n_and_update) would
+    /// return if it were called instead.
+    fn test_n(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        at: P,
+    ) -> Result<(), NegativeMultiDecision<Self::NegativeDecision>>;
+
+    /// Tests if a single cell could be accommodated in the rate
+    /// limiter at the instant `at`, without updating the rate limiter
+    /// state.
+    ///
+    /// This method is provided by default, using the `n` test method.
+    fn test(&self, state: &Self::BucketState, at: P) -> Result<(), Self::NegativeDecision> {
+        match self.test_n(state, 1, at) {
+            Ok(()) => Ok(()),
+            Err(NegativeMultiDecision::BatchNonConforming(1, nc)) => Err(nc),
+            Err(other) => unreachable!(
+                "BUG: measuring a batch of size 1 reported insufficient capacity: {:?}",
+                other
+            ),
+        }
+    }
+
+    /// Charges `n` cells to the rate limiter state unconditionally,
+    /// e.g. for work whose cost is only known after it was done.
+    ///
+    /// Charging can take the state past its capacity, into "debt":
+    /// Cells tested after that are non-conforming until the debt has
+    /// been paid off. The debt is capped at `debt_ceiling` cells
+    /// beyond the capacity; anything charged past that is forgiven.
+    /// Charging never makes the state more lenient than it already
+    /// was.
+    ///
+    /// The default implementation is for algorithms that can't go
+    /// into debt: It charges as many of the cells as fit, like
+    /// [`test_up_to_n_and_update`](#method.test_up_to_n_and_update)
+    /// does, and forgives the rest.
+    fn charge_n(&self, state: &Self::BucketState, n: u32, _debt_ceiling: u32, at: P) {
+        self.test_up_to_n_and_update(state, n, at);
+    }
+
+    /// Gives the capacity of `n` previously accepted cells back to the
+    /// rate limiter state, e.g. when the work they stood for was
+    /// cancelled before it started.
+    ///
+    /// Refunds are clamped so that they can never leave more capacity
+    /// than an empty bucket holds, no matter how many cells are
+    /// refunded.
+    ///
+    /// The default implementation is for algorithms that can't give
+    /// capacity back: It leaves the state alone.
+    fn refund_n(&self, _state: &Self::BucketState, _n: u32, _at: P) {}
+
+    /// Returns a snapshot of the capacity that the rate limiter state
+    /// has left at the instant `at`, without updating the state.
+    fn snapshot(&self, state: &Self::BucketState, at: P) -> StateSnapshot<P>;
+
+    /// Converts a rate limiter state that was accumulated under the
+    /// parameters of `previous` into this algorithm's parameters at
+    /// the instant `at`, e.g. when a rate limiter is
+    /// [reconfigured](reconfigurable/struct.Reconfigurable.html).
+    ///
+    /// The number of cells that the state accounts for stays the
+    /// same; only the time they take up is adjusted. The default
+    /// implementation leaves the state alone, so the new parameters
+    /// apply to it as it is.
+    fn rescale(&self, _state: &Self::BucketState, _previous: &Self, _at: P) {}
+
+    /// Returns a copy of `state`, converted like
+    /// [`rescale`](#method.rescale) would convert it, and leaves
+    /// `state` itself alone. This lets rate limiters that
+    /// [peek](#tymethod.test_n) at a state or take a
+    /// [snapshot](#tymethod.snapshot) of it see it under the new
+    /// parameters without updating it.
+    ///
+    /// The default implementation returns `None`, meaning that the
+    /// new parameters apply to `state` as it is.
+    fn rescaled(
+        &self,
+        _state: &Self::BucketState,
+        _previous: &Self,
+        _at: P,
+    ) -> Option<Self::BucketState> {
+        None
+    }
+}
+
+/// Converts a number of nanoseconds that fits into a `Duration`.
+pub(crate) fn from_nanos(nanos: u128) -> Duration {
+    Duration::new(
+        (nanos / 1_000_000_000) as u64,
+        (nanos % 1_000_000_000) as u32,
+    )
+}
+
+/// Returns the weight of a cell and the size of a burst in fractions
+/// of a nanosecond, along with the number of fractions that make up a
+/// nanosecond, all reduced to their lowest terms.
+///
+/// Returns an error if no cell could ever be rate-limited at these
+/// parameters (i.e., the time unit is zero), or if the weight of a
+/// batch of `u32::MAX` cells would overflow.
+pub(crate) fn fractional_intervals(
+    capacity: NonZeroU32,
+    cell_weight: NonZeroU32,
+    per_time_unit: Duration,
+    burst: NonZeroU32,
+) -> Result<(u128, u128, u32), InconsistentCapacity> {
+    let nanos = per_time_unit.as_nanos();
+    let weight = nanos * u128::from(cell_weight.get());
+    let burst = nanos * u128::from(burst.get());
+    let divisor = gcd(gcd(weight, burst), u128::from(capacity.get()));
+    let (weight, burst) = (weight / divisor, burst / divisor);
+    if nanos == 0 || burst.checked_mul(u128::from(u32::MAX)).is_none() {
+        return Err(InconsistentCapacity::rate_unrepresentable(
+            capacity,
+            per_time_unit,
+        ));
+    }
+    Ok((weight, burst, (u128::from(capacity.get()) / divisor) as u32))
+}
+
+/// Scales `amount` by `numerator / denominator`, rounding down, or
+/// returns `None` if the result doesn't fit.
+///
+/// Intermediate results stay small as long as the result does, so
+/// this only fails where the result itself would overflow, or where
+/// `numerator` and `denominator` share few factors and are both huge.
+pub(crate) fn checked_scale(amount: u128, numerator: u128, denominator: u128) -> Option<u128> {
+    let divisor = gcd(numerator, denominator);
+    let (numerator, denominator) = (numerator / divisor, denominator / divisor);
+    let whole = (amount / denominator).checked_mul(numerator)?;
+    let part = (amount % denominator).checked_mul(numerator)? / denominator;
+    whole.checked_add(part)
+}
+
+/// Returns the greatest common divisor of `a` and `b`.
+pub(crate) fn gcd(mut a: u128, mut b: u128) -> u128 {
+    while b != 0 {
+        let r = a % b;
+        a = b;
+        b = r;
+    }
+    a
+}
+
+/// Returns the number of times that `per_cell` fits into `span`,
+/// saturating at `u32::MAX`.
+pub(crate) fn cells_within(span: u128, per_cell: u128) -> u32 {
+    span.checked_div(per_cell).map_or(u32::MAX, |cells| {
+        cmp::min(cells, u128::from(u32::MAX)) as u32
+    })
+}
+
+/// Encodes a time stamp as nanoseconds after `origin`, to be kept in
+/// an atomic integer. Zero is reserved to mean "no time stamp"; all
+/// other values are offset by one.
+///
+/// Time stamps before the origin are rounded up to the origin.
+pub(crate) fn encode_since<P: clock::Reference>(origin: P, at: P) -> u64 {
+    let nanos = at.duration_since(origin).as_nanos();
+    cmp::min(nanos, u128::from(u64::MAX - 1)) as u64 + 1
+}
+
+/// Checks that a cell, taking up `interval` of the `denominator`
+/// fractions that make up a nanosecond, spans at least one whole
+/// nanosecond. States that keep time stamps
+/// encoded with [`encode_since`] can't represent faster rates.
+pub(crate) fn check_whole_nanos(
+    interval: u128,
+    denominator: u32,
+    capacity: NonZeroU32,
+    per_time_unit: Duration,
+) -> Result<(), InconsistentCapacity> {
+    if interval < u128::from(denominator) {
+        return Err(InconsistentCapacity::rate_unrepresentable(
+            capacity,
+            per_time_unit,
+        ));
+    }
+    Ok(())
+}
+
+/// Decodes a time stamp encoded with [`encode_since`].
+pub(crate) fn decode_since<P: clock::Reference>(origin: P, nanos: u64) -> Option<P> {
+    match nanos {
+        0 => None,
+        n => Some(origin + Duration::from_nanos(n - 1)),
+    }
 }
 
 /// Trait that all rate limit states have to implement around
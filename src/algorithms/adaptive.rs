@@ -0,0 +1,429 @@
+//! An adaptive rate limiter that adjusts its rate based on feedback
+
+use crate::lib::*;
+use crate::{
+    algorithms::{reconfigurable, Algorithm, RateLimitState, Reconfigurable, StateSnapshot},
+    clock, InconsistentCapacity, InvalidAdaptation, NegativeMultiDecision, NonConformance,
+};
+
+#[cfg(feature = "std")]
+use parking_lot::Mutex;
+
+#[cfg(not(feature = "std"))]
+use spin::Mutex;
+
+/// Wraps a rate limiting algorithm (e.g., [`GCRA`](../gcra/struct.GCRA.html)
+/// or a [`LeakyBucket`](../leaky_bucket/struct.LeakyBucket.html)) and
+/// adjusts its rate at runtime, using an additive increase /
+/// multiplicative decrease (AIMD) scheme:
+///
+/// * [`on_success`](#method.on_success) increases the number of cells
+///   allowed per time unit by a fixed step, up to a ceiling.
+/// * [`on_backoff`](#method.on_backoff) multiplies the number of cells
+///   allowed per time unit by a factor between 0 and 1, down to a
+///   floor.
+///
+/// This lets the rate creep up steadily while a downstream service
+/// keeps up, and drop sharply when it throttles or fails requests.
+///
+/// Clones of an adaptive algorithm share their rate, so a clone can
+/// be passed to a rate limiter while the original is kept around to
+/// report feedback. Like with a
+/// [`Reconfigurable`](../reconfigurable/struct.Reconfigurable.html)
+/// algorithm, the rate limiting states are rescaled to the new rate
+/// the next time they are used, so the cells they account for keep
+/// counting against it.
+///
+/// Feedback only updates an atomic integer, so reporting it doesn't
+/// block decisions. Decreases are handed to the rate limiter right
+/// away, but increases only once they add up to at least an eighth of
+/// the rate it currently uses: That way, a steady stream of successes
+/// doesn't make every state rescale on every step.
+///
+/// By default, the rate starts at the `capacity` it was constructed
+/// with, which is also its ceiling; it goes up by one cell per
+/// success, is halved on backoff, and never drops below one cell per
+/// time unit (i.e., a capacity of `cell_weight`).
+///
+/// # Example
+/// ``` rust
+/// # use ratelimit_meter::{algorithms::{Adaptive, Algorithm}, DirectRateLimiter, GCRA};
+/// # use std::time::Duration;
+/// # #[macro_use] extern crate nonzero_ext;
+/// # extern crate ratelimit_meter;
+/// # #[cfg(feature = "std")]
+/// # fn main () {
+/// let adaptive = <Adaptive<GCRA> as Algorithm>::construct(
+///     nonzero!(50u32),
+///     nonzero!(1u32),
+///     Duration::from_secs(1),
+/// )
+/// .unwrap()
+/// .with_bounds(nonzero!(5u32), nonzero!(100u32))
+/// .unwrap();
+/// let mut lim = DirectRateLimiter::<Adaptive<GCRA>>::with_algorithm(adaptive.clone());
+/// if lim.check().is_ok() {
+///     // ...call the downstream service; it asks us to slow down:
+///     adaptive.on_backoff();
+/// }
+/// assert_eq!(nonzero!(25u32), adaptive.capacity());
+/// # }
+/// # #[cfg(not(feature = "std"))] fn main() {}
+/// ```
+pub struct Adaptive<
+    A: Algorithm<P>,
+    P: clock::Reference = <clock::DefaultClock as clock::Clock>::Instant,
+> {
+    cell_weight: NonZeroU32,
+    per_time_unit: Duration,
+    burst: Option<NonZeroU32>,
+    floor: NonZeroU32,
+    ceiling: NonZeroU32,
+    increase: u32,
+    decrease_factor: f64,
+    // The rate that feedback arrived at:
+    capacity: Arc<AtomicU32>,
+    // The rate that `inner` was last constructed with, which is only
+    // changed while holding `publishing`:
+    published: Arc<AtomicU32>,
+    publishing: Arc<Mutex<()>>,
+    inner: Reconfigurable<A, P>,
+    // The wrapped algorithm at the floor rate, which states take the
+    // longest to recover at:
+    slowest: Arc<A>,
+}
+
+impl<A: Algorithm<P>, P: clock::Reference> Clone for Adaptive<A, P> {
+    fn clone(&self) -> Self {
+        Adaptive {
+            cell_weight: self.cell_weight,
+            per_time_unit: self.per_time_unit,
+            burst: self.burst,
+            floor: self.floor,
+            ceiling: self.ceiling,
+            increase: self.increase,
+            decrease_factor: self.decrease_factor,
+            capacity: self.capacity.clone(),
+            published: self.published.clone(),
+            publishing: self.publishing.clone(),
+            inner: self.inner.clone(),
+            slowest: self.slowest.clone(),
+        }
+    }
+}
+
+impl<A: Algorithm<P>, P: clock::Reference> fmt::Debug for Adaptive<A, P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(
+            f,
+            "Adaptive{{capacity: {}, floor: {}, ceiling: {}, algorithm: {:?}}}",
+            self.capacity(),
+            self.floor,
+            self.ceiling,
+            self.inner
+        )
+    }
+}
+
+impl<A: Algorithm<P>, P: clock::Reference> Adaptive<A, P> {
+    /// Sets the lowest and highest number of cells that can be
+    /// allowed per time unit. The current rate is clamped to these
+    /// bounds.
+    ///
+    /// Returns an error if the wrapped algorithm can't be constructed
+    /// with the floor as its capacity, or if the ceiling is below the
+    /// floor.
+    pub fn with_bounds(
+        mut self,
+        floor: NonZeroU32,
+        ceiling: NonZeroU32,
+    ) -> Result<Self, InvalidAdaptation> {
+        if ceiling < floor {
+            return Err(InvalidAdaptation::bounds_inverted(floor, ceiling));
+        }
+        let slowest = self
+            .construct_inner(floor)
+            .map_err(InvalidAdaptation::floor_inconsistent)?;
+        self.slowest = Arc::new(slowest);
+        self.floor = floor;
+        self.ceiling = ceiling;
+        self.adjust(|capacity| capacity);
+        self.publish(|capacity, published| capacity != published);
+        Ok(self)
+    }
+
+    /// Sets the number of cells per time unit by which the rate goes
+    /// up on [`on_success`](#method.on_success).
+    pub fn with_increase(mut self, step: u32) -> Self {
+        self.increase = step;
+        self
+    }
+
+    /// Sets the factor by which the number of cells per time unit is
+    /// multiplied on [`on_backoff`](#method.on_backoff).
+    ///
+    /// Returns an error if `factor` is not between 0 and 1.
+    pub fn with_decrease_factor(mut self, factor: f64) -> Result<Self, InvalidAdaptation> {
+        if !(0.0..=1.0).contains(&factor) {
+            return Err(InvalidAdaptation::decrease_factor_out_of_range(factor));
+        }
+        self.decrease_factor = factor;
+        Ok(self)
+    }
+
+    /// Returns the number of cells per time unit that feedback
+    /// arrived at. Since rate limiters pick up increases in steps,
+    /// they may still use a slightly lower rate for a while.
+    pub fn capacity(&self) -> NonZeroU32 {
+        NonZeroU32::new(self.capacity.load(Ordering::Acquire)).unwrap_or(self.floor)
+    }
+
+    /// Reports that a cell was processed successfully, increasing the
+    /// rate by the configured step (up to the ceiling).
+    pub fn on_success(&self) {
+        self.adjust(|capacity| capacity.saturating_add(self.increase));
+        // Only take the lock once the increase adds up to an eighth:
+        let worth = |capacity, published| {
+            capacity > published && capacity - published >= cmp::max(1, published / 8)
+        };
+        if worth(
+            self.capacity.load(Ordering::Acquire),
+            self.published.load(Ordering::Acquire),
+        ) {
+            self.publish(worth);
+        }
+    }
+
+    /// Reports that a cell was throttled or failed downstream,
+    /// multiplying the rate by the configured factor (down to the
+    /// floor).
+    pub fn on_backoff(&self) {
+        self.adjust(|capacity| (f64::from(capacity) * self.decrease_factor) as u32);
+        // Always check under the lock, so that the decrease isn't
+        // missed while another thread publishes an increase:
+        self.publish(|capacity, published| capacity < published);
+    }
+
+    /// Computes the new capacity from the current one and clamps it
+    /// to the bounds.
+    fn adjust<F: Fn(u32) -> u32>(&self, f: F) {
+        let (floor, ceiling) = (self.floor.get(), self.ceiling.get());
+        let _ = self
+            .capacity
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |capacity| {
+                Some(cmp::min(cmp::max(f(capacity), floor), ceiling))
+            });
+    }
+
+    /// Swaps in the algorithm for the current capacity if `worth`
+    /// returns true for it and the capacity that rate limiters use.
+    /// States are rescaled to it the next time they are used.
+    fn publish<F: Fn(u32, u32) -> bool>(&self, worth: F) {
+        let _publishing = self.publishing.lock();
+        let capacity = self.capacity.load(Ordering::Acquire);
+        let published = self.published.load(Ordering::Acquire);
+        if !worth(capacity, published) {
+            return;
+        }
+        // The floor was validated to construct successfully; if a
+        // higher capacity can't be represented, go back to the
+        // published one.
+        match self.construct_inner(NonZeroU32::new(capacity).unwrap_or(self.floor)) {
+            Ok(algorithm) => {
+                self.inner.replace(algorithm);
+                self.published.store(capacity, Ordering::Release);
+            }
+            Err(_) => {
+                let _ = self.capacity.compare_exchange(
+                    capacity,
+                    published,
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                );
+            }
+        }
+    }
+
+    fn construct_inner(&self, capacity: NonZeroU32) -> Result<A, InconsistentCapacity> {
+        Self::construct_params(capacity, self.cell_weight, self.per_time_unit, self.burst)
+    }
+
+    fn construct_params(
+        capacity: NonZeroU32,
+        cell_weight: NonZeroU32,
+        per_time_unit: Duration,
+        burst: Option<NonZeroU32>,
+    ) -> Result<A, InconsistentCapacity> {
+        match burst {
+            Some(burst) => A::construct_with_burst(capacity, cell_weight, per_time_unit, burst),
+            None => A::construct(capacity, cell_weight, per_time_unit),
+        }
+    }
+
+    fn with_params(
+        capacity: NonZeroU32,
+        cell_weight: NonZeroU32,
+        per_time_unit: Duration,
+        burst: Option<NonZeroU32>,
+    ) -> Result<Self, InconsistentCapacity> {
+        let algorithm = Self::construct_params(capacity, cell_weight, per_time_unit, burst)?;
+        let slowest = Self::construct_params(cell_weight, cell_weight, per_time_unit, burst)?;
+        Ok(Adaptive {
+            cell_weight,
+            per_time_unit,
+            burst,
+            floor: cell_weight,
+            ceiling: capacity,
+            increase: 1,
+            decrease_factor: 0.5,
+            capacity: Arc::new(AtomicU32::new(capacity.get())),
+            published: Arc::new(AtomicU32::new(capacity.get())),
+            publishing: Arc::new(Mutex::new(())),
+            inner: Reconfigurable::new(algorithm),
+            slowest: Arc::new(slowest),
+        })
+    }
+}
+
+/// Represents the state of a single history of decisions, as kept
+/// by the wrapped algorithm, along with the rate it was last used
+/// with.
+pub struct State<A: Algorithm<P>, P: clock::Reference>(reconfigurable::State<A, P>);
+
+impl<A: Algorithm<P>, P: clock::Reference> Default for State<A, P> {
+    fn default() -> Self {
+        State(Default::default())
+    }
+}
+
+impl<A: Algorithm<P>, P: clock::Reference> Clone for State<A, P>
+where
+    A::BucketState: Clone,
+{
+    fn clone(&self) -> Self {
+        State(self.0.clone())
+    }
+}
+
+impl<A: Algorithm<P>, P: clock::Reference> fmt::Debug for State<A, P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        self.0.fmt(f)
+    }
+}
+
+impl<A: Algorithm<P>, P: clock::Reference> PartialEq for State<A, P> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl<A: Algorithm<P>, P: clock::Reference> Eq for State<A, P> {}
+
+impl<A: Algorithm<P>, P: clock::Reference> RateLimitState<Adaptive<A, P>, P> for State<A, P> {
+    fn last_touched(&self, params: &Adaptive<A, P>) -> Option<P> {
+        // A slower rate takes longer to recover; use the floor to
+        // make sure the state isn't expired too early.
+        self.0.last_touched_by(&params.slowest)
+    }
+}
+
+#[cfg(feature = "std")]
+mod std {
+    use crate::algorithms::{Algorithm, KeyableRateLimitState};
+    use crate::clock;
+    use evmap::ShallowCopy;
+
+    impl<A: Algorithm<P>, P: clock::Reference> ShallowCopy for super::State<A, P>
+    where
+        A::BucketState: KeyableRateLimitState<A, P>,
+    {
+        unsafe fn shallow_copy(&mut self) -> Self {
+            super::State(self.0.shallow_copy())
+        }
+    }
+}
+
+impl<A: Algorithm<P>, P: clock::Reference> Algorithm<P> for Adaptive<A, P> {
+    type BucketState = State<A, P>;
+
+    type NegativeDecision = A::NegativeDecision;
+
+    /// Constructs an adaptive rate limiter whose rate starts at (and
+    /// is capped by) `capacity` cells per `per_time_unit`.
+    fn construct(
+        capacity: NonZeroU32,
+        cell_weight: NonZeroU32,
+        per_time_unit: Duration,
+    ) -> Result<Self, InconsistentCapacity> {
+        Self::with_params(capacity, cell_weight, per_time_unit, None)
+    }
+
+    fn construct_with_burst(
+        capacity: NonZeroU32,
+        cell_weight: NonZeroU32,
+        per_time_unit: Duration,
+        burst: NonZeroU32,
+    ) -> Result<Self, InconsistentCapacity> {
+        Self::with_params(capacity, cell_weight, per_time_unit, Some(burst))
+    }
+
+    fn test_n_and_update(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        t0: P,
+    ) -> Result<(), NegativeMultiDecision<Self::NegativeDecision>> {
+        self.inner.test_n_and_update(&state.0, n, t0)
+    }
+
+    fn test_n_and_update_with_info(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        t0: P,
+    ) -> Result<StateSnapshot<P>, NegativeMultiDecision<Self::NegativeDecision>> {
+        self.inner.test_n_and_update_with_info(&state.0, n, t0)
+    }
+
+    fn reserve_n(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        t0: P,
+    ) -> Result<P, NegativeMultiDecision<Self::NegativeDecision>>
+    where
+        Self::NegativeDecision: NonConformance<P>,
+    {
+        self.inner.reserve_n(&state.0, n, t0)
+    }
+
+    fn test_up_to_n_and_update(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        t0: P,
+    ) -> (u32, Option<Self::NegativeDecision>) {
+        self.inner.test_up_to_n_and_update(&state.0, n, t0)
+    }
+
+    fn test_n(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        t0: P,
+    ) -> Result<(), NegativeMultiDecision<Self::NegativeDecision>> {
+        self.inner.test_n(&state.0, n, t0)
+    }
+
+    fn charge_n(&self, state: &Self::BucketState, n: u32, debt_ceiling: u32, t0: P) {
+        self.inner.charge_n(&state.0, n, debt_ceiling, t0)
+    }
+
+    fn snapshot(&self, state: &Self::BucketState, t0: P) -> StateSnapshot<P> {
+        self.inner.snapshot(&state.0, t0)
+    }
+
+    fn refund_n(&self, state: &Self::BucketState, n: u32, t0: P) {
+        self.inner.refund_n(&state.0, n, t0)
+    }
+}
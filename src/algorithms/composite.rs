@@ -0,0 +1,349 @@
+//! A composite of several GCRA bandwidths
+
+use crate::lib::*;
+use crate::thread_safety::ThreadsafeWrapper;
+use crate::{
+    algorithms::{
+        gcra::Arrival, Algorithm, NonConformance, NotUntil, RateLimitState, StateSnapshot, GCRA,
+    },
+    clock, InconsistentCapacity, NegativeMultiDecision,
+};
+
+/// Enforces several rate limits (each one a "bandwidth") at once,
+/// e.g. "10 cells per second, and 1000 cells per hour".
+///
+/// Each bandwidth is a [GCRA](../gcra/struct.GCRA.html), and a cell
+/// is only let through if it conforms to all of them. The bandwidths
+/// share a single rate limiting state, so decisions are all or
+/// nothing: If any of the bandwidths can not accommodate the cells,
+/// none of them are charged for them. (Chaining several rate limiters
+/// by hand would charge the earlier ones even if a later one denies
+/// the cells.)
+///
+/// The negative decision reports the longest wait time of all the
+/// bandwidths that can not accommodate the cells. Reservations and
+/// checks that let through part of a batch are answered exactly, as
+/// for a single GCRA: at the instant that suits all bandwidths, and
+/// with as many cells as fit into every bandwidth.
+///
+/// # Example
+/// ``` rust
+/// # use ratelimit_meter::{algorithms::Algorithm, Composite, DirectRateLimiter, GCRA};
+/// # use std::time::Duration;
+/// # #[macro_use] extern crate nonzero_ext;
+/// # extern crate ratelimit_meter;
+/// # #[cfg(feature = "std")]
+/// # fn main () {
+/// // Allow 10 cells per second, but no more than 1000 per hour:
+/// let algorithm = <Composite as Algorithm>::construct(nonzero!(10u32), nonzero!(1u32), Duration::from_secs(1))
+///     .unwrap()
+///     .and(GCRA::construct(nonzero!(1000u32), nonzero!(1u32), Duration::from_secs(3600)).unwrap());
+/// let mut lim = DirectRateLimiter::<Composite>::with_algorithm(algorithm);
+/// assert_eq!(Ok(()), lim.check());
+/// # }
+/// # #[cfg(not(feature = "std"))] fn main() {}
+/// ```
+#[derive(Debug, Clone)]
+pub struct Composite<P: clock::Reference = <clock::DefaultClock as clock::Clock>::Instant> {
+    bandwidths: Vec<GCRA<P>>,
+}
+
+impl<P: clock::Reference> Composite<P> {
+    /// Adds another bandwidth that cells must conform to.
+    pub fn and(mut self, bandwidth: GCRA<P>) -> Self {
+        self.bandwidths.push(bandwidth);
+        self
+    }
+
+    /// Decides whether `n` cells conform to all bandwidths at `t0`,
+    /// without updating their theoretical arrival times.
+    fn decide(
+        &self,
+        tats: &[Option<Arrival<P>>],
+        n: u32,
+        t0: P,
+    ) -> Result<(), NegativeMultiDecision<NotUntil<P>>> {
+        let mut longest: Option<NotUntil<P>> = None;
+        for (i, bandwidth) in self.bandwidths.iter().enumerate() {
+            let tat = tats.get(i).copied().flatten();
+            match bandwidth.test_n_tat(tat, n, t0) {
+                Ok(_) => {}
+                Err(NegativeMultiDecision::BatchNonConforming(_, nc)) => {
+                    longest = match longest {
+                        Some(l) if l.earliest_possible() >= nc.earliest_possible() => Some(l),
+                        _ => Some(nc),
+                    };
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        match longest {
+            Some(nc) => Err(NegativeMultiDecision::BatchNonConforming(n, nc)),
+            None => Ok(()),
+        }
+    }
+
+    /// Accommodates `n` cells at `t0` in all bandwidths' theoretical
+    /// arrival times if they conform to all of them, and leaves the
+    /// times alone otherwise.
+    fn update(
+        &self,
+        tats: &mut Vec<Option<Arrival<P>>>,
+        n: u32,
+        t0: P,
+    ) -> Result<(), NegativeMultiDecision<NotUntil<P>>> {
+        // Check all bandwidths first, and only update them if all
+        // of them conform:
+        self.decide(tats, n, t0)?;
+        tats.resize(self.bandwidths.len(), None);
+        for (bandwidth, tat) in self.bandwidths.iter().zip(tats.iter_mut()) {
+            if let Ok(updated) = bandwidth.test_n_tat(*tat, n, t0) {
+                *tat = Some(updated);
+            }
+        }
+        Ok(())
+    }
+
+    /// Converts the theoretical arrival times that were accumulated
+    /// under the bandwidths of `previous` into this composite's
+    /// bandwidths at `t0`.
+    fn rescale_tats(&self, tats: &mut Vec<Option<Arrival<P>>>, previous: &Self, t0: P) {
+        tats.truncate(self.bandwidths.len());
+        let bandwidths = self.bandwidths.iter().zip(previous.bandwidths.iter());
+        for ((bandwidth, previous), tat) in bandwidths.zip(tats.iter_mut()) {
+            *tat = bandwidth.rescale_tat(*tat, previous, t0);
+        }
+    }
+
+    /// Combines the bandwidths' snapshots into one for the most
+    /// restrictive of them.
+    fn snapshot_tats(&self, tats: &[Option<Arrival<P>>], t0: P) -> StateSnapshot<P> {
+        let (mut limit, mut remaining, mut reset_at) = (u32::MAX, u32::MAX, t0);
+        for (i, bandwidth) in self.bandwidths.iter().enumerate() {
+            let snapshot = bandwidth.snapshot_tat(tats.get(i).copied().flatten(), t0);
+            limit = cmp::min(limit, snapshot.limit());
+            remaining = cmp::min(remaining, snapshot.remaining());
+            reset_at = cmp::max(reset_at, snapshot.reset_at());
+        }
+        StateSnapshot::new(limit, remaining, reset_at)
+    }
+}
+
+/// The theoretical arrival times of all bandwidths of a composite
+/// rate limiter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Tats<P: clock::Reference>(Vec<Option<Arrival<P>>>);
+
+impl<P: clock::Reference> Default for Tats<P> {
+    fn default() -> Self {
+        Tats(Vec::new())
+    }
+}
+
+/// Represents the state of a single history of decisions.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct State<P: clock::Reference>(ThreadsafeWrapper<Tats<P>>);
+
+impl<P: clock::Reference> Default for State<P> {
+    fn default() -> Self {
+        State(Default::default())
+    }
+}
+
+impl<P: clock::Reference> RateLimitState<Composite<P>, P> for State<P> {
+    fn last_touched(&self, params: &Composite<P>) -> Option<P> {
+        let data = self.0.snapshot();
+        params
+            .bandwidths
+            .iter()
+            .zip(data.0)
+            .filter_map(|(bandwidth, tat)| bandwidth.last_touched_tat(tat))
+            .max()
+    }
+}
+
+#[cfg(feature = "std")]
+mod std {
+    use crate::clock;
+    use evmap::ShallowCopy;
+
+    impl<P: clock::Reference> ShallowCopy for super::State<P> {
+        unsafe fn shallow_copy(&mut self) -> Self {
+            super::State(self.0.shallow_copy())
+        }
+    }
+}
+
+impl<P: clock::Reference> Algorithm<P> for Composite<P> {
+    type BucketState = State<P>;
+
+    type NegativeDecision = NotUntil<P>;
+
+    /// Constructs a composite with a single bandwidth; use
+    /// [`and`](#method.and) to add more.
+    fn construct(
+        capacity: NonZeroU32,
+        cell_weight: NonZeroU32,
+        per_time_unit: Duration,
+    ) -> Result<Self, InconsistentCapacity> {
+        let bandwidth = GCRA::construct(capacity, cell_weight, per_time_unit)?;
+        Ok(Composite {
+            bandwidths: Vec::new(),
+        }
+        .and(bandwidth))
+    }
+
+    fn construct_with_burst(
+        capacity: NonZeroU32,
+        cell_weight: NonZeroU32,
+        per_time_unit: Duration,
+        burst: NonZeroU32,
+    ) -> Result<Self, InconsistentCapacity> {
+        let bandwidth = GCRA::construct_with_burst(capacity, cell_weight, per_time_unit, burst)?;
+        Ok(Composite {
+            bandwidths: Vec::new(),
+        }
+        .and(bandwidth))
+    }
+
+    fn test_n_and_update(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        t0: P,
+    ) -> Result<(), NegativeMultiDecision<NotUntil<P>>> {
+        state
+            .0
+            .measure_and_update(|tats| self.update(&mut tats.0, n, t0))
+    }
+
+    fn test_n_and_update_with_info(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        t0: P,
+    ) -> Result<StateSnapshot<P>, NegativeMultiDecision<NotUntil<P>>> {
+        state.0.measure_and_update(|tats| {
+            self.update(&mut tats.0, n, t0)?;
+            Ok(self.snapshot_tats(&tats.0, t0))
+        })
+    }
+
+    /// Reserves the cells at the exact instant at which they conform
+    /// to all bandwidths: the latest of the instants at which they
+    /// conform to each one.
+    fn reserve_n(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        t0: P,
+    ) -> Result<P, NegativeMultiDecision<NotUntil<P>>> {
+        state.0.measure_and_update(|tats| {
+            let mut start = t0;
+            for (i, bandwidth) in self.bandwidths.iter().enumerate() {
+                let (conforming, _) =
+                    bandwidth.reserve_n_tat(tats.0.get(i).copied().flatten(), n, t0)?;
+                start = cmp::max(start, conforming);
+            }
+            self.update(&mut tats.0, n, start)?;
+            Ok(start)
+        })
+    }
+
+    /// Lets through as many cells as fit into every bandwidth, and
+    /// reports the longest wait of the bandwidths that reject the next
+    /// one.
+    fn test_up_to_n_and_update(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        t0: P,
+    ) -> (u32, Option<NotUntil<P>>) {
+        state.0.measure_and_update(|tats| {
+            let accepted = self
+                .bandwidths
+                .iter()
+                .enumerate()
+                .map(|(i, bandwidth)| {
+                    let (fitting, _, _) =
+                        bandwidth.test_up_to_n_tat(tats.0.get(i).copied().flatten(), n, t0);
+                    fitting
+                })
+                .min()
+                .unwrap_or(n);
+            if accepted > 0 {
+                tats.0.resize(self.bandwidths.len(), None);
+                for (bandwidth, tat) in self.bandwidths.iter().zip(tats.0.iter_mut()) {
+                    let (_, updated, _) = bandwidth.test_up_to_n_tat(*tat, accepted, t0);
+                    *tat = updated;
+                }
+            }
+            if accepted == n {
+                return (accepted, None);
+            }
+            match self.decide(&tats.0, 1, t0) {
+                Err(NegativeMultiDecision::BatchNonConforming(_, nc)) => (accepted, Some(nc)),
+                _ => (accepted, None),
+            }
+        })
+    }
+
+    fn test_n(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        t0: P,
+    ) -> Result<(), NegativeMultiDecision<NotUntil<P>>> {
+        state.0.measure(|tats| self.decide(&tats.0, n, t0))
+    }
+
+    /// Charges `n` cells to every bandwidth, each of which may go
+    /// into debt by up to `debt_ceiling` of its own cells.
+    fn charge_n(&self, state: &Self::BucketState, n: u32, debt_ceiling: u32, t0: P) {
+        state.0.measure_and_update(|tats| {
+            tats.0.resize(self.bandwidths.len(), None);
+            for (bandwidth, tat) in self.bandwidths.iter().zip(tats.0.iter_mut()) {
+                *tat = Some(bandwidth.charge_n_tat(*tat, n, debt_ceiling, t0));
+            }
+        })
+    }
+
+    /// Reports the capacity left in the most restrictive bandwidth, and
+    /// the instant at which all bandwidths will have replenished their
+    /// capacity.
+    fn snapshot(&self, state: &Self::BucketState, t0: P) -> StateSnapshot<P> {
+        state.0.measure(|tats| self.snapshot_tats(&tats.0, t0))
+    }
+
+    /// Refunds `n` cells to every bandwidth.
+    fn refund_n(&self, state: &Self::BucketState, n: u32, t0: P) {
+        state.0.measure_and_update(|tats| {
+            for (bandwidth, tat) in self.bandwidths.iter().zip(tats.0.iter_mut()) {
+                *tat = bandwidth.refund_n_tat(*tat, n, t0);
+            }
+        })
+    }
+
+    /// Rescales each bandwidth's state from the bandwidth at the same
+    /// position in `previous`. The states of bandwidths that
+    /// `previous` had beyond this composite's are dropped, and
+    /// bandwidths added beyond `previous`'s start out unused.
+    fn rescale(&self, state: &Self::BucketState, previous: &Self, t0: P) {
+        state
+            .0
+            .measure_and_update(|tats| self.rescale_tats(&mut tats.0, previous, t0))
+    }
+
+    fn rescaled(
+        &self,
+        state: &Self::BucketState,
+        previous: &Self,
+        t0: P,
+    ) -> Option<Self::BucketState> {
+        let mut data = state.0.snapshot();
+        self.rescale_tats(&mut data.0, previous, t0);
+        let rescaled = State::default();
+        rescaled.0.measure_and_update(|tats| *tats = data);
+        Some(rescaled)
+    }
+}
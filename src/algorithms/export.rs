@@ -0,0 +1,108 @@
+//! Rate limiting states that outlive the process keeping them.
+
+use crate::lib::*;
+
+use crate::{algorithms::Algorithm, clock};
+
+/// Ties an instant of a rate limiter's clock to the wall-clock time
+/// (as the time since the UNIX epoch) at which it was measured.
+///
+/// Instants of most clocks (e.g. the
+/// [`MonotonicClock`](../../clock/struct.MonotonicClock.html)'s) lose
+/// their meaning when the process exits. Anchoring them to the wall
+/// clock lets a state exported by one process be restored by
+/// another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WallClockAnchor<P: clock::Reference> {
+    at: P,
+    since_epoch: Duration,
+}
+
+impl<P: clock::Reference> WallClockAnchor<P> {
+    /// Anchors the clock's instant `at` to the wall-clock time
+    /// `since_epoch` after the UNIX epoch.
+    pub fn new(at: P, since_epoch: Duration) -> Self {
+        WallClockAnchor { at, since_epoch }
+    }
+
+    /// Anchors the clock's instant `at`, which must have been
+    /// measured just now, to the current wall-clock time.
+    #[cfg(feature = "std")]
+    pub fn now(at: P) -> Self {
+        let since_epoch = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_else(|_| Duration::new(0, 0));
+        WallClockAnchor::new(at, since_epoch)
+    }
+
+    /// Returns the clock's instant that the anchor was measured at.
+    pub fn at(&self) -> P {
+        self.at
+    }
+
+    /// Converts an instant of the clock into wall-clock time.
+    pub(crate) fn wall_clock_at(&self, instant: P) -> Duration {
+        if instant >= self.at {
+            self.since_epoch + instant.duration_since(self.at)
+        } else {
+            self.since_epoch - cmp::min(self.at.duration_since(instant), self.since_epoch)
+        }
+    }
+
+    /// Converts wall-clock time into an instant of the clock, if it
+    /// is no earlier than the anchor.
+    pub(crate) fn instant_at(&self, since_epoch: Duration) -> Option<P> {
+        since_epoch
+            .checked_sub(self.since_epoch)
+            .map(|offset| self.at + offset)
+    }
+}
+
+/// The state of a single rate limiting history, exported so that it
+/// can be restored in another process, e.g. after a restart.
+///
+/// The state is recorded as the wall-clock time at which it is as
+/// good as new again, i.e. at which the bucket will have drained.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExportedState {
+    drained_at: Duration,
+}
+
+impl ExportedState {
+    /// Constructs an exported state that drains at the wall-clock
+    /// time `drained_at` after the UNIX epoch.
+    pub fn new(drained_at: Duration) -> Self {
+        ExportedState { drained_at }
+    }
+
+    /// Returns the wall-clock time, after the UNIX epoch, at which
+    /// the state will have drained.
+    pub fn drained_at(&self) -> Duration {
+        self.drained_at
+    }
+}
+
+/// Trait for algorithms whose rate limiting states can be exported
+/// and restored.
+///
+/// Since exported states only keep track of the time at which the
+/// bucket drains, they can be restored into an algorithm with
+/// different parameters; the restored state then takes as long to
+/// drain as the exported one would have.
+pub trait Exportable<P: clock::Reference>: Algorithm<P> {
+    /// Exports the state as of the anchor's instant. Returns `None` if
+    /// the state has drained at that instant, and need not be
+    /// restored.
+    fn export_state(
+        &self,
+        state: &Self::BucketState,
+        anchor: &WallClockAnchor<P>,
+    ) -> Option<ExportedState>;
+
+    /// Restores an exported state as of the anchor's instant.
+    fn import_state(
+        &self,
+        exported: &ExportedState,
+        anchor: &WallClockAnchor<P>,
+    ) -> Self::BucketState;
+}
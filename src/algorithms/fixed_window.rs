@@ -0,0 +1,246 @@
+#![cfg(feature = "std")]
+//! A fixed window counter aligned to wall-clock time
+
+use crate::lib::*;
+use crate::thread_safety::ThreadsafeWrapper;
+use crate::{
+    algorithms::{Algorithm, RateLimitState, StateSnapshot},
+    InconsistentCapacity, NegativeMultiDecision, NonConformance,
+};
+use evmap::ShallowCopy;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Implements a fixed window counter: Time is divided into windows
+/// of `per_time_unit` length, and each window can accommodate
+/// `capacity / cell_weight` cells. Once a window is full, no further
+/// cells are let through until the next window starts, at which point
+/// the full capacity is available again.
+///
+/// Windows are aligned to multiples of `per_time_unit` since the
+/// UNIX epoch, so a rate limiter allowing 1000 cells per minute
+/// resets at the start of every minute on the wall clock (and one
+/// allowing 1000 cells per hour resets on the hour, in UTC). This
+/// matches how quotas are often stated in contracts, but it also
+/// means that up to twice the capacity can be let through around a
+/// window boundary.
+///
+/// Since it relies on wall-clock time, this algorithm only works with
+/// [`SystemTime`](https://doc.rust-lang.org/std/time/struct.SystemTime.html)
+/// as the time reference, i.e. with the
+/// [`SystemClock`](../../clock/struct.SystemClock.html).
+///
+/// # Example
+/// ``` rust
+/// # use ratelimit_meter::{DirectRateLimiter, FixedWindow, clock::SystemClock};
+/// # use std::time::{Duration, UNIX_EPOCH};
+/// # #[macro_use] extern crate nonzero_ext;
+/// # extern crate ratelimit_meter;
+/// # fn main () {
+/// let mut lim = DirectRateLimiter::<FixedWindow, SystemClock>::new(
+///     nonzero!(2u32),
+///     Duration::from_secs(60),
+/// );
+/// let minute = UNIX_EPOCH + Duration::from_secs(60 * 1000);
+/// assert_eq!(Ok(()), lim.check_n_at(2, minute + Duration::from_secs(58)));
+/// assert!(lim.check_at(minute + Duration::from_secs(59)).is_err());
+/// // The next minute has begun:
+/// assert_eq!(Ok(()), lim.check_n_at(2, minute + Duration::from_secs(60)));
+/// # }
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FixedWindow {
+    window: Duration,
+    max_cells: u32,
+}
+
+impl FixedWindow {
+    /// Returns the start of the window that contains `at`.
+    fn window_start(&self, at: SystemTime) -> SystemTime {
+        let since_epoch = match at.duration_since(UNIX_EPOCH) {
+            Ok(since_epoch) => since_epoch,
+            // Time stamps before the epoch don't happen on systems we
+            // care about; treat them as part of the first window.
+            Err(_) => return UNIX_EPOCH,
+        };
+        let offset = since_epoch.as_nanos() % self.window.as_nanos();
+        at - Duration::from_nanos(offset as u64)
+    }
+
+    /// Decides whether `n` cells fit into the window containing `t0`,
+    /// and returns the window after accommodating them.
+    fn decide(
+        &self,
+        window: &Window,
+        n: u32,
+        t0: SystemTime,
+    ) -> Result<Window, NegativeMultiDecision<UntilNextWindow>> {
+        let start = self.window_start(t0);
+        let (start, count) = match window.start {
+            // Prevent time travel: If the wall clock was set back,
+            // keep counting in the newest window.
+            Some(current) if current >= start => (current, window.count),
+            _ => (start, 0),
+        };
+        // A window charged deep into debt may hold a count close to
+        // `u32::MAX`, where adding to it overflows:
+        match count.checked_add(n) {
+            Some(count) if count <= self.max_cells => Ok(Window {
+                start: Some(start),
+                count,
+            }),
+            _ => Err(NegativeMultiDecision::BatchNonConforming(
+                n,
+                UntilNextWindow(start + self.window),
+            )),
+        }
+    }
+}
+
+/// Represents the state of a single history of decisions.
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+pub struct State(ThreadsafeWrapper<Window>);
+
+impl RateLimitState<FixedWindow, SystemTime> for State {
+    fn last_touched(&self, params: &FixedWindow) -> Option<SystemTime> {
+        let data = self.0.snapshot();
+        Some(data.start? + params.window)
+    }
+}
+
+impl ShallowCopy for State {
+    unsafe fn shallow_copy(&mut self) -> Self {
+        State(self.0.shallow_copy())
+    }
+}
+
+/// Returned in case of a negative rate-limiting decision. Indicates
+/// the start of the next window, which is the earliest instant at
+/// which cells can be let through again.
+///
+/// Since all rate-limited clients will retry at that same instant,
+/// client code should always add a random amount of jitter to wait
+/// time estimates.
+#[derive(Debug, PartialEq)]
+pub struct UntilNextWindow(SystemTime);
+
+impl fmt::Display for UntilNextWindow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "rate-limited until {:?}", self.0)
+    }
+}
+
+impl NonConformance<SystemTime> for UntilNextWindow {
+    #[inline]
+    fn earliest_possible(&self) -> SystemTime {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+struct Window {
+    start: Option<SystemTime>,
+    count: u32,
+}
+
+impl Algorithm<SystemTime> for FixedWindow {
+    type BucketState = State;
+
+    type NegativeDecision = UntilNextWindow;
+
+    fn construct(
+        capacity: NonZeroU32,
+        cell_weight: NonZeroU32,
+        per_time_unit: Duration,
+    ) -> Result<Self, InconsistentCapacity> {
+        if capacity < cell_weight {
+            return Err(InconsistentCapacity::new(capacity, cell_weight));
+        }
+        if per_time_unit == Duration::new(0, 0) {
+            return Err(InconsistentCapacity::rate_unrepresentable(
+                capacity,
+                per_time_unit,
+            ));
+        }
+        Ok(FixedWindow {
+            window: per_time_unit,
+            max_cells: capacity.get() / cell_weight.get(),
+        })
+    }
+
+    fn test_n_and_update(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        t0: SystemTime,
+    ) -> Result<(), NegativeMultiDecision<UntilNextWindow>> {
+        if n > self.max_cells {
+            return Err(NegativeMultiDecision::InsufficientCapacity(n));
+        }
+        state
+            .0
+            .measure_and_replace(|window| match self.decide(window, n, t0) {
+                Ok(new) => (Ok(()), Some(new)),
+                Err(e) => (Err(e), None),
+            })
+    }
+
+    fn test_n(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        t0: SystemTime,
+    ) -> Result<(), NegativeMultiDecision<UntilNextWindow>> {
+        if n > self.max_cells {
+            return Err(NegativeMultiDecision::InsufficientCapacity(n));
+        }
+        state
+            .0
+            .measure(|window| self.decide(window, n, t0).map(|_| ()))
+    }
+
+    /// Charges `n` cells to the current window. Since the count starts
+    /// over in every window, any debt is forgiven once the window
+    /// ends.
+    fn charge_n(&self, state: &Self::BucketState, n: u32, debt_ceiling: u32, t0: SystemTime) {
+        let start = self.window_start(t0);
+        let ceiling = self.max_cells.saturating_add(debt_ceiling);
+        state.0.measure_and_update(|window| {
+            let (start, count) = match window.start {
+                Some(current) if current >= start => (current, window.count),
+                _ => (start, 0),
+            };
+            *window = Window {
+                start: Some(start),
+                count: cmp::max(count, cmp::min(count.saturating_add(n), ceiling)),
+            };
+        })
+    }
+
+    /// Refunds `n` cells to the current window. Cells that were let
+    /// through in earlier windows can't be refunded anymore.
+    fn refund_n(&self, state: &Self::BucketState, n: u32, t0: SystemTime) {
+        let start = self.window_start(t0);
+        state.0.measure_and_update(|window| match window.start {
+            Some(current) if current >= start => {
+                window.count -= cmp::min(n, window.count);
+            }
+            _ => {}
+        })
+    }
+
+    fn snapshot(&self, state: &Self::BucketState, t0: SystemTime) -> StateSnapshot<SystemTime> {
+        let start = self.window_start(t0);
+        state.0.measure(|window| {
+            let (start, count) = match window.start {
+                Some(current) if current >= start => (current, window.count),
+                _ => (start, 0),
+            };
+            let reset_at = if count > 0 { start + self.window } else { t0 };
+            StateSnapshot::new(
+                self.max_cells,
+                self.max_cells - cmp::min(count, self.max_cells),
+                reset_at,
+            )
+        })
+    }
+}
@@ -3,12 +3,19 @@
 use crate::lib::*;
 
 use crate::{
-    algorithms::{Algorithm, NonConformance, RateLimitState},
+    algorithms::{
+        cells_within, checked_scale, fractional_intervals, from_nanos, Algorithm, Exportable,
+        ExportedState, NonConformance, RateLimitState, StateSnapshot, WallClockAnchor,
+    },
     clock,
     thread_safety::ThreadsafeWrapper,
-    InconsistentCapacity, NegativeMultiDecision,
+    InconsistentCapacity, NegativeMultiDecision, Quota,
 };
 
+pub mod atomic;
+
+pub use self::atomic::AtomicGCRA;
+
 #[cfg(feature = "std")]
 mod std {
     use crate::clock;
@@ -21,8 +28,75 @@ mod std {
     }
 }
 
+/// A theoretical arrival time, exact to a fraction of a nanosecond:
+/// The arrival time lies `frac` of the [`GCRA`]'s fractions of a
+/// nanosecond (see its `denominator`) after `at`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct Arrival<P: clock::Reference> {
+    at: P,
+    frac: u32,
+}
+
+impl<P: clock::Reference> Arrival<P> {
+    pub(crate) fn new(at: P) -> Self {
+        Arrival { at, frac: 0 }
+    }
+
+    /// Returns the earliest instant, in whole nanoseconds, that is no
+    /// earlier than the arrival time.
+    pub(crate) fn ceil(self) -> P {
+        if self.frac > 0 {
+            self.at + Duration::from_nanos(1)
+        } else {
+            self.at
+        }
+    }
+
+    /// Returns the arrival time `amount` fractions of a nanosecond
+    /// later.
+    fn after(self, amount: u128, denominator: u32) -> Self {
+        let denominator = u128::from(denominator);
+        let total = u128::from(self.frac) + amount;
+        Arrival {
+            at: self.at + from_nanos(total / denominator),
+            frac: (total % denominator) as u32,
+        }
+    }
+
+    /// Returns the arrival time `amount` fractions of a nanosecond
+    /// earlier, saturating like
+    /// [`Reference::saturating_sub`](../../clock/trait.Reference.html#tymethod.saturating_sub).
+    fn before(self, amount: u128, denominator: u32) -> Self {
+        let denominator = u128::from(denominator);
+        let (whole, rem) = (amount / denominator, amount % denominator);
+        let frac = u128::from(self.frac);
+        if frac >= rem {
+            Arrival {
+                at: self.at.saturating_sub(from_nanos(whole)),
+                frac: (frac - rem) as u32,
+            }
+        } else {
+            Arrival {
+                at: self.at.saturating_sub(from_nanos(whole + 1)),
+                frac: (frac + denominator - rem) as u32,
+            }
+        }
+    }
+
+    /// Returns the number of fractions of a nanosecond from `earlier`
+    /// until the arrival time, or zero if `earlier` is later.
+    fn since(self, earlier: Self, denominator: u32) -> u128 {
+        if self <= earlier {
+            return 0;
+        }
+        self.at.duration_since(earlier.at).as_nanos() * u128::from(denominator)
+            + u128::from(self.frac)
+            - u128::from(earlier.frac)
+    }
+}
+
 #[derive(Debug, Eq, PartialEq, Clone)]
-struct Tat<P: clock::Reference>(Option<P>);
+struct Tat<P: clock::Reference>(Option<Arrival<P>>);
 
 impl<P: clock::Reference> Default for Tat<P> {
     fn default() -> Self {
@@ -42,8 +116,7 @@ impl<P: clock::Reference> Default for State<P> {
 
 impl<P: clock::Reference> RateLimitState<GCRA<P>, P> for State<P> {
     fn last_touched(&self, params: &GCRA<P>) -> Option<P> {
-        let data = self.0.snapshot();
-        Some(data.0? + params.tau)
+        params.last_touched_tat(self.0.snapshot().0)
     }
 }
 
@@ -68,6 +141,38 @@ impl<P: clock::Reference> NonConformance<P> for NotUntil<P> {
     }
 }
 
+/// The instants at which the cells of a batch may be sent, as
+/// returned by [`GCRA::schedule_n`](struct.GCRA.html#method.schedule_n).
+///
+/// The instants are spaced T (the minimum time between cells) apart.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule<P: clock::Reference> {
+    next: Arrival<P>,
+    interval: u128,
+    denominator: u32,
+    remaining: u32,
+}
+
+impl<P: clock::Reference> Iterator for Schedule<P> {
+    type Item = P;
+
+    fn next(&mut self) -> Option<P> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let at = self.next;
+        self.remaining -= 1;
+        self.next = at.after(self.interval, self.denominator);
+        Some(at.ceil())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining as usize, Some(self.remaining as usize))
+    }
+}
+
+impl<P: clock::Reference> ExactSizeIterator for Schedule<P> {}
+
 /// Implements the virtual scheduling description of the Generic Cell
 /// Rate Algorithm, attributed to ITU-T in recommendation I.371
 /// Traffic control and congestion control in B-ISDN; from
@@ -94,7 +199,24 @@ impl<P: clock::Reference> NonConformance<P> for NotUntil<P> {
 /// single big batch of `n` cells through. This assumption may not be
 /// correct for your application, but if you depend on GCRA's
 /// traffic-shaping properties, it's better to not use the `_n`
-/// suffixed check functions.
+/// suffixed check functions, and to use
+/// [`schedule_n`](#method.schedule_n) instead: It accepts a batch of
+/// cells and returns the instants, spaced T apart, at which each of
+/// them may be sent.
+///
+/// # Burst size
+/// By default, GCRA lets through a burst of as many cells as the
+/// capacity allows at once, τ equals the time unit. When constructed
+/// [with a separate burst
+/// size](../trait.Algorithm.html#method.construct_with_burst), T
+/// still follows the sustained rate, but τ is shortened or stretched
+/// to accommodate only that many cells at once.
+///
+/// # Lock-free state
+/// This algorithm's state is kept behind a mutex. The
+/// [`AtomicGCRA`](atomic/struct.AtomicGCRA.html) does without it, at
+/// the cost of some precision; see [lock-free
+/// variants](../../index.html#lock-free-variants).
 ///
 /// # Example
 /// In this example, we construct a rate-limiter with the GCR
@@ -127,11 +249,21 @@ impl<P: clock::Reference> NonConformance<P> for NotUntil<P> {
 /// ```
 #[derive(Debug, Clone)]
 pub struct GCRA<P: clock::Reference = <clock::DefaultClock as clock::Clock>::Instant> {
-    // The "weight" of a single packet in units of time.
-    t: Duration,
+    // The "weight" of a single packet in units of time, in fractions
+    // of a nanosecond.
+    t: u128,
+
+    // The "capacity" of the bucket, in fractions of a nanosecond.
+    tau: u128,
 
-    // The "capacity" of the bucket.
-    tau: Duration,
+    // The number of fractions that make up a nanosecond. Keeping T
+    // and τ in fractions keeps them exact, even if the time unit
+    // can't be divided evenly by the capacity (e.g. 3 cells per
+    // second); otherwise, rounding errors would add up cell by cell.
+    denominator: u32,
+
+    // The parameters the GCRA was constructed with.
+    quota: Quota,
 
     point: PhantomData<P>,
 }
@@ -145,13 +277,29 @@ impl<P: clock::Reference> Algorithm<P> for GCRA<P> {
         capacity: NonZeroU32,
         cell_weight: NonZeroU32,
         per_time_unit: Duration,
+    ) -> Result<Self, InconsistentCapacity> {
+        Self::construct_with_burst(capacity, cell_weight, per_time_unit, capacity)
+    }
+
+    fn construct_with_burst(
+        capacity: NonZeroU32,
+        cell_weight: NonZeroU32,
+        per_time_unit: Duration,
+        burst: NonZeroU32,
     ) -> Result<Self, InconsistentCapacity> {
         if capacity < cell_weight {
             return Err(InconsistentCapacity::new(capacity, cell_weight));
         }
+        if burst < cell_weight {
+            return Err(InconsistentCapacity::burst_too_small(burst, cell_weight));
+        }
+        let (t, tau, denominator) =
+            fractional_intervals(capacity, cell_weight, per_time_unit, burst)?;
         Ok(GCRA {
-            t: (per_time_unit / capacity.get()) * cell_weight.get(),
-            tau: per_time_unit,
+            t,
+            tau,
+            denominator,
+            quota: Quota::of(capacity, cell_weight, per_time_unit, burst),
             point: PhantomData,
         })
     }
@@ -163,17 +311,16 @@ impl<P: clock::Reference> Algorithm<P> for GCRA<P> {
         state: &Self::BucketState,
         t0: P,
     ) -> Result<(), Self::NegativeDecision> {
-        let tau = self.tau;
-        let t = self.t;
-        state.0.measure_and_replace(|tat| {
-            // the "theoretical arrival time" of the next cell:
-            let tat = tat.0.unwrap_or(t0);
-            if t0 < tat.saturating_sub(tau) {
-                (Err(NotUntil(tat)), None)
-            } else {
-                (Ok(()), Some(Tat(Some(cmp::max(tat, t0) + t))))
-            }
-        })
+        state
+            .0
+            .measure_and_replace(|tat| match self.test_n_tat(tat.0, 1, t0) {
+                Ok(tat) => (Ok(()), Some(Tat(Some(tat)))),
+                Err(NegativeMultiDecision::BatchNonConforming(_, nc)) => (Err(nc), None),
+                Err(other) => unreachable!(
+                    "BUG: measuring a batch of size 1 reported insufficient capacity: {:?}",
+                    other
+                ),
+            })
     }
 
     /// Tests if `n` cells can be accommodated by the rate-limiter
@@ -188,39 +335,391 @@ impl<P: clock::Reference> Algorithm<P> for GCRA<P> {
         n: u32,
         t0: P,
     ) -> Result<(), NegativeMultiDecision<Self::NegativeDecision>> {
+        state
+            .0
+            .measure_and_replace(|tat| match self.test_n_tat(tat.0, n, t0) {
+                Ok(tat) => (Ok(()), Some(Tat(Some(tat)))),
+                Err(e) => (Err(e), None),
+            })
+    }
+
+    fn test_n_and_update_with_info(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        t0: P,
+    ) -> Result<StateSnapshot<P>, NegativeMultiDecision<Self::NegativeDecision>> {
+        state.0.measure_and_update(|tat| {
+            let updated = self.test_n_tat(tat.0, n, t0)?;
+            tat.0 = Some(updated);
+            Ok(self.snapshot_tat(tat.0, t0))
+        })
+    }
+
+    /// Reserves the cells at the exact instant at which they conform,
+    /// which can be earlier than the one reported by negative
+    /// decisions.
+    fn reserve_n(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        t0: P,
+    ) -> Result<P, NegativeMultiDecision<Self::NegativeDecision>> {
+        state.0.measure_and_update(|tat| {
+            let (start, updated) = self.reserve_n_tat(tat.0, n, t0)?;
+            tat.0 = Some(updated);
+            Ok(start)
+        })
+    }
+
+    fn test_up_to_n_and_update(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        t0: P,
+    ) -> (u32, Option<Self::NegativeDecision>) {
+        state.0.measure_and_update(|tat| {
+            let (accepted, updated, nc) = self.test_up_to_n_tat(tat.0, n, t0);
+            tat.0 = updated;
+            (accepted, nc)
+        })
+    }
+
+    fn test_n(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        t0: P,
+    ) -> Result<(), NegativeMultiDecision<Self::NegativeDecision>> {
+        state
+            .0
+            .measure(|tat| self.test_n_tat(tat.0, n, t0).map(|_| ()))
+    }
+
+    fn charge_n(&self, state: &Self::BucketState, n: u32, debt_ceiling: u32, t0: P) {
+        state
+            .0
+            .measure_and_update(|tat| tat.0 = Some(self.charge_n_tat(tat.0, n, debt_ceiling, t0)))
+    }
+
+    fn refund_n(&self, state: &Self::BucketState, n: u32, t0: P) {
+        state
+            .0
+            .measure_and_update(|tat| tat.0 = self.refund_n_tat(tat.0, n, t0))
+    }
+
+    /// Reports the number of cells that fit into the bucket as its
+    /// limit. Since GCRA lets a single cell through as long as the
+    /// bucket is not over capacity, one more cell than `remaining`
+    /// may be let through one at a time.
+    fn snapshot(&self, state: &Self::BucketState, t0: P) -> StateSnapshot<P> {
+        state.0.measure(|tat| self.snapshot_tat(tat.0, t0))
+    }
+
+    fn rescale(&self, state: &Self::BucketState, previous: &Self, t0: P) {
+        state
+            .0
+            .measure_and_update(|tat| tat.0 = self.rescale_tat(tat.0, previous, t0))
+    }
+
+    fn rescaled(
+        &self,
+        state: &Self::BucketState,
+        previous: &Self,
+        t0: P,
+    ) -> Option<Self::BucketState> {
+        let tat = self.rescale_tat(state.0.snapshot().0, previous, t0);
+        let rescaled = State::default();
+        rescaled.0.measure_and_update(|data| data.0 = tat);
+        Some(rescaled)
+    }
+}
+
+impl<P: clock::Reference> Exportable<P> for GCRA<P> {
+    fn export_state(
+        &self,
+        state: &Self::BucketState,
+        anchor: &WallClockAnchor<P>,
+    ) -> Option<ExportedState> {
+        let tat = state.0.snapshot().0?.ceil();
+        if tat <= anchor.at() {
+            return None;
+        }
+        Some(ExportedState::new(anchor.wall_clock_at(tat)))
+    }
+
+    fn import_state(
+        &self,
+        exported: &ExportedState,
+        anchor: &WallClockAnchor<P>,
+    ) -> Self::BucketState {
+        let state = State::default();
+        let tat = anchor
+            .instant_at(exported.drained_at())
+            .filter(|tat| *tat > anchor.at());
+        state
+            .0
+            .measure_and_update(|data| data.0 = tat.map(Arrival::new));
+        state
+    }
+}
+
+impl<P: clock::Reference> GCRA<P> {
+    /// Returns the parameters that the GCRA was constructed with.
+    pub fn quota(&self) -> Quota {
+        self.quota
+    }
+
+    /// Schedules a batch of `n` cells for sending, starting no
+    /// earlier than `t0`, and updates the rate limiter state to
+    /// account for all of them.
+    ///
+    /// Unlike [`test_n_and_update`](#method.test_n_and_update), this
+    /// keeps GCRA's traffic-shaping guarantee: The returned schedule
+    /// holds one instant per cell, each spaced T apart, and each cell
+    /// conforms to the rate limit at its instant. The first cell is
+    /// scheduled for the earliest instant at which a single cell would
+    /// be let through.
+    ///
+    /// Scheduling never fails, but a large batch commits the rate
+    /// limiter far into the future: Cells checked after it are only
+    /// let through once the schedule has (nearly) run its course.
+    ///
+    /// # Example
+    /// ``` rust
+    /// # use ratelimit_meter::{algorithms::Algorithm, GCRA};
+    /// # use std::time::{Duration, Instant};
+    /// # #[macro_use] extern crate nonzero_ext;
+    /// # extern crate ratelimit_meter;
+    /// # #[cfg(feature = "std")]
+    /// # fn main () {
+    /// let gcra = GCRA::construct(nonzero!(10u32), nonzero!(1u32), Duration::from_secs(1)).unwrap();
+    /// let state = <GCRA as Algorithm>::BucketState::default();
+    /// let now = Instant::now();
+    /// let ms = Duration::from_millis(1);
+    /// let schedule: Vec<Instant> = gcra.schedule_n(&state, 3, now).collect();
+    /// assert_eq!(vec![now, now + ms * 100, now + ms * 200], schedule);
+    /// # }
+    /// # #[cfg(not(feature = "std"))] fn main() {}
+    /// ```
+    pub fn schedule_n(&self, state: &State<P>, n: u32, t0: P) -> Schedule<P> {
+        let now = Arrival::new(t0);
+        state.0.measure_and_update(|tat| {
+            let current = tat.0.unwrap_or(now);
+            let start = cmp::max(now, current.before(self.tau, self.denominator));
+            if n > 0 {
+                tat.0 =
+                    Some(cmp::max(current, start).after(self.t * u128::from(n), self.denominator));
+            }
+            Schedule {
+                next: start,
+                interval: self.t,
+                denominator: self.denominator,
+                remaining: n,
+            }
+        })
+    }
+
+    /// Returns the number of times that T fits into `span` (in
+    /// fractions of a nanosecond), saturating at `u32::MAX`.
+    fn cells_within(&self, span: u128) -> u32 {
+        cells_within(span, self.t)
+    }
+
+    /// Tests if `n` cells can be accommodated at `t0`, given the
+    /// theoretical arrival time `tat` of the next cell, and returns
+    /// the theoretical arrival time after accommodating them.
+    pub(crate) fn test_n_tat(
+        &self,
+        tat: Option<Arrival<P>>,
+        n: u32,
+        t0: P,
+    ) -> Result<Arrival<P>, NegativeMultiDecision<NotUntil<P>>> {
         let tau = self.tau;
         let t = self.t;
-        state.0.measure_and_replace(|tat| {
-            let tat = tat.0.unwrap_or(t0);
-            let tat = match n {
-                0 => t0,
-                1 => tat,
-                _ => {
-                    let weight = t * (n - 1);
-                    if (weight + t) > tau {
-                        // The bucket capacity can never accommodate this request
-                        return (Err(NegativeMultiDecision::InsufficientCapacity(n)), None);
-                    }
-                    tat + weight
+        let now = Arrival::new(t0);
+        let tat = tat.unwrap_or(now);
+        let tat = match n {
+            0 => now,
+            1 => tat,
+            _ => {
+                let weight = t * u128::from(n - 1);
+                if (weight + t) > tau {
+                    // The bucket capacity can never accommodate this request
+                    return Err(NegativeMultiDecision::InsufficientCapacity(n));
                 }
-            };
-
-            let additional_weight = match n {
-                0 => Duration::new(0, 0),
-                1 => t,
-                _ => t * n,
-            };
-            if t0 < tat.saturating_sub(tau) {
-                (
-                    Err(NegativeMultiDecision::BatchNonConforming(n, NotUntil(tat))),
-                    None,
-                )
-            } else {
-                (
-                    Ok(()),
-                    Some(Tat(Some(cmp::max(tat, t0) + additional_weight))),
-                )
+                tat.after(weight, self.denominator)
             }
-        })
+        };
+
+        let additional_weight = match n {
+            0 => 0,
+            1 => t,
+            _ => t * u128::from(n),
+        };
+        if now.after(tau, self.denominator) < tat {
+            Err(NegativeMultiDecision::BatchNonConforming(
+                n,
+                NotUntil(tat.ceil()),
+            ))
+        } else {
+            Ok(cmp::max(tat, now).after(additional_weight, self.denominator))
+        }
+    }
+
+    /// Finds the largest number of cells, up to `n`, that conform at
+    /// `t0`, given the theoretical arrival time `tat` of the next
+    /// cell. Returns that number, the theoretical arrival time after
+    /// accommodating the cells, and the negative decision for the
+    /// first cell that did not conform, if any.
+    pub(crate) fn test_up_to_n_tat(
+        &self,
+        tat: Option<Arrival<P>>,
+        n: u32,
+        t0: P,
+    ) -> (u32, Option<Arrival<P>>, Option<NotUntil<P>>) {
+        let (mut accepted, mut tat) = (0, tat);
+        let now = Arrival::new(t0);
+        let current = tat.unwrap_or(now);
+        let reach = now.after(self.tau, self.denominator);
+        if n > 1 && current <= reach {
+            // A batch conforms if it fits into tau, and if its last
+            // cell's theoretical arrival time is within tau of t0:
+            let slack = reach.since(current, self.denominator);
+            let fitting = cmp::min(
+                self.cells_within(slack).saturating_add(1),
+                self.cells_within(self.tau),
+            );
+            let batch = cmp::min(n, cmp::max(fitting, 1));
+            if let Ok(updated) = self.test_n_tat(tat, batch, t0) {
+                accepted = batch;
+                tat = Some(updated);
+            }
+        }
+        // Single cells can still conform after a batch that filled
+        // the bucket:
+        while accepted < n {
+            match self.test_n_tat(tat, 1, t0) {
+                Ok(updated) => {
+                    accepted += 1;
+                    tat = Some(updated);
+                }
+                Err(NegativeMultiDecision::BatchNonConforming(_, nc)) => {
+                    return (accepted, tat, Some(nc))
+                }
+                Err(other) => unreachable!(
+                    "BUG: measuring a batch of size 1 reported insufficient capacity: {:?}",
+                    other
+                ),
+            }
+        }
+        (accepted, tat, None)
+    }
+
+    /// Finds the earliest instant no earlier than `t0` at which `n`
+    /// cells conform, given the theoretical arrival time `tat` of the
+    /// next cell, and returns it along with the theoretical arrival
+    /// time after accommodating the cells there.
+    pub(crate) fn reserve_n_tat(
+        &self,
+        tat: Option<Arrival<P>>,
+        n: u32,
+        t0: P,
+    ) -> Result<(P, Arrival<P>), NegativeMultiDecision<NotUntil<P>>> {
+        match self.test_n_tat(tat, n, t0) {
+            Ok(updated) => Ok((t0, updated)),
+            Err(NegativeMultiDecision::BatchNonConforming(..)) => {
+                // The batch conforms once its last cell's theoretical
+                // arrival time is within tau:
+                let last = tat
+                    .unwrap_or_else(|| Arrival::new(t0))
+                    .after(self.t * u128::from(n.saturating_sub(1)), self.denominator);
+                let start = cmp::max(t0, last.before(self.tau, self.denominator).ceil());
+                Ok((start, self.test_n_tat(tat, n, start)?))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Charges `n` cells at `t0`, given the theoretical arrival time
+    /// `tat` of the next cell, and returns the theoretical arrival
+    /// time after charging them. The result is never later than
+    /// `debt_ceiling` cells past a full bucket, and never earlier than
+    /// `tat`.
+    pub(crate) fn charge_n_tat(
+        &self,
+        tat: Option<Arrival<P>>,
+        n: u32,
+        debt_ceiling: u32,
+        t0: P,
+    ) -> Arrival<P> {
+        let now = Arrival::new(t0);
+        let tat = tat.unwrap_or(now);
+        let ceiling = now.after(
+            self.tau + self.t * u128::from(debt_ceiling),
+            self.denominator,
+        );
+        let charged = cmp::max(tat, now).after(self.t * u128::from(n), self.denominator);
+        cmp::max(tat, cmp::min(charged, ceiling))
+    }
+
+    /// Refunds `n` cells at `t0`, given the theoretical arrival time
+    /// `tat` of the next cell, and returns the theoretical arrival
+    /// time after refunding them. The result is never earlier than
+    /// `t0` (an empty bucket), and never later than `tat`.
+    pub(crate) fn refund_n_tat(
+        &self,
+        tat: Option<Arrival<P>>,
+        n: u32,
+        t0: P,
+    ) -> Option<Arrival<P>> {
+        let tat = tat?;
+        let refunded = tat.before(self.t * u128::from(n), self.denominator);
+        Some(cmp::min(tat, cmp::max(refunded, Arrival::new(t0))))
+    }
+
+    /// Converts the theoretical arrival time `tat`, as accumulated
+    /// under the parameters of `previous`, so that the cells it
+    /// accounts for at `t0` take up the same number of cells' worth
+    /// of time under these parameters.
+    pub(crate) fn rescale_tat(
+        &self,
+        tat: Option<Arrival<P>>,
+        previous: &Self,
+        t0: P,
+    ) -> Option<Arrival<P>> {
+        let tat = tat?;
+        let now = Arrival::new(t0);
+        if tat <= now {
+            // The fraction of a nanosecond is counted in the previous
+            // parameters' unit, and doesn't matter this far back:
+            return Some(Arrival::new(tat.at));
+        }
+        // Keep the same number of cells in the bucket, even if that's
+        // more than its capacity (e.g. after the capacity was cut).
+        // Only where converting them would overflow, fall back to a
+        // bucket filled up to the capacity:
+        let used = tat.since(now, previous.denominator);
+        let rescaled = checked_scale(used, self.t, previous.t).unwrap_or(self.tau);
+        Some(now.after(rescaled, self.denominator))
+    }
+
+    /// Returns a snapshot of the capacity left at `t0`, given the
+    /// theoretical arrival time `tat` of the next cell.
+    pub(crate) fn snapshot_tat(&self, tat: Option<Arrival<P>>, t0: P) -> StateSnapshot<P> {
+        let now = Arrival::new(t0);
+        let tat = tat.map_or(now, |tat| cmp::max(tat, now));
+        let used = tat.since(now, self.denominator);
+        StateSnapshot::new(
+            self.cells_within(self.tau),
+            self.cells_within(self.tau - cmp::min(used, self.tau)),
+            tat.ceil(),
+        )
+    }
+
+    /// Returns the last instant at which the theoretical arrival time
+    /// `tat` has any effect on decisions.
+    pub(crate) fn last_touched_tat(&self, tat: Option<Arrival<P>>) -> Option<P> {
+        Some(tat?.after(self.tau, self.denominator).ceil())
     }
 }
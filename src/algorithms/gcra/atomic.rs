@@ -0,0 +1,359 @@
+//! A lock-free variant of the Generic Cell Rate Algorithm
+
+use crate::lib::*;
+
+use crate::{
+    algorithms::{
+        gcra::{Arrival, NotUntil},
+        Algorithm, RateLimitState, StateSnapshot, GCRA,
+    },
+    clock,
+    thread_safety::SetOnce,
+    InconsistentCapacity, NegativeMultiDecision,
+};
+
+/// The shortest span of time after its first decision that a state
+/// must be able to keep track of. Rates that need to split a
+/// nanosecond into so many fractions that a state would cover less
+/// can't be constructed.
+const MIN_SPAN: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+#[cfg(feature = "std")]
+mod std {
+    use crate::clock;
+    use evmap::ShallowCopy;
+
+    impl<P: clock::Reference> ShallowCopy for super::State<P> {
+        unsafe fn shallow_copy(&mut self) -> Self {
+            super::State(self.0.shallow_copy())
+        }
+    }
+}
+
+/// Marks a theoretical arrival time that lies too far after the
+/// origin to be stored; see [`decode`].
+const BEYOND: u64 = u64::MAX;
+
+/// The theoretical arrival time, stored as fractions of a nanosecond
+/// (see the [`GCRA`]'s `denominator`) after an origin that is set on
+/// the first decision. Zero means that there is no theoretical
+/// arrival time yet; all other values but [`BEYOND`] are offset by
+/// one.
+#[derive(Debug)]
+struct Tat<P: clock::Reference> {
+    origin: SetOnce<P>,
+    nanos: AtomicU64,
+}
+
+impl<P: clock::Reference> Default for Tat<P> {
+    fn default() -> Self {
+        Tat {
+            origin: Default::default(),
+            nanos: AtomicU64::new(0),
+        }
+    }
+}
+
+impl<P: clock::Reference> Tat<P> {
+    fn snapshot(&self, denominator: u32, t0: P) -> Option<Arrival<P>> {
+        let origin = *self.origin.get()?;
+        decode(origin, self.nanos.load(Ordering::Acquire), denominator, t0)
+    }
+
+    /// Returns an independent copy of the theoretical arrival time.
+    fn copy(&self) -> Self {
+        // The origin is set before the first arrival time is stored,
+        // so load that first:
+        let nanos = self.nanos.load(Ordering::Acquire);
+        let copy = Tat::default();
+        if let Some(origin) = self.origin.get() {
+            copy.origin.get_or_init(|| *origin);
+        }
+        copy.nanos.store(nanos, Ordering::Relaxed);
+        copy
+    }
+}
+
+/// Encodes an arrival time as fractions of a nanosecond after
+/// `origin`, or as [`BEYOND`] where they don't fit. Arrival times
+/// before the origin are rounded up to the origin.
+fn encode<P: clock::Reference>(origin: P, tat: Arrival<P>, denominator: u32) -> u64 {
+    let fractions = if tat.at < origin {
+        0
+    } else {
+        tat.at.duration_since(origin).as_nanos() * u128::from(denominator) + u128::from(tat.frac)
+    };
+    if fractions >= u128::from(BEYOND - 1) {
+        return BEYOND;
+    }
+    fractions as u64 + 1
+}
+
+/// Decodes an arrival time encoded with [`encode`], for a decision
+/// at `t0`.
+///
+/// Arrival times that didn't fit are taken to lie `MIN_SPAN` after
+/// `t0`: Once a state has been in use for longer than it can keep
+/// track of, it turns away all cells instead of letting them through.
+fn decode<P: clock::Reference>(
+    origin: P,
+    encoded: u64,
+    denominator: u32,
+    t0: P,
+) -> Option<Arrival<P>> {
+    if encoded == BEYOND {
+        return Some(Arrival {
+            at: t0 + MIN_SPAN,
+            frac: 0,
+        });
+    }
+    let fractions = encoded.checked_sub(1)?;
+    let denominator = u64::from(denominator);
+    Some(Arrival {
+        at: origin + Duration::from_nanos(fractions / denominator),
+        frac: (fractions % denominator) as u32,
+    })
+}
+
+/// The lock-free GCRA's state about a single rate limiting history.
+#[derive(Debug, Clone)]
+pub struct State<P: clock::Reference>(Arc<Tat<P>>);
+
+impl<P: clock::Reference> Default for State<P> {
+    fn default() -> Self {
+        State(Default::default())
+    }
+}
+
+impl<P: clock::Reference> PartialEq for State<P> {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+            || (self.0.origin.get() == other.0.origin.get()
+                && self.0.nanos.load(Ordering::Acquire) == other.0.nanos.load(Ordering::Acquire))
+    }
+}
+
+impl<P: clock::Reference> Eq for State<P> {}
+
+impl<P: clock::Reference> RateLimitState<AtomicGCRA<P>, P> for State<P> {
+    fn last_touched(&self, params: &AtomicGCRA<P>) -> Option<P> {
+        let origin = *self.0.origin.get()?;
+        match self.0.nanos.load(Ordering::Acquire) {
+            // A state that turns away all cells never stops having an
+            // effect on decisions:
+            BEYOND => None,
+            nanos => params
+                .0
+                .last_touched_tat(decode(origin, nanos, params.0.denominator, origin)),
+        }
+    }
+}
+
+/// A variant of the [`GCRA`](../struct.GCRA.html) that keeps its
+/// state in an atomic integer instead of behind a mutex.
+///
+/// Decisions update the theoretical arrival time in a
+/// compare-and-swap loop, so threads checking cells against the same
+/// state never block each other. This helps when many threads share a
+/// rate limiter, as in a [`DirectRateLimiter`](../../../state/direct/struct.DirectRateLimiter.html)
+/// that is cloned across threads. The theoretical arrival time is
+/// kept exactly, so it makes the same decisions as the `GCRA`; see
+/// [lock-free variants](../../../index.html#lock-free-variants) for
+/// which rates it can't be constructed for.
+///
+/// # Example
+/// ``` rust
+/// # use ratelimit_meter::{algorithms::gcra::AtomicGCRA, DirectRateLimiter};
+/// # #[macro_use] extern crate nonzero_ext;
+/// # extern crate ratelimit_meter;
+/// # #[cfg(feature = "std")]
+/// # fn main () {
+/// let mut lim = DirectRateLimiter::<AtomicGCRA>::per_second(nonzero!(20u32));
+/// assert_eq!(Ok(()), lim.check());
+/// # }
+/// # #[cfg(not(feature = "std"))] fn main() {}
+/// ```
+#[derive(Debug, Clone)]
+pub struct AtomicGCRA<P: clock::Reference = <clock::DefaultClock as clock::Clock>::Instant>(
+    GCRA<P>,
+);
+
+impl<P: clock::Reference> AtomicGCRA<P> {
+    /// Calls `f` with the theoretical arrival time and swaps in the
+    /// one it returns, calling `f` again with the latest one if
+    /// another thread changed it in the meantime. If `f` returns an
+    /// error, the state is left alone.
+    fn update<F, R, E>(&self, state: &State<P>, t0: P, f: F) -> Result<R, E>
+    where
+        F: FnMut(Option<Arrival<P>>) -> Result<(Arrival<P>, R), E>,
+    {
+        self.update_from(self.0.denominator, state, t0, f)
+    }
+
+    /// Like `update`, but reads theoretical arrival times that were
+    /// stored in the given fractions of a nanosecond, e.g. by an
+    /// `AtomicGCRA` with different parameters.
+    fn update_from<F, R, E>(
+        &self,
+        previous_denominator: u32,
+        state: &State<P>,
+        t0: P,
+        mut f: F,
+    ) -> Result<R, E>
+    where
+        F: FnMut(Option<Arrival<P>>) -> Result<(Arrival<P>, R), E>,
+    {
+        let data = &state.0;
+        let denominator = self.0.denominator;
+        let mut nanos = data.nanos.load(Ordering::Acquire);
+        loop {
+            let tat = data
+                .origin
+                .get()
+                .and_then(|origin| decode(*origin, nanos, previous_denominator, t0));
+            let (tat, result) = f(tat)?;
+            let origin = *data.origin.get_or_init(|| t0);
+            match data.nanos.compare_exchange_weak(
+                nanos,
+                encode(origin, tat, denominator),
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return Ok(result),
+                Err(actual) => nanos = actual,
+            }
+        }
+    }
+}
+
+impl<P: clock::Reference> Algorithm<P> for AtomicGCRA<P> {
+    type BucketState = State<P>;
+
+    type NegativeDecision = NotUntil<P>;
+
+    fn construct(
+        capacity: NonZeroU32,
+        cell_weight: NonZeroU32,
+        per_time_unit: Duration,
+    ) -> Result<Self, InconsistentCapacity> {
+        Self::construct_with_burst(capacity, cell_weight, per_time_unit, capacity)
+    }
+
+    fn construct_with_burst(
+        capacity: NonZeroU32,
+        cell_weight: NonZeroU32,
+        per_time_unit: Duration,
+        burst: NonZeroU32,
+    ) -> Result<Self, InconsistentCapacity> {
+        let gcra = GCRA::construct_with_burst(capacity, cell_weight, per_time_unit, burst)?;
+        if u128::from(u64::MAX - 1) / u128::from(gcra.denominator) < MIN_SPAN.as_nanos() {
+            return Err(InconsistentCapacity::rate_unrepresentable(
+                capacity,
+                per_time_unit,
+            ));
+        }
+        Ok(AtomicGCRA(gcra))
+    }
+
+    fn test_n_and_update(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        t0: P,
+    ) -> Result<(), NegativeMultiDecision<NotUntil<P>>> {
+        self.update(state, t0, |tat| Ok((self.0.test_n_tat(tat, n, t0)?, ())))
+    }
+
+    fn test_n_and_update_with_info(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        t0: P,
+    ) -> Result<StateSnapshot<P>, NegativeMultiDecision<NotUntil<P>>> {
+        let tat = self.update(state, t0, |tat| {
+            let tat = self.0.test_n_tat(tat, n, t0)?;
+            Ok((tat, tat))
+        })?;
+        Ok(self.0.snapshot_tat(Some(tat), t0))
+    }
+
+    /// Reserves the cells at the exact instant at which they conform,
+    /// like the [`GCRA`](../struct.GCRA.html) does.
+    fn reserve_n(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        t0: P,
+    ) -> Result<P, NegativeMultiDecision<NotUntil<P>>> {
+        self.update(state, t0, |tat| {
+            let (start, tat) = self.0.reserve_n_tat(tat, n, t0)?;
+            Ok((tat, start))
+        })
+    }
+
+    fn test_up_to_n_and_update(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        t0: P,
+    ) -> (u32, Option<NotUntil<P>>) {
+        self.update(state, t0, |tat| match self.0.test_up_to_n_tat(tat, n, t0) {
+            (accepted, Some(tat), nc) if accepted > 0 => Ok((tat, (accepted, nc))),
+            (accepted, _, nc) => Err((accepted, nc)),
+        })
+        .unwrap_or_else(|unchanged| unchanged)
+    }
+
+    fn test_n(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        t0: P,
+    ) -> Result<(), NegativeMultiDecision<NotUntil<P>>> {
+        self.0
+            .test_n_tat(state.0.snapshot(self.0.denominator, t0), n, t0)
+            .map(|_| ())
+    }
+
+    fn charge_n(&self, state: &Self::BucketState, n: u32, debt_ceiling: u32, t0: P) {
+        let _: Result<(), ()> = self.update(state, t0, |tat| {
+            Ok((self.0.charge_n_tat(tat, n, debt_ceiling, t0), ()))
+        });
+    }
+
+    fn snapshot(&self, state: &Self::BucketState, t0: P) -> StateSnapshot<P> {
+        self.0
+            .snapshot_tat(state.0.snapshot(self.0.denominator, t0), t0)
+    }
+
+    fn refund_n(&self, state: &Self::BucketState, n: u32, t0: P) {
+        // Nothing was ever accepted if there's no theoretical arrival
+        // time, so there's nothing to refund.
+        let _ = self.update(state, t0, |tat| {
+            self.0
+                .refund_n_tat(tat, n, t0)
+                .map(|tat| (tat, ()))
+                .ok_or(())
+        });
+    }
+
+    fn rescale(&self, state: &Self::BucketState, previous: &Self, t0: P) {
+        let _ = self.update_from(previous.0.denominator, state, t0, |tat| {
+            self.0
+                .rescale_tat(tat, &previous.0, t0)
+                .map(|tat| (tat, ()))
+                .ok_or(())
+        });
+    }
+
+    fn rescaled(
+        &self,
+        state: &Self::BucketState,
+        previous: &Self,
+        t0: P,
+    ) -> Option<Self::BucketState> {
+        let rescaled = State(Arc::new(state.0.copy()));
+        self.rescale(&rescaled, previous, t0);
+        Some(rescaled)
+    }
+}
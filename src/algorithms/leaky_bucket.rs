@@ -3,10 +3,17 @@
 use crate::lib::*;
 use crate::thread_safety::ThreadsafeWrapper;
 use crate::{
-    algorithms::{Algorithm, RateLimitState},
-    clock, InconsistentCapacity, NegativeMultiDecision, NonConformance,
+    algorithms::{
+        cells_within, checked_scale, fractional_intervals, from_nanos, Algorithm, Exportable,
+        ExportedState, RateLimitState, StateSnapshot, WallClockAnchor,
+    },
+    clock, InconsistentCapacity, NegativeMultiDecision, NonConformance, Quota,
 };
 
+pub mod atomic;
+
+pub use self::atomic::AtomicLeakyBucket;
+
 /// Implements the industry-standard leaky bucket rate-limiting
 /// as-a-meter. The bucket keeps a "fill height", pretending to drip
 /// steadily (which reduces the fill height), and increases the fill
@@ -29,6 +36,21 @@ use crate::{
 /// library must take care to apply positive jitter to these wait
 /// times.
 ///
+/// # Burst size
+///
+/// By default, the bucket holds exactly as many cells as it lets
+/// through per time unit. When constructed [with a separate burst
+/// size](../trait.Algorithm.html#method.construct_with_burst), the
+/// bucket drips at the same rate, but can hold only `burst` units of
+/// weight.
+///
+/// # Lock-free state
+///
+/// This algorithm's state is kept behind a mutex. For the
+/// [`AtomicLeakyBucket`](atomic/struct.AtomicLeakyBucket.html), which
+/// does without it, see [lock-free
+/// variants](../../index.html#lock-free-variants).
+///
 /// # Example
 /// ``` rust
 /// # use ratelimit_meter::{DirectRateLimiter, LeakyBucket};
@@ -43,8 +65,19 @@ use crate::{
 /// ```
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct LeakyBucket<P: clock::Reference = <clock::DefaultClock as clock::Clock>::Instant> {
-    full: Duration,
-    token_interval: Duration,
+    // The bucket's size and the weight of a single cell, both in
+    // fractions of a nanosecond (see `denominator`):
+    full: u128,
+    token_interval: u128,
+
+    // The number of fractions that make up a nanosecond. Like in the
+    // GCRA, this keeps the bucket's drip rate exact even if the time
+    // unit can't be divided evenly by the capacity.
+    denominator: u32,
+
+    // The parameters the bucket was constructed with.
+    quota: Quota,
+
     point: PhantomData<P>,
 }
 
@@ -59,9 +92,9 @@ impl<P: clock::Reference> Default for State<P> {
 }
 
 impl<P: clock::Reference> RateLimitState<LeakyBucket<P>, P> for State<P> {
-    fn last_touched(&self, _params: &LeakyBucket<P>) -> Option<P> {
+    fn last_touched(&self, params: &LeakyBucket<P>) -> Option<P> {
         let data = self.0.snapshot();
-        Some(data.last_update? + data.level)
+        Some(data.last_update? + params.duration(data.level))
     }
 }
 
@@ -99,14 +132,15 @@ impl<P: clock::Reference> NonConformance<P> for TooEarly<P> {
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 struct BucketState<P: clock::Reference> {
-    level: Duration,
+    // The bucket's fill level, in fractions of a nanosecond:
+    level: u128,
     last_update: Option<P>,
 }
 
 impl<P: clock::Reference> Default for BucketState<P> {
     fn default() -> Self {
         BucketState {
-            level: Duration::new(0, 0),
+            level: 0,
             last_update: None,
         }
     }
@@ -121,14 +155,29 @@ impl<P: clock::Reference> Algorithm<P> for LeakyBucket<P> {
         capacity: NonZeroU32,
         cell_weight: NonZeroU32,
         per_time_unit: Duration,
+    ) -> Result<Self, InconsistentCapacity> {
+        Self::construct_with_burst(capacity, cell_weight, per_time_unit, capacity)
+    }
+
+    fn construct_with_burst(
+        capacity: NonZeroU32,
+        cell_weight: NonZeroU32,
+        per_time_unit: Duration,
+        burst: NonZeroU32,
     ) -> Result<Self, InconsistentCapacity> {
         if capacity < cell_weight {
             return Err(InconsistentCapacity::new(capacity, cell_weight));
         }
-        let token_interval = (per_time_unit * cell_weight.get()) / capacity.get();
+        if burst < cell_weight {
+            return Err(InconsistentCapacity::burst_too_small(burst, cell_weight));
+        }
+        let (token_interval, full, denominator) =
+            fractional_intervals(capacity, cell_weight, per_time_unit, burst)?;
         Ok(LeakyBucket {
-            full: per_time_unit,
             token_interval,
+            full,
+            denominator,
+            quota: Quota::of(capacity, cell_weight, per_time_unit, burst),
             point: PhantomData,
         })
     }
@@ -139,37 +188,262 @@ impl<P: clock::Reference> Algorithm<P> for LeakyBucket<P> {
         n: u32,
         t0: P,
     ) -> Result<(), NegativeMultiDecision<TooEarly<P>>> {
-        let full = self.full;
-        let weight = self.token_interval * n;
-        if weight > self.full {
+        if self.token_interval * u128::from(n) > self.full {
             return Err(NegativeMultiDecision::InsufficientCapacity(n));
         }
-        state.0.measure_and_replace(|state| {
-            let mut new = BucketState {
-                last_update: Some(t0),
-                level: Duration::new(0, 0),
-            };
+        state
+            .0
+            .measure_and_replace(|state| match self.decide(state, n, t0) {
+                Ok(new) => (Ok(()), Some(new)),
+                Err(e) => (Err(e), None),
+            })
+    }
+
+    fn test_n_and_update_with_info(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        t0: P,
+    ) -> Result<StateSnapshot<P>, NegativeMultiDecision<TooEarly<P>>> {
+        if self.token_interval * u128::from(n) > self.full {
+            return Err(NegativeMultiDecision::InsufficientCapacity(n));
+        }
+        state.0.measure_and_update(|state| {
+            *state = self.decide(state, n, t0)?;
+            Ok(self.snapshot_level(state.level, t0))
+        })
+    }
+
+    fn test_up_to_n_and_update(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        t0: P,
+    ) -> (u32, Option<TooEarly<P>>) {
+        state.0.measure_and_update(|state| {
             let last = state.last_update.unwrap_or(t0);
-            // Prevent time travel: If any parallel calls get re-ordered,
-            // or any tests attempt silly things, make sure to answer from
-            // the last query onwards instead.
-            let t0 = cmp::max(t0, last);
-            // Decrement the level by the amount the bucket
-            // has dripped in the meantime:
-            new.level = state.level - cmp::min(t0.duration_since(last), state.level);
-            if weight + new.level <= full {
-                new.level += weight;
-                (Ok(()), Some(new))
+            let t0c = cmp::max(t0, last);
+            let level = self.drain(state.level, t0c.duration_since(last));
+            let accepted = cmp::min(n, self.fitting_cells(level));
+            let level = level + self.token_interval * u128::from(accepted);
+            if accepted > 0 {
+                *state = BucketState {
+                    last_update: Some(t0),
+                    level,
+                };
+            }
+            if accepted < n {
+                let wait_period = self.duration((level + self.token_interval) - self.full);
+                (accepted, Some(TooEarly(t0c, wait_period)))
             } else {
-                let wait_period = (weight + new.level) - full;
-                (
-                    Err(NegativeMultiDecision::BatchNonConforming(
-                        n,
-                        TooEarly(t0, wait_period),
-                    )),
-                    None,
-                )
+                (accepted, None)
             }
         })
     }
+
+    fn test_n(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        t0: P,
+    ) -> Result<(), NegativeMultiDecision<TooEarly<P>>> {
+        if self.token_interval * u128::from(n) > self.full {
+            return Err(NegativeMultiDecision::InsufficientCapacity(n));
+        }
+        state
+            .0
+            .measure(|state| self.decide(state, n, t0).map(|_| ()))
+    }
+
+    fn charge_n(&self, state: &Self::BucketState, n: u32, debt_ceiling: u32, t0: P) {
+        state.0.measure_and_update(|state| {
+            let last = state.last_update.unwrap_or(t0);
+            let t0c = cmp::max(t0, last);
+            let level = self.drain(state.level, t0c.duration_since(last));
+            state.level = self.charge_level(level, n, debt_ceiling);
+            state.last_update = Some(t0);
+        })
+    }
+
+    fn refund_n(&self, state: &Self::BucketState, n: u32, t0: P) {
+        state.0.measure_and_update(|state| {
+            let last = match state.last_update {
+                Some(last) => last,
+                None => return,
+            };
+            let t0c = cmp::max(t0, last);
+            let level = self.drain(state.level, t0c.duration_since(last));
+            state.level = level - cmp::min(self.token_interval * u128::from(n), level);
+            state.last_update = Some(t0);
+        })
+    }
+
+    fn snapshot(&self, state: &Self::BucketState, t0: P) -> StateSnapshot<P> {
+        state.0.measure(|state| {
+            let last = state.last_update.unwrap_or(t0);
+            let t0 = cmp::max(t0, last);
+            let level = self.drain(state.level, t0.duration_since(last));
+            self.snapshot_level(level, t0)
+        })
+    }
+
+    fn rescale(&self, state: &Self::BucketState, previous: &Self, t0: P) {
+        state
+            .0
+            .measure_and_update(|state| self.rescale_state(state, previous, t0))
+    }
+
+    fn rescaled(
+        &self,
+        state: &Self::BucketState,
+        previous: &Self,
+        t0: P,
+    ) -> Option<Self::BucketState> {
+        let mut data = state.0.snapshot();
+        self.rescale_state(&mut data, previous, t0);
+        let rescaled = State::default();
+        rescaled.0.measure_and_update(|state| *state = data);
+        Some(rescaled)
+    }
+}
+
+impl<P: clock::Reference> Exportable<P> for LeakyBucket<P> {
+    fn export_state(
+        &self,
+        state: &Self::BucketState,
+        anchor: &WallClockAnchor<P>,
+    ) -> Option<ExportedState> {
+        let data = state.0.snapshot();
+        let drained = data.last_update? + self.duration(data.level);
+        if drained <= anchor.at() {
+            return None;
+        }
+        Some(ExportedState::new(anchor.wall_clock_at(drained)))
+    }
+
+    fn import_state(
+        &self,
+        exported: &ExportedState,
+        anchor: &WallClockAnchor<P>,
+    ) -> Self::BucketState {
+        let state = State::default();
+        if let Some(drained) = anchor.instant_at(exported.drained_at()) {
+            state.0.measure_and_update(|data| {
+                data.level = self.fractions(drained.duration_since(anchor.at()));
+                data.last_update = Some(anchor.at());
+            });
+        }
+        state
+    }
+}
+
+impl<P: clock::Reference> LeakyBucket<P> {
+    /// Returns the parameters that the bucket was constructed with.
+    pub fn quota(&self) -> Quota {
+        self.quota
+    }
+
+    /// Decides whether `n` cells fit into the bucket at `t0`, and
+    /// returns the bucket's state after accommodating them.
+    fn decide(
+        &self,
+        state: &BucketState<P>,
+        n: u32,
+        t0: P,
+    ) -> Result<BucketState<P>, NegativeMultiDecision<TooEarly<P>>> {
+        let full = self.full;
+        let weight = self.token_interval * u128::from(n);
+        let mut new = BucketState {
+            last_update: Some(t0),
+            level: 0,
+        };
+        let last = state.last_update.unwrap_or(t0);
+        // Prevent time travel: If any parallel calls get re-ordered,
+        // or any tests attempt silly things, make sure to answer from
+        // the last query onwards instead.
+        let t0 = cmp::max(t0, last);
+        // Decrement the level by the amount the bucket
+        // has dripped in the meantime:
+        new.level = self.drain(state.level, t0.duration_since(last));
+        if weight + new.level <= full {
+            new.level += weight;
+            Ok(new)
+        } else {
+            let wait_period = self.duration((weight + new.level) - full);
+            Err(NegativeMultiDecision::BatchNonConforming(
+                n,
+                TooEarly(t0, wait_period),
+            ))
+        }
+    }
+
+    /// Returns a snapshot of the capacity left in the bucket when it
+    /// is filled to `level` at `t0`.
+    pub(crate) fn snapshot_level(&self, level: u128, t0: P) -> StateSnapshot<P> {
+        StateSnapshot::new(
+            cells_within(self.full, self.token_interval),
+            self.fitting_cells(level),
+            t0 + self.duration(level),
+        )
+    }
+
+    /// Returns the number of cells that fit into the bucket when it is
+    /// filled to `level`.
+    pub(crate) fn fitting_cells(&self, level: u128) -> u32 {
+        cells_within(self.full - cmp::min(level, self.full), self.token_interval)
+    }
+
+    /// Returns the bucket's level after charging `n` cells to it at
+    /// `level`. The result is never higher than `debt_ceiling` cells
+    /// past a full bucket, and never lower than `level`.
+    pub(crate) fn charge_level(&self, level: u128, n: u32, debt_ceiling: u32) -> u128 {
+        let ceiling = self.full + self.token_interval * u128::from(debt_ceiling);
+        cmp::max(
+            level,
+            cmp::min(level + self.token_interval * u128::from(n), ceiling),
+        )
+    }
+
+    /// Returns the bucket's level after it drained for `elapsed`,
+    /// starting at `level`.
+    pub(crate) fn drain(&self, level: u128, elapsed: Duration) -> u128 {
+        level.saturating_sub(self.fractions(elapsed))
+    }
+
+    /// Converts a bucket state that was accumulated under the
+    /// parameters of `previous` into this bucket's parameters at `t0`.
+    fn rescale_state(&self, state: &mut BucketState<P>, previous: &Self, t0: P) {
+        let last = match state.last_update {
+            Some(last) => last,
+            None => return,
+        };
+        let t0c = cmp::max(t0, last);
+        // The level is still counted in the previous parameters'
+        // fractions of a nanosecond:
+        let level = previous.drain(state.level, t0c.duration_since(last));
+        state.level = self.rescale_level(level, previous);
+        state.last_update = Some(t0);
+    }
+
+    /// Converts a level of the `previous` bucket into one that holds
+    /// the same number of cells in this bucket, even if that's more
+    /// than fit into it. Only levels that would overflow when
+    /// converted fall back to a full bucket.
+    pub(crate) fn rescale_level(&self, level: u128, previous: &Self) -> u128 {
+        checked_scale(level, self.token_interval, previous.token_interval).unwrap_or(self.full)
+    }
+
+    /// Converts a duration into fractions of a nanosecond.
+    pub(crate) fn fractions(&self, duration: Duration) -> u128 {
+        duration
+            .as_nanos()
+            .saturating_mul(u128::from(self.denominator))
+    }
+
+    /// Converts fractions of a nanosecond into a duration, rounding
+    /// up to the next whole nanosecond.
+    pub(crate) fn duration(&self, fractions: u128) -> Duration {
+        let denominator = u128::from(self.denominator);
+        from_nanos(fractions / denominator + u128::from(fractions % denominator != 0))
+    }
 }
@@ -0,0 +1,337 @@
+//! A lock-free variant of the leaky bucket algorithm
+
+use crate::lib::*;
+
+use crate::{
+    algorithms::{
+        check_whole_nanos, decode_since, encode_since, leaky_bucket::TooEarly, Algorithm,
+        LeakyBucket, RateLimitState, StateSnapshot,
+    },
+    clock,
+    thread_safety::SetOnce,
+    InconsistentCapacity, NegativeMultiDecision,
+};
+
+#[cfg(feature = "std")]
+mod std {
+    use crate::clock;
+    use evmap::ShallowCopy;
+
+    impl<P: clock::Reference> ShallowCopy for super::State<P> {
+        unsafe fn shallow_copy(&mut self) -> Self {
+            super::State(self.0.shallow_copy())
+        }
+    }
+}
+
+/// The bucket's fill level, stored as the time at which the bucket
+/// will have drained completely, in nanoseconds after an origin set
+/// on the first decision. The level at any instant is the time left
+/// until then, so the whole level fits into a single atomic integer.
+#[derive(Debug)]
+struct Level<P: clock::Reference> {
+    origin: SetOnce<P>,
+    empty_at: AtomicU64,
+}
+
+impl<P: clock::Reference> Default for Level<P> {
+    fn default() -> Self {
+        Level {
+            origin: Default::default(),
+            empty_at: AtomicU64::new(0),
+        }
+    }
+}
+
+impl<P: clock::Reference> Level<P> {
+    /// Returns the bucket's level at `t0`, if it was ever updated,
+    /// given the time at which it drains as encoded with
+    /// `encode_since`.
+    fn level_at(&self, empty_at: u64, t0: P) -> Option<Duration> {
+        let origin = *self.origin.get()?;
+        let drained = decode_since(origin, empty_at)?;
+        Some(drained.duration_since(t0))
+    }
+
+    /// Returns an independent copy of the level.
+    fn copy(&self) -> Self {
+        // The origin is set before the first level is stored, so load
+        // that first:
+        let empty_at = self.empty_at.load(Ordering::Acquire);
+        let copy = Level::default();
+        if let Some(origin) = self.origin.get() {
+            copy.origin.get_or_init(|| *origin);
+        }
+        copy.empty_at.store(empty_at, Ordering::Relaxed);
+        copy
+    }
+
+    fn snapshot(&self) -> Option<P> {
+        let origin = *self.origin.get()?;
+        decode_since(origin, self.empty_at.load(Ordering::Acquire))
+    }
+}
+
+/// The lock-free leaky bucket's state about a single rate limiting
+/// history.
+#[derive(Debug, Clone)]
+pub struct State<P: clock::Reference>(Arc<Level<P>>);
+
+impl<P: clock::Reference> Default for State<P> {
+    fn default() -> Self {
+        State(Default::default())
+    }
+}
+
+impl<P: clock::Reference> PartialEq for State<P> {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0) || self.0.snapshot() == other.0.snapshot()
+    }
+}
+
+impl<P: clock::Reference> Eq for State<P> {}
+
+impl<P: clock::Reference> RateLimitState<AtomicLeakyBucket<P>, P> for State<P> {
+    fn last_touched(&self, _params: &AtomicLeakyBucket<P>) -> Option<P> {
+        self.0.snapshot()
+    }
+}
+
+/// A variant of the [`LeakyBucket`](../struct.LeakyBucket.html) that
+/// keeps its state in atomic integers instead of behind a mutex.
+///
+/// The bucket's fill level is represented as the instant at which the
+/// bucket will have drained, which decisions update in a
+/// compare-and-swap loop. The drain instant is rounded up to whole
+/// nanoseconds; see [lock-free
+/// variants](../../../index.html#lock-free-variants) for how that
+/// changes the decisions.
+///
+/// Since it doesn't keep the time of its last update, a decision
+/// dated before an earlier one sees the bucket as it is at its own
+/// instant, i.e. without draining up to the earlier decision's
+/// instant the way the `LeakyBucket` does. Such decisions may turn
+/// away cells that the `LeakyBucket` lets through.
+///
+/// # Example
+/// ``` rust
+/// # use ratelimit_meter::{algorithms::leaky_bucket::AtomicLeakyBucket, DirectRateLimiter};
+/// # #[macro_use] extern crate nonzero_ext;
+/// # extern crate ratelimit_meter;
+/// # #[cfg(feature = "std")]
+/// # fn main () {
+/// let mut lb = DirectRateLimiter::<AtomicLeakyBucket>::per_second(nonzero!(2u32));
+/// assert_eq!(Ok(()), lb.check());
+/// # }
+/// # #[cfg(not(feature = "std"))] fn main() {}
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct AtomicLeakyBucket<P: clock::Reference = <clock::DefaultClock as clock::Clock>::Instant>(
+    LeakyBucket<P>,
+);
+
+impl<P: clock::Reference> AtomicLeakyBucket<P> {
+    /// Calls `f` with the bucket's level at `t0` (if it was ever
+    /// updated), and swaps in the level, in this bucket's fractions of
+    /// a nanosecond, that `f` returns. `f` is called again with the
+    /// latest level if another thread changed it in the meantime. If
+    /// `f` returns an error, the state is left alone.
+    fn update<F, R, E>(&self, state: &State<P>, t0: P, mut f: F) -> Result<R, E>
+    where
+        F: FnMut(Option<Duration>) -> Result<(u128, R), E>,
+    {
+        let data = &state.0;
+        let mut empty_at = data.empty_at.load(Ordering::Acquire);
+        loop {
+            let (level, result) = f(data.level_at(empty_at, t0))?;
+            let origin = *data.origin.get_or_init(|| t0);
+            let new = encode_since(origin, t0 + self.0.duration(level));
+            match data.empty_at.compare_exchange_weak(
+                empty_at,
+                new,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return Ok(result),
+                Err(actual) => empty_at = actual,
+            }
+        }
+    }
+
+    /// Tests if `n` cells can be accommodated at `t0` and updates the
+    /// state if so, returning the bucket's new level as of `t0`.
+    fn update_level(
+        &self,
+        state: &State<P>,
+        n: u32,
+        t0: P,
+    ) -> Result<u128, NegativeMultiDecision<TooEarly<P>>> {
+        let full = self.0.full;
+        let weight = self.0.token_interval * u128::from(n);
+        if weight > full {
+            return Err(NegativeMultiDecision::InsufficientCapacity(n));
+        }
+        self.update(state, t0, |level| {
+            let level = level.map_or(0, |level| self.0.fractions(level));
+            if weight + level > full {
+                let wait_period = self.0.duration((weight + level) - full);
+                return Err(NegativeMultiDecision::BatchNonConforming(
+                    n,
+                    TooEarly(t0, wait_period),
+                ));
+            }
+            Ok((level + weight, level + weight))
+        })
+    }
+
+    /// Returns the bucket's level at `t0`, in fractions of a
+    /// nanosecond.
+    fn current_level(&self, state: &State<P>, t0: P) -> u128 {
+        let data = &state.0;
+        data.level_at(data.empty_at.load(Ordering::Acquire), t0)
+            .map_or(0, |level| self.0.fractions(level))
+    }
+}
+
+impl<P: clock::Reference> Algorithm<P> for AtomicLeakyBucket<P> {
+    type BucketState = State<P>;
+
+    type NegativeDecision = TooEarly<P>;
+
+    fn construct(
+        capacity: NonZeroU32,
+        cell_weight: NonZeroU32,
+        per_time_unit: Duration,
+    ) -> Result<Self, InconsistentCapacity> {
+        Self::construct_with_burst(capacity, cell_weight, per_time_unit, capacity)
+    }
+
+    fn construct_with_burst(
+        capacity: NonZeroU32,
+        cell_weight: NonZeroU32,
+        per_time_unit: Duration,
+        burst: NonZeroU32,
+    ) -> Result<Self, InconsistentCapacity> {
+        let bucket =
+            LeakyBucket::construct_with_burst(capacity, cell_weight, per_time_unit, burst)?;
+        check_whole_nanos(
+            bucket.token_interval,
+            bucket.denominator,
+            capacity,
+            per_time_unit,
+        )?;
+        Ok(AtomicLeakyBucket(bucket))
+    }
+
+    fn test_n_and_update(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        t0: P,
+    ) -> Result<(), NegativeMultiDecision<TooEarly<P>>> {
+        self.update_level(state, n, t0).map(|_| ())
+    }
+
+    fn test_n_and_update_with_info(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        t0: P,
+    ) -> Result<StateSnapshot<P>, NegativeMultiDecision<TooEarly<P>>> {
+        let level = self.update_level(state, n, t0)?;
+        Ok(self.0.snapshot_level(level, t0))
+    }
+
+    fn test_up_to_n_and_update(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        t0: P,
+    ) -> (u32, Option<TooEarly<P>>) {
+        let interval = self.0.token_interval;
+        self.update(state, t0, |level| {
+            let level = level.map_or(0, |level| self.0.fractions(level));
+            let accepted = cmp::min(n, self.0.fitting_cells(level));
+            let level = level + interval * u128::from(accepted);
+            let nc = if accepted < n {
+                Some(TooEarly(
+                    t0,
+                    self.0.duration((level + interval) - self.0.full),
+                ))
+            } else {
+                None
+            };
+            if accepted == 0 {
+                return Err((accepted, nc));
+            }
+            Ok((level, (accepted, nc)))
+        })
+        .unwrap_or_else(|unchanged| unchanged)
+    }
+
+    fn test_n(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        t0: P,
+    ) -> Result<(), NegativeMultiDecision<TooEarly<P>>> {
+        let full = self.0.full;
+        let weight = self.0.token_interval * u128::from(n);
+        if weight > full {
+            return Err(NegativeMultiDecision::InsufficientCapacity(n));
+        }
+        let level = self.current_level(state, t0);
+        if weight + level > full {
+            let wait_period = self.0.duration((weight + level) - full);
+            return Err(NegativeMultiDecision::BatchNonConforming(
+                n,
+                TooEarly(t0, wait_period),
+            ));
+        }
+        Ok(())
+    }
+
+    fn charge_n(&self, state: &Self::BucketState, n: u32, debt_ceiling: u32, t0: P) {
+        let _: Result<(), ()> = self.update(state, t0, |level| {
+            let level = level.map_or(0, |level| self.0.fractions(level));
+            Ok((self.0.charge_level(level, n, debt_ceiling), ()))
+        });
+    }
+
+    fn snapshot(&self, state: &Self::BucketState, t0: P) -> StateSnapshot<P> {
+        self.0.snapshot_level(self.current_level(state, t0), t0)
+    }
+
+    fn refund_n(&self, state: &Self::BucketState, n: u32, t0: P) {
+        let _: Result<(), ()> = self.update(state, t0, |level| {
+            // Nothing was ever accepted, so there's nothing to refund.
+            let level = level.ok_or(())?;
+            let level = self.0.fractions(level);
+            let level = level - cmp::min(self.0.token_interval * u128::from(n), level);
+            Ok((level, ()))
+        });
+    }
+
+    fn rescale(&self, state: &Self::BucketState, previous: &Self, t0: P) {
+        let _: Result<(), ()> = self.update(state, t0, |level| {
+            let level = level.ok_or(())?;
+            // The level is counted in the previous parameters'
+            // fractions of a nanosecond:
+            let level = self
+                .0
+                .rescale_level(previous.0.fractions(level), &previous.0);
+            Ok((level, ()))
+        });
+    }
+
+    fn rescaled(
+        &self,
+        state: &Self::BucketState,
+        previous: &Self,
+        t0: P,
+    ) -> Option<Self::BucketState> {
+        let rescaled = State(Arc::new(state.0.copy()));
+        self.rescale(&rescaled, previous, t0);
+        Some(rescaled)
+    }
+}
@@ -0,0 +1,427 @@
+//! A rate limiter whose parameters can be changed at runtime
+
+use crate::lib::*;
+use crate::thread_safety::ThreadsafeWrapper;
+use crate::{
+    algorithms::{Algorithm, RateLimitState, StateSnapshot},
+    clock, InconsistentCapacity, NegativeMultiDecision, NonConformance,
+};
+
+#[cfg(feature = "std")]
+use parking_lot::RwLock;
+
+#[cfg(not(feature = "std"))]
+use spin::RwLock;
+
+/// Wraps a rate limiting algorithm (e.g., [`GCRA`](../gcra/struct.GCRA.html)
+/// or a [`LeakyBucket`](../leaky_bucket/struct.LeakyBucket.html)) so
+/// that its capacity, cell weight and time unit can be changed while
+/// rate limiters are using it.
+///
+/// Clones of a reconfigurable algorithm share their parameters, so
+/// reconfiguring one of them (or a rate limiter that holds one)
+/// affects all clones at once.
+///
+/// Unlike constructing a new rate limiter, reconfiguring keeps the
+/// rate limiting states that were accumulated so far: Each state is
+/// [rescaled](../trait.Algorithm.html#method.rescale) into the new
+/// parameters the next time a decision is made on it, so the cells it
+/// accounts for keep counting against the new limit. (Peeking at a
+/// state or taking a snapshot of it only looks at a rescaled copy.) For
+/// [`GCRA`](../gcra/struct.GCRA.html) and
+/// [`LeakyBucket`](../leaky_bucket/struct.LeakyBucket.html) (and their
+/// lock-free variants), e.g. a bucket that was half full stays half
+/// full when the capacity is doubled or halved. A
+/// [`Composite`](../composite/struct.Composite.html) rescales each of
+/// its bandwidths that way; since
+/// [`reconfigure`](#method.reconfigure) constructs a composite with a
+/// single bandwidth, use [`replace`](#method.replace) to keep several.
+///
+/// # Example
+/// ``` rust
+/// # use ratelimit_meter::{DirectRateLimiter, Reconfigurable, GCRA};
+/// # use std::time::Duration;
+/// # #[macro_use] extern crate nonzero_ext;
+/// # extern crate ratelimit_meter;
+/// # #[cfg(feature = "std")]
+/// # fn main () {
+/// let mut lim = DirectRateLimiter::<Reconfigurable<GCRA>>::per_second(nonzero!(50u32));
+/// assert_eq!(Ok(()), lim.check());
+/// // During an incident, drop to 5 cells per second:
+/// lim.reconfigure(nonzero!(5u32), nonzero!(1u32), Duration::from_secs(1))
+///     .unwrap();
+/// # }
+/// # #[cfg(not(feature = "std"))] fn main() {}
+/// ```
+pub struct Reconfigurable<
+    A: Algorithm<P>,
+    P: clock::Reference = <clock::DefaultClock as clock::Clock>::Instant,
+> {
+    current: Arc<RwLock<Current<A>>>,
+    point: PhantomData<P>,
+}
+
+/// Hands out a new generation number to every set of parameters, so
+/// that states can tell whether they were rescaled into the current
+/// ones without locking anything. Zero is reserved for states that
+/// weren't used yet.
+static NEXT_GENERATION: AtomicU64 = AtomicU64::new(1);
+
+/// The parameters that decisions are made with, along with their
+/// generation number.
+struct Current<A> {
+    algorithm: Arc<A>,
+    generation: u64,
+}
+
+impl<A> Current<A> {
+    fn new(algorithm: A) -> Self {
+        Current {
+            algorithm: Arc::new(algorithm),
+            generation: NEXT_GENERATION.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+}
+
+impl<A: Algorithm<P>, P: clock::Reference> Clone for Reconfigurable<A, P> {
+    fn clone(&self) -> Self {
+        Reconfigurable {
+            current: self.current.clone(),
+            point: PhantomData,
+        }
+    }
+}
+
+impl<A: Algorithm<P>, P: clock::Reference> fmt::Debug for Reconfigurable<A, P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(
+            f,
+            "Reconfigurable{{algorithm: {:?}}}",
+            self.current.read().algorithm
+        )
+    }
+}
+
+impl<A: Algorithm<P>, P: clock::Reference> Reconfigurable<A, P> {
+    /// Wraps an algorithm, so that it can be reconfigured.
+    pub fn new(algorithm: A) -> Self {
+        Reconfigurable {
+            current: Arc::new(RwLock::new(Current::new(algorithm))),
+            point: PhantomData,
+        }
+    }
+
+    /// Changes the parameters to let through `capacity` cells,
+    /// weighing `cell_weight`, every `per_time_unit`.
+    ///
+    /// Returns an error (and keeps the current parameters) if the
+    /// wrapped algorithm can't be constructed with the new ones.
+    pub fn reconfigure(
+        &self,
+        capacity: NonZeroU32,
+        cell_weight: NonZeroU32,
+        per_time_unit: Duration,
+    ) -> Result<(), InconsistentCapacity> {
+        self.replace(A::construct(capacity, cell_weight, per_time_unit)?);
+        Ok(())
+    }
+
+    /// Changes the parameters like [`reconfigure`](#method.reconfigure),
+    /// but accommodating bursts of at most `burst` units of weight.
+    /// See [`Algorithm::construct_with_burst`](../trait.Algorithm.html#method.construct_with_burst).
+    pub fn reconfigure_with_burst(
+        &self,
+        capacity: NonZeroU32,
+        cell_weight: NonZeroU32,
+        per_time_unit: Duration,
+        burst: NonZeroU32,
+    ) -> Result<(), InconsistentCapacity> {
+        self.replace(A::construct_with_burst(
+            capacity,
+            cell_weight,
+            per_time_unit,
+            burst,
+        )?);
+        Ok(())
+    }
+
+    /// Swaps in an algorithm that was constructed with the new
+    /// parameters.
+    pub fn replace(&self, algorithm: A) {
+        *self.current.write() = Current::new(algorithm);
+    }
+
+    /// Rescales the state into the current parameters if it was last
+    /// used with different ones, and calls `f` with the current
+    /// algorithm and the wrapped state.
+    ///
+    /// The parameters can't change while `f` runs. Only states that
+    /// need rescaling are locked; once a state is rescaled, this
+    /// takes nothing but the (shared) lock on the parameters.
+    fn with_current<F, R>(&self, state: &State<A, P>, at: P, f: F) -> R
+    where
+        F: FnOnce(&A, &A::BucketState) -> R,
+    {
+        let current = self.current.read();
+        if state.generation.load(Ordering::Acquire) != current.generation {
+            state.seen.measure_and_update(|seen| {
+                // Another thread may have rescaled the state while
+                // this one waited for the lock:
+                if state.generation.load(Ordering::Acquire) == current.generation {
+                    return;
+                }
+                if let Some(previous) = &seen.0 {
+                    current.algorithm.rescale(&state.inner, previous, at);
+                }
+                seen.0 = Some(current.algorithm.clone());
+                state
+                    .generation
+                    .store(current.generation, Ordering::Release);
+            });
+        }
+        f(&current.algorithm, &state.inner)
+    }
+
+    /// Like `with_current`, but leaves the state alone: If it needs
+    /// rescaling, `f` gets a rescaled copy.
+    fn peek_current<F, R>(&self, state: &State<A, P>, at: P, f: F) -> R
+    where
+        F: FnOnce(&A, &A::BucketState) -> R,
+    {
+        let current = self.current.read();
+        let mut rescaled = None;
+        if state.generation.load(Ordering::Acquire) != current.generation {
+            rescaled = state.seen.measure(|seen| {
+                if state.generation.load(Ordering::Acquire) == current.generation {
+                    return None;
+                }
+                current
+                    .algorithm
+                    .rescaled(&state.inner, seen.0.as_ref()?, at)
+            });
+        }
+        f(
+            &current.algorithm,
+            rescaled.as_ref().unwrap_or(&state.inner),
+        )
+    }
+}
+
+/// The parameters that a state was last used with.
+struct Seen<A>(Option<Arc<A>>);
+
+impl<A> Default for Seen<A> {
+    fn default() -> Self {
+        Seen(None)
+    }
+}
+
+impl<A> Clone for Seen<A> {
+    fn clone(&self) -> Self {
+        Seen(self.0.clone())
+    }
+}
+
+impl<A> PartialEq for Seen<A> {
+    fn eq(&self, other: &Self) -> bool {
+        match (&self.0, &other.0) {
+            (Some(mine), Some(other)) => Arc::ptr_eq(mine, other),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+}
+
+impl<A> Eq for Seen<A> {}
+
+impl<A: fmt::Debug> fmt::Debug for Seen<A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        self.0.fmt(f)
+    }
+}
+
+/// Represents the state of a single history of decisions, as kept
+/// by the wrapped algorithm, along with the parameters it was last
+/// used with.
+pub struct State<A: Algorithm<P>, P: clock::Reference> {
+    inner: A::BucketState,
+    seen: ThreadsafeWrapper<Seen<A>>,
+    // The generation number of the parameters in `seen`, which can
+    // be checked without locking them.
+    generation: Arc<AtomicU64>,
+}
+
+impl<A: Algorithm<P>, P: clock::Reference> Default for State<A, P> {
+    fn default() -> Self {
+        State {
+            inner: Default::default(),
+            seen: Default::default(),
+            generation: Arc::new(AtomicU64::new(0)),
+        }
+    }
+}
+
+impl<A: Algorithm<P>, P: clock::Reference> Clone for State<A, P>
+where
+    A::BucketState: Clone,
+{
+    fn clone(&self) -> Self {
+        State {
+            inner: self.inner.clone(),
+            seen: self.seen.clone(),
+            generation: self.generation.clone(),
+        }
+    }
+}
+
+impl<A: Algorithm<P>, P: clock::Reference> fmt::Debug for State<A, P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        self.inner.fmt(f)
+    }
+}
+
+impl<A: Algorithm<P>, P: clock::Reference> PartialEq for State<A, P> {
+    fn eq(&self, other: &Self) -> bool {
+        self.inner == other.inner
+    }
+}
+
+impl<A: Algorithm<P>, P: clock::Reference> Eq for State<A, P> {}
+
+impl<A: Algorithm<P>, P: clock::Reference> State<A, P> {
+    /// Returns the last instant at which the state has any effect on
+    /// decisions that `algorithm` makes.
+    pub(crate) fn last_touched_by(&self, algorithm: &A) -> Option<P> {
+        self.inner.last_touched(algorithm)
+    }
+}
+
+impl<A: Algorithm<P>, P: clock::Reference> RateLimitState<Reconfigurable<A, P>, P> for State<A, P> {
+    fn last_touched(&self, params: &Reconfigurable<A, P>) -> Option<P> {
+        self.last_touched_by(&params.current.read().algorithm)
+    }
+}
+
+#[cfg(feature = "std")]
+mod std {
+    use crate::algorithms::{Algorithm, KeyableRateLimitState};
+    use crate::clock;
+    use evmap::ShallowCopy;
+
+    impl<A: Algorithm<P>, P: clock::Reference> ShallowCopy for super::State<A, P>
+    where
+        A::BucketState: KeyableRateLimitState<A, P>,
+    {
+        unsafe fn shallow_copy(&mut self) -> Self {
+            super::State {
+                inner: self.inner.shallow_copy(),
+                seen: self.seen.shallow_copy(),
+                generation: self.generation.shallow_copy(),
+            }
+        }
+    }
+}
+
+impl<A: Algorithm<P>, P: clock::Reference> Algorithm<P> for Reconfigurable<A, P> {
+    type BucketState = State<A, P>;
+
+    type NegativeDecision = A::NegativeDecision;
+
+    fn construct(
+        capacity: NonZeroU32,
+        cell_weight: NonZeroU32,
+        per_time_unit: Duration,
+    ) -> Result<Self, InconsistentCapacity> {
+        Ok(Self::new(A::construct(
+            capacity,
+            cell_weight,
+            per_time_unit,
+        )?))
+    }
+
+    fn construct_with_burst(
+        capacity: NonZeroU32,
+        cell_weight: NonZeroU32,
+        per_time_unit: Duration,
+        burst: NonZeroU32,
+    ) -> Result<Self, InconsistentCapacity> {
+        Ok(Self::new(A::construct_with_burst(
+            capacity,
+            cell_weight,
+            per_time_unit,
+            burst,
+        )?))
+    }
+
+    fn test_n_and_update(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        t0: P,
+    ) -> Result<(), NegativeMultiDecision<Self::NegativeDecision>> {
+        self.with_current(state, t0, |algorithm, state| {
+            algorithm.test_n_and_update(state, n, t0)
+        })
+    }
+
+    fn test_n_and_update_with_info(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        t0: P,
+    ) -> Result<StateSnapshot<P>, NegativeMultiDecision<Self::NegativeDecision>> {
+        self.with_current(state, t0, |algorithm, state| {
+            algorithm.test_n_and_update_with_info(state, n, t0)
+        })
+    }
+
+    fn reserve_n(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        t0: P,
+    ) -> Result<P, NegativeMultiDecision<Self::NegativeDecision>>
+    where
+        Self::NegativeDecision: NonConformance<P>,
+    {
+        self.with_current(state, t0, |algorithm, state| {
+            algorithm.reserve_n(state, n, t0)
+        })
+    }
+
+    fn test_up_to_n_and_update(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        t0: P,
+    ) -> (u32, Option<Self::NegativeDecision>) {
+        self.with_current(state, t0, |algorithm, state| {
+            algorithm.test_up_to_n_and_update(state, n, t0)
+        })
+    }
+
+    fn test_n(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        t0: P,
+    ) -> Result<(), NegativeMultiDecision<Self::NegativeDecision>> {
+        self.peek_current(state, t0, |algorithm, state| algorithm.test_n(state, n, t0))
+    }
+
+    fn charge_n(&self, state: &Self::BucketState, n: u32, debt_ceiling: u32, t0: P) {
+        self.with_current(state, t0, |algorithm, state| {
+            algorithm.charge_n(state, n, debt_ceiling, t0)
+        })
+    }
+
+    fn refund_n(&self, state: &Self::BucketState, n: u32, t0: P) {
+        self.with_current(state, t0, |algorithm, state| {
+            algorithm.refund_n(state, n, t0)
+        })
+    }
+
+    fn snapshot(&self, state: &Self::BucketState, t0: P) -> StateSnapshot<P> {
+        self.peek_current(state, t0, |algorithm, state| algorithm.snapshot(state, t0))
+    }
+}
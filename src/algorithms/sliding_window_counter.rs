@@ -0,0 +1,310 @@
+//! An approximate sliding window counter algorithm
+
+use crate::lib::*;
+use crate::thread_safety::ThreadsafeWrapper;
+use crate::{
+    algorithms::{from_nanos, Algorithm, RateLimitState, StateSnapshot},
+    clock, InconsistentCapacity, NegativeMultiDecision, NonConformance,
+};
+
+/// Implements a sliding window counter: Time is divided into
+/// consecutive windows of `per_time_unit` length, and the rate
+/// limiter only counts the cells let through in the current and in
+/// the previous window.
+///
+/// To approximate a window that slides along with the arrival time
+/// of a cell, the count of the previous window is weighted by how
+/// much of it still overlaps with the sliding window: A cell arriving
+/// a quarter into the current window sees three quarters of the
+/// previous window's count, plus the full count of the current
+/// window.
+///
+/// # Trade-offs
+///
+/// Compared to the exact [sliding window
+/// log](../sliding_window_log/struct.SlidingWindowLog.html), this
+/// algorithm keeps only two counters per rate limiting state, no
+/// matter how large the capacity is. This makes it a good choice for
+/// [keyed rate limiters](../../state/keyed/struct.KeyedRateLimiter.html)
+/// with long windows, like 10,000 cells per day.
+///
+/// The price is accuracy: The weighting assumes that the cells in
+/// the previous window arrived evenly spread out. If they did not,
+/// the number of cells in a sliding window can be slightly above or
+/// below the capacity.
+///
+/// Windows start at the first cell checked against a state, not at
+/// any wall-clock boundaries.
+///
+/// # Example
+/// ``` rust
+/// # use ratelimit_meter::{DirectRateLimiter, SlidingWindowCounter};
+/// # use std::time::{Duration, Instant};
+/// # #[macro_use] extern crate nonzero_ext;
+/// # extern crate ratelimit_meter;
+/// # #[cfg(feature = "std")]
+/// # fn main () {
+/// let mut lim = DirectRateLimiter::<SlidingWindowCounter>::per_second(nonzero!(4u32));
+/// let now = Instant::now();
+/// let ms = Duration::from_millis(1);
+/// assert_eq!(Ok(()), lim.check_n_at(4, now));
+/// // Half-way into the next window, the previous window's 4 cells
+/// // still count as 2:
+/// assert_eq!(Ok(()), lim.check_n_at(2, now + ms * 1500));
+/// assert!(lim.check_at(now + ms * 1500).is_err());
+/// # }
+/// # #[cfg(not(feature = "std"))] fn main() {}
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SlidingWindowCounter<
+    P: clock::Reference = <clock::DefaultClock as clock::Clock>::Instant,
+> {
+    window: Duration,
+    max_cells: u32,
+    point: PhantomData<P>,
+}
+
+/// Represents the state of a single history of decisions.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct State<P: clock::Reference>(ThreadsafeWrapper<Counts<P>>);
+
+impl<P: clock::Reference> Default for State<P> {
+    fn default() -> Self {
+        State(Default::default())
+    }
+}
+
+impl<P: clock::Reference> RateLimitState<SlidingWindowCounter<P>, P> for State<P> {
+    fn last_touched(&self, params: &SlidingWindowCounter<P>) -> Option<P> {
+        let data = self.0.snapshot();
+        let start = data.window_start?;
+        if data.current > 0 {
+            Some(start + params.window + params.window)
+        } else {
+            Some(start + params.window)
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+mod std {
+    use crate::clock;
+    use evmap::ShallowCopy;
+
+    impl<P: clock::Reference> ShallowCopy for super::State<P> {
+        unsafe fn shallow_copy(&mut self) -> Self {
+            super::State(self.0.shallow_copy())
+        }
+    }
+}
+
+/// Returned in case of a negative rate-limiting decision. Indicates
+/// the earliest instant at which the weighted count of cells will
+/// have dropped enough to accommodate the cells in question.
+///
+/// To avoid thundering herd effects, client code should always add a
+/// random amount of jitter to wait time estimates.
+#[derive(Debug, PartialEq)]
+pub struct WindowEstimateFull<P: clock::Reference>(P);
+
+impl<P: clock::Reference> fmt::Display for WindowEstimateFull<P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "rate-limited until {:?}", self.0)
+    }
+}
+
+impl<P: clock::Reference> NonConformance<P> for WindowEstimateFull<P> {
+    #[inline]
+    fn earliest_possible(&self) -> P {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Counts<P: clock::Reference> {
+    window_start: Option<P>,
+    previous: u32,
+    current: u32,
+}
+
+impl<P: clock::Reference> Default for Counts<P> {
+    fn default() -> Self {
+        Counts {
+            window_start: None,
+            previous: 0,
+            current: 0,
+        }
+    }
+}
+
+impl<P: clock::Reference> Algorithm<P> for SlidingWindowCounter<P> {
+    type BucketState = State<P>;
+
+    type NegativeDecision = WindowEstimateFull<P>;
+
+    fn construct(
+        capacity: NonZeroU32,
+        cell_weight: NonZeroU32,
+        per_time_unit: Duration,
+    ) -> Result<Self, InconsistentCapacity> {
+        if capacity < cell_weight {
+            return Err(InconsistentCapacity::new(capacity, cell_weight));
+        }
+        if per_time_unit == Duration::new(0, 0) {
+            return Err(InconsistentCapacity::rate_unrepresentable(
+                capacity,
+                per_time_unit,
+            ));
+        }
+        Ok(SlidingWindowCounter {
+            window: per_time_unit,
+            max_cells: capacity.get() / cell_weight.get(),
+            point: PhantomData,
+        })
+    }
+
+    fn test_n_and_update(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        t0: P,
+    ) -> Result<(), NegativeMultiDecision<WindowEstimateFull<P>>> {
+        if n > self.max_cells {
+            return Err(NegativeMultiDecision::InsufficientCapacity(n));
+        }
+        state
+            .0
+            .measure_and_replace(|counts| match self.decide(counts, n, t0) {
+                Ok(new) => (Ok(()), Some(new)),
+                Err(e) => (Err(e), None),
+            })
+    }
+
+    fn test_n(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        t0: P,
+    ) -> Result<(), NegativeMultiDecision<WindowEstimateFull<P>>> {
+        if n > self.max_cells {
+            return Err(NegativeMultiDecision::InsufficientCapacity(n));
+        }
+        state
+            .0
+            .measure(|counts| self.decide(counts, n, t0).map(|_| ()))
+    }
+
+    fn charge_n(&self, state: &Self::BucketState, n: u32, debt_ceiling: u32, t0: P) {
+        let ceiling = self.max_cells.saturating_add(debt_ceiling);
+        state.0.measure_and_update(|counts| {
+            let (_, start, previous, current) = self.advance(counts, t0);
+            *counts = Counts {
+                window_start: Some(start),
+                previous,
+                current: cmp::max(current, cmp::min(current.saturating_add(n), ceiling)),
+            };
+        })
+    }
+
+    /// Refunds `n` cells from the current window, and any remaining
+    /// ones from the previous window.
+    fn refund_n(&self, state: &Self::BucketState, n: u32, t0: P) {
+        state.0.measure_and_update(|counts| {
+            if counts.window_start.is_none() {
+                return;
+            }
+            let (_, start, previous, current) = self.advance(counts, t0);
+            let from_current = cmp::min(n, current);
+            *counts = Counts {
+                window_start: Some(start),
+                previous: previous - cmp::min(n - from_current, previous),
+                current: current - from_current,
+            };
+        })
+    }
+
+    /// Reports the weighted count of cells, rounded up, as used.
+    fn snapshot(&self, state: &Self::BucketState, t0: P) -> StateSnapshot<P> {
+        let window = self.window;
+        state.0.measure(|counts| {
+            let (t0, start, previous, current) = self.advance(counts, t0);
+            let w = window.as_nanos();
+            let into = t0.duration_since(start).as_nanos();
+            let weighted = u128::from(previous) * (w - into) + u128::from(current) * w;
+            let used = (weighted + w - 1) / w;
+            let used = cmp::min(used, u128::from(self.max_cells)) as u32;
+            let reset_at = if current > 0 {
+                start + window + window
+            } else if previous > 0 {
+                start + window
+            } else {
+                t0
+            };
+            StateSnapshot::new(self.max_cells, self.max_cells - used, reset_at)
+        })
+    }
+}
+
+impl<P: clock::Reference> SlidingWindowCounter<P> {
+    /// Decides whether `n` cells fit into the sliding window ending at
+    /// `t0`, and returns the counts after accommodating them.
+    fn decide(
+        &self,
+        counts: &Counts<P>,
+        n: u32,
+        t0: P,
+    ) -> Result<Counts<P>, NegativeMultiDecision<WindowEstimateFull<P>>> {
+        let window = self.window;
+        let w = window.as_nanos();
+        let max = u128::from(self.max_cells);
+        let (t0, start, previous, current) = self.advance(counts, t0);
+        let (p, c, n) = (u128::from(previous), u128::from(current), u128::from(n));
+        let into = t0.duration_since(start).as_nanos();
+
+        // The weighted count, scaled up by the window length to
+        // stay in integer arithmetic:
+        if p * (w - into) + (c + n) * w <= max * w {
+            return Ok(Counts {
+                window_start: Some(start),
+                previous,
+                current: current + n as u32,
+            });
+        }
+
+        // Find the offset into a window at which the weighted
+        // previous count has dropped far enough:
+        let earliest = if c + n <= max {
+            start + from_nanos(w - (max - c - n) * w / p)
+        } else {
+            // The current window is full; wait for it to become the
+            // previous window:
+            start + window + from_nanos(w - (max - n) * w / c)
+        };
+        Err(NegativeMultiDecision::BatchNonConforming(
+            n as u32,
+            WindowEstimateFull(earliest),
+        ))
+    }
+
+    /// Moves the counts forward to the window containing `t0`, and
+    /// returns the (time-travel-corrected) `t0`, the start of that
+    /// window, and the previous and current window's counts.
+    fn advance(&self, counts: &Counts<P>, t0: P) -> (P, P, u32, u32) {
+        let window = self.window;
+        let start = counts.window_start.unwrap_or(t0);
+        // Prevent time travel: Answer queries from before the
+        // current window as if they happened at its start.
+        let t0 = cmp::max(t0, start);
+        let elapsed = t0.duration_since(start);
+        if elapsed < window {
+            (t0, start, counts.previous, counts.current)
+        } else if elapsed - window < window {
+            (t0, start + window, counts.current, 0)
+        } else {
+            // Skip ahead to the window containing t0:
+            let elapsed = elapsed.as_nanos();
+            let skipped = elapsed - elapsed % window.as_nanos();
+            (t0, start + from_nanos(skipped), 0, 0)
+        }
+    }
+}
@@ -0,0 +1,291 @@
+//! A sliding window log algorithm
+
+use crate::lib::*;
+use crate::thread_safety::ThreadsafeWrapper;
+use crate::{
+    algorithms::{Algorithm, RateLimitState, StateSnapshot},
+    clock, InconsistentCapacity, NegativeMultiDecision, NonConformance,
+};
+
+/// Implements a sliding window log: The rate limiter remembers the
+/// time stamp of every cell it let through during the last
+/// `per_time_unit`, and only lets a new cell through if the number
+/// of cells recorded in the window that ends at the new cell's
+/// arrival time stays within capacity.
+///
+/// Unlike the [leaky bucket](../leaky_bucket/struct.LeakyBucket.html)
+/// and the [GCRA](../gcra/struct.GCRA.html), which both approximate
+/// a rate over time, the sliding window log gives an exact guarantee:
+/// No window of length `per_time_unit` will ever contain more than
+/// `capacity / cell_weight` conforming cells.
+///
+/// # Memory usage
+///
+/// The price for that guarantee is that each rate limiting state
+/// keeps a time stamp for every decision in the current window that
+/// let cells through, so its size grows linearly with the
+/// capacity. For large capacities (in particular when using a [keyed
+/// rate limiter](../../state/keyed/struct.KeyedRateLimiter.html)),
+/// one of the other algorithms is probably a better choice.
+///
+/// # Example
+/// ``` rust
+/// # use ratelimit_meter::{DirectRateLimiter, SlidingWindowLog};
+/// # use std::time::{Duration, Instant};
+/// # #[macro_use] extern crate nonzero_ext;
+/// # extern crate ratelimit_meter;
+/// # #[cfg(feature = "std")]
+/// # fn main () {
+/// let mut lim = DirectRateLimiter::<SlidingWindowLog>::per_second(nonzero!(2u32));
+/// let now = Instant::now();
+/// let ms = Duration::from_millis(1);
+/// assert_eq!(Ok(()), lim.check_at(now));
+/// assert_eq!(Ok(()), lim.check_at(now + ms * 500));
+/// // The window that ends now contains two cells already:
+/// assert!(lim.check_at(now + ms * 999).is_err());
+/// // ...but the first cell has left the window after a second:
+/// assert_eq!(Ok(()), lim.check_at(now + ms * 1000));
+/// # }
+/// # #[cfg(not(feature = "std"))] fn main() {}
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SlidingWindowLog<P: clock::Reference = <clock::DefaultClock as clock::Clock>::Instant> {
+    window: Duration,
+    max_cells: u32,
+    point: PhantomData<P>,
+}
+
+/// Represents the state of a single history of decisions.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct State<P: clock::Reference>(ThreadsafeWrapper<Log<P>>);
+
+impl<P: clock::Reference> Default for State<P> {
+    fn default() -> Self {
+        State(Default::default())
+    }
+}
+
+impl<P: clock::Reference> RateLimitState<SlidingWindowLog<P>, P> for State<P> {
+    fn last_touched(&self, params: &SlidingWindowLog<P>) -> Option<P> {
+        let data = self.0.snapshot();
+        Some(data.0.back()?.0 + params.window)
+    }
+}
+
+#[cfg(feature = "std")]
+mod std {
+    use crate::clock;
+    use evmap::ShallowCopy;
+
+    impl<P: clock::Reference> ShallowCopy for super::State<P> {
+        unsafe fn shallow_copy(&mut self) -> Self {
+            super::State(self.0.shallow_copy())
+        }
+    }
+}
+
+/// Returned in case of a negative rate-limiting decision. Indicates
+/// the earliest instant at which enough cells will have left the
+/// window to accommodate the cells in question.
+///
+/// To avoid thundering herd effects, client code should always add a
+/// random amount of jitter to wait time estimates.
+#[derive(Debug, PartialEq)]
+pub struct WindowFull<P: clock::Reference>(P);
+
+impl<P: clock::Reference> fmt::Display for WindowFull<P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "rate-limited until {:?}", self.0)
+    }
+}
+
+impl<P: clock::Reference> NonConformance<P> for WindowFull<P> {
+    #[inline]
+    fn earliest_possible(&self) -> P {
+        self.0
+    }
+}
+
+impl<P: clock::Reference> SlidingWindowLog<P> {
+    /// Decides whether `n` cells fit into the window ending at `t0`.
+    /// If they do, returns the instant to log them at and the number
+    /// of log entries that have left the window.
+    fn decide(
+        &self,
+        log: &Log<P>,
+        n: u32,
+        t0: P,
+    ) -> Result<(P, usize), NegativeMultiDecision<WindowFull<P>>> {
+        let (t0, expired) = log.window_end(self.window, t0);
+        let live = log.live(expired);
+        let max_cells = u64::from(self.max_cells);
+        if live + u64::from(n) > max_cells {
+            // The batch fits once enough of the oldest cells have
+            // left the window:
+            let mut leaving = live + u64::from(n) - max_cells;
+            let (at, _) = log
+                .0
+                .iter()
+                .skip(expired)
+                .find(|&&(_, count)| {
+                    leaving = leaving.saturating_sub(u64::from(count));
+                    leaving == 0
+                })
+                .expect("BUG: more cells must leave the window than are in it");
+            return Err(NegativeMultiDecision::BatchNonConforming(
+                n,
+                WindowFull(*at + self.window),
+            ));
+        }
+        Ok((t0, expired))
+    }
+}
+
+/// The time stamps of the cells let through, oldest first, along
+/// with the number of cells let through at each of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Log<P: clock::Reference>(VecDeque<(P, u32)>);
+
+impl<P: clock::Reference> Default for Log<P> {
+    fn default() -> Self {
+        Log(VecDeque::new())
+    }
+}
+
+impl<P: clock::Reference> Log<P> {
+    /// Returns the end of the window that a decision at `t0` is made
+    /// in, along with the number of log entries that have left it.
+    fn window_end(&self, window: Duration, t0: P) -> (P, usize) {
+        // Prevent time travel: The log must stay sorted, so answer
+        // any queries that arrive out of order from the newest
+        // entry onwards.
+        let t0 = self.0.back().map_or(t0, |&(last, _)| cmp::max(t0, last));
+        let expired = self
+            .0
+            .iter()
+            .take_while(|&&(at, _)| at + window <= t0)
+            .count();
+        (t0, expired)
+    }
+
+    /// Returns the number of cells in the log entries after the first
+    /// `expired` ones.
+    fn live(&self, expired: usize) -> u64 {
+        self.0
+            .iter()
+            .skip(expired)
+            .map(|&(_, count)| u64::from(count))
+            .sum()
+    }
+
+    /// Records `n` cells let through at `t0`, which must be no
+    /// earlier than the newest entry.
+    fn push(&mut self, n: u32, t0: P) {
+        if n == 0 {
+            return;
+        }
+        match self.0.back_mut() {
+            Some((last, count)) if *last == t0 => *count += n,
+            _ => self.0.push_back((t0, n)),
+        }
+    }
+}
+
+impl<P: clock::Reference> Algorithm<P> for SlidingWindowLog<P> {
+    type BucketState = State<P>;
+
+    type NegativeDecision = WindowFull<P>;
+
+    fn construct(
+        capacity: NonZeroU32,
+        cell_weight: NonZeroU32,
+        per_time_unit: Duration,
+    ) -> Result<Self, InconsistentCapacity> {
+        if capacity < cell_weight {
+            return Err(InconsistentCapacity::new(capacity, cell_weight));
+        }
+        if per_time_unit == Duration::new(0, 0) {
+            return Err(InconsistentCapacity::rate_unrepresentable(
+                capacity,
+                per_time_unit,
+            ));
+        }
+        Ok(SlidingWindowLog {
+            window: per_time_unit,
+            max_cells: capacity.get() / cell_weight.get(),
+            point: PhantomData,
+        })
+    }
+
+    fn test_n_and_update(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        t0: P,
+    ) -> Result<(), NegativeMultiDecision<WindowFull<P>>> {
+        if n > self.max_cells {
+            return Err(NegativeMultiDecision::InsufficientCapacity(n));
+        }
+        state.0.measure_and_update(|log| {
+            let (t0, expired) = self.decide(log, n, t0)?;
+            log.0.drain(..expired);
+            log.push(n, t0);
+            Ok(())
+        })
+    }
+
+    fn test_n(
+        &self,
+        state: &Self::BucketState,
+        n: u32,
+        t0: P,
+    ) -> Result<(), NegativeMultiDecision<WindowFull<P>>> {
+        if n > self.max_cells {
+            return Err(NegativeMultiDecision::InsufficientCapacity(n));
+        }
+        state.0.measure(|log| self.decide(log, n, t0).map(|_| ()))
+    }
+
+    fn charge_n(&self, state: &Self::BucketState, n: u32, debt_ceiling: u32, t0: P) {
+        let ceiling = u64::from(self.max_cells.saturating_add(debt_ceiling));
+        state.0.measure_and_update(|log| {
+            let (t0, expired) = log.window_end(self.window, t0);
+            log.0.drain(..expired);
+            let room = ceiling.saturating_sub(log.live(0));
+            log.push(cmp::min(u64::from(n), room) as u32, t0);
+        })
+    }
+
+    /// Refunds the `n` newest cells that are still in the window.
+    fn refund_n(&self, state: &Self::BucketState, n: u32, t0: P) {
+        state.0.measure_and_update(|log| {
+            let (_, expired) = log.window_end(self.window, t0);
+            log.0.drain(..expired);
+            let mut n = n;
+            while let Some((_, count)) = log.0.back_mut() {
+                if *count > n {
+                    *count -= n;
+                    return;
+                }
+                n -= *count;
+                log.0.pop_back();
+            }
+        })
+    }
+
+    fn snapshot(&self, state: &Self::BucketState, t0: P) -> StateSnapshot<P> {
+        state.0.measure(|log| {
+            let (t0, expired) = log.window_end(self.window, t0);
+            let live = cmp::min(log.live(expired), u64::from(u32::MAX)) as u32;
+            let reset_at = match log.0.back() {
+                Some(&(last, _)) if live > 0 => last + self.window,
+                _ => t0,
+            };
+            StateSnapshot::new(
+                self.max_cells,
+                self.max_cells - cmp::min(live, self.max_cells),
+                reset_at,
+            )
+        })
+    }
+}
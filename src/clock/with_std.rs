@@ -1,6 +1,8 @@
-use super::{Clock, Reference};
+use super::{Clock, Nanos, Reference};
 use crate::lib::*;
 use parking_lot::Mutex;
+use std::sync::{OnceLock, Weak};
+use std::thread;
 use std::time::SystemTime;
 
 /// The default clock that reports [`Instant`]s.
@@ -64,6 +66,174 @@ impl Clock for MonotonicClock {
     }
 }
 
+/// A clock that serves a cached reading of the [`MonotonicClock`],
+/// which a background thread refreshes at a configurable granularity.
+///
+/// Reading the cached clock costs a single atomic load, which is
+/// cheaper than [`Instant::now`] on most platforms. In exchange, its
+/// readings lag behind the monotonic clock by up to the granularity
+/// (plus any delay in scheduling the background thread), so rate
+/// limiters that use it may let cells through up to that much later
+/// than they could. Its readings never go backwards.
+///
+/// Clones of a cached clock share its reading and background thread,
+/// which exits once the last clone is dropped. The default cached
+/// clock is shared by the whole process and refreshed every
+/// millisecond; like any other cached clock, it panics on
+/// construction if its background thread can't be spawned.
+///
+/// Since it reports [`Instant`]s, the cached clock can stand in for
+/// the monotonic clock in any rate limiter.
+///
+/// # Example
+/// ``` rust
+/// # use ratelimit_meter::{clock::CachedClock, DirectRateLimiter, GCRA};
+/// # use std::time::Duration;
+/// # #[macro_use] extern crate nonzero_ext;
+/// # extern crate ratelimit_meter;
+/// # fn main () {
+/// let clock = CachedClock::with_granularity(Duration::from_millis(10));
+/// let mut lim = DirectRateLimiter::<GCRA, CachedClock>::build_with_capacity(nonzero!(50u32))
+///     .using_clock(clock)
+///     .build()
+///     .unwrap();
+/// assert_eq!(Ok(()), lim.check());
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct CachedClock {
+    cache: Arc<Cache>,
+}
+
+#[derive(Debug)]
+struct Cache {
+    origin: Instant,
+    // The nanoseconds from the origin to the last refresh:
+    elapsed: AtomicU64,
+    granularity: Duration,
+}
+
+impl CachedClock {
+    /// The finest granularity a cached clock is refreshed at. Finer
+    /// ones would keep the background thread busy without making
+    /// readings any cheaper than the monotonic clock's.
+    pub const MIN_GRANULARITY: Duration = Duration::from_micros(100);
+
+    /// Starts a cached clock whose reading is refreshed every
+    /// `granularity` (but no more often than every
+    /// [`MIN_GRANULARITY`](#associatedconstant.MIN_GRANULARITY)) by a
+    /// new background thread.
+    ///
+    /// # Panics
+    /// Panics if the background thread can't be spawned.
+    pub fn with_granularity(granularity: Duration) -> Self {
+        let cache = Arc::new(Cache {
+            origin: Instant::now(),
+            elapsed: AtomicU64::new(0),
+            granularity: cmp::max(granularity, Self::MIN_GRANULARITY),
+        });
+        let weak = Arc::downgrade(&cache);
+        thread::spawn(move || refresh(weak));
+        CachedClock { cache }
+    }
+
+    /// Returns the interval at which the clock's reading is
+    /// refreshed.
+    pub fn granularity(&self) -> Duration {
+        self.cache.granularity
+    }
+}
+
+/// Refreshes the cached reading until the last clock using it is
+/// dropped.
+fn refresh(cache: Weak<Cache>) {
+    loop {
+        let granularity = match cache.upgrade() {
+            Some(cache) => {
+                let elapsed = cache.origin.elapsed().as_nanos();
+                let elapsed = cmp::min(elapsed, u128::from(u64::MAX)) as u64;
+                cache.elapsed.store(elapsed, Ordering::Release);
+                cache.granularity
+            }
+            None => return,
+        };
+        thread::sleep(granularity);
+    }
+}
+
+impl Default for CachedClock {
+    fn default() -> Self {
+        static DEFAULT: OnceLock<CachedClock> = OnceLock::new();
+        DEFAULT
+            .get_or_init(|| CachedClock::with_granularity(Duration::from_millis(1)))
+            .clone()
+    }
+}
+
+impl Clock for CachedClock {
+    type Instant = Instant;
+
+    fn now(&self) -> Self::Instant {
+        self.cache.origin + Duration::from_nanos(self.cache.elapsed.load(Ordering::Acquire))
+    }
+}
+
+/// A monotonic clock that reports compact [`Nanos`] since an anchor
+/// [`Instant`].
+///
+/// Rate limiting states measured by this clock take less space than
+/// ones measured by the [`MonotonicClock`], which makes a difference
+/// for keyed rate limiters tracking many keys. Default clocks share
+/// an anchor taken when the first of them is constructed.
+///
+/// # Example
+/// ``` rust
+/// # use ratelimit_meter::{clock::AnchoredClock, KeyedRateLimiter, GCRA};
+/// # #[macro_use] extern crate nonzero_ext;
+/// # extern crate ratelimit_meter;
+/// # fn main () {
+/// let mut lim = KeyedRateLimiter::<&str, GCRA<_>, AnchoredClock>::per_second(nonzero!(50u32));
+/// assert_eq!(Ok(()), lim.check("foo"));
+/// # }
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct AnchoredClock {
+    anchor: Instant,
+}
+
+impl AnchoredClock {
+    /// Constructs a clock that measures nanoseconds since `anchor`.
+    /// Instants earlier than the anchor are measured as the anchor.
+    pub fn with_anchor(anchor: Instant) -> Self {
+        AnchoredClock { anchor }
+    }
+
+    /// Returns the instant that the clock measures from.
+    pub fn anchor(&self) -> Instant {
+        self.anchor
+    }
+
+    /// Converts a measurement of the clock back into an [`Instant`].
+    pub fn instant_at(&self, at: Nanos) -> Instant {
+        self.anchor + Duration::from(at)
+    }
+}
+
+impl Default for AnchoredClock {
+    fn default() -> Self {
+        static ANCHOR: OnceLock<Instant> = OnceLock::new();
+        AnchoredClock::with_anchor(*ANCHOR.get_or_init(Instant::now))
+    }
+}
+
+impl Clock for AnchoredClock {
+    type Instant = Nanos;
+
+    fn now(&self) -> Self::Instant {
+        Nanos::from(Reference::duration_since(&Instant::now(), self.anchor))
+    }
+}
+
 /// The non-monotonic clock implemented by [`SystemTime`].
 #[derive(Clone, Debug, Default)]
 pub struct SystemClock();
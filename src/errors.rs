@@ -1,29 +1,213 @@
 use crate::lib::*;
 
-/// An error that is returned when initializing a rate limiter that is
-/// too small to let a single cell through.
+/// An error that is returned when initializing a rate limiter with
+/// parameters that can not work together, e.g. a bucket that is too
+/// small to let a single cell through.
 #[derive(Debug)]
 pub struct InconsistentCapacity {
-    capacity: NonZeroU32,
-    cell_weight: NonZeroU32,
+    reason: Inconsistency,
+}
+
+#[derive(Debug)]
+enum Inconsistency {
+    CellTooHeavy {
+        capacity: NonZeroU32,
+        cell_weight: NonZeroU32,
+    },
+    BurstTooSmall {
+        burst: NonZeroU32,
+        cell_weight: NonZeroU32,
+    },
+    BurstUnsupported {
+        capacity: NonZeroU32,
+        burst: NonZeroU32,
+    },
+    RateUnrepresentable {
+        capacity: NonZeroU32,
+        per_time_unit: Duration,
+    },
 }
 
 impl InconsistentCapacity {
     pub(crate) fn new(capacity: NonZeroU32, cell_weight: NonZeroU32) -> InconsistentCapacity {
         InconsistentCapacity {
-            capacity,
-            cell_weight,
+            reason: Inconsistency::CellTooHeavy {
+                capacity,
+                cell_weight,
+            },
+        }
+    }
+
+    pub(crate) fn burst_too_small(burst: NonZeroU32, cell_weight: NonZeroU32) -> Self {
+        InconsistentCapacity {
+            reason: Inconsistency::BurstTooSmall { burst, cell_weight },
+        }
+    }
+
+    pub(crate) fn burst_unsupported(capacity: NonZeroU32, burst: NonZeroU32) -> Self {
+        InconsistentCapacity {
+            reason: Inconsistency::BurstUnsupported { capacity, burst },
+        }
+    }
+
+    pub(crate) fn rate_unrepresentable(capacity: NonZeroU32, per_time_unit: Duration) -> Self {
+        InconsistentCapacity {
+            reason: Inconsistency::RateUnrepresentable {
+                capacity,
+                per_time_unit,
+            },
         }
     }
 }
 
 impl fmt::Display for InconsistentCapacity {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        write!(
-            f,
-            "bucket capacity {} too small for a single cell with weight {}",
-            self.capacity, self.cell_weight
-        )
+        match self.reason {
+            Inconsistency::CellTooHeavy {
+                capacity,
+                cell_weight,
+            } => write!(
+                f,
+                "bucket capacity {} too small for a single cell with weight {}",
+                capacity, cell_weight
+            ),
+            Inconsistency::BurstTooSmall { burst, cell_weight } => write!(
+                f,
+                "burst size {} too small for a single cell with weight {}",
+                burst, cell_weight
+            ),
+            Inconsistency::BurstUnsupported { capacity, burst } => write!(
+                f,
+                "algorithm can not allow bursts of {} independently of capacity {}",
+                burst, capacity
+            ),
+            Inconsistency::RateUnrepresentable {
+                capacity,
+                per_time_unit,
+            } => write!(
+                f,
+                "algorithm can not represent a rate of {} per {:?}",
+                capacity, per_time_unit
+            ),
+        }
+    }
+}
+
+/// An error that is returned when setting up how an
+/// [`Adaptive`](algorithms/adaptive/struct.Adaptive.html) algorithm
+/// adjusts its rate, e.g. with a ceiling below the floor.
+#[derive(Debug)]
+pub struct InvalidAdaptation {
+    reason: Misadaptation,
+}
+
+#[derive(Debug)]
+enum Misadaptation {
+    BoundsInverted {
+        floor: NonZeroU32,
+        ceiling: NonZeroU32,
+    },
+    DecreaseFactorOutOfRange {
+        factor: f64,
+    },
+    FloorInconsistent(InconsistentCapacity),
+}
+
+impl InvalidAdaptation {
+    pub(crate) fn bounds_inverted(floor: NonZeroU32, ceiling: NonZeroU32) -> Self {
+        InvalidAdaptation {
+            reason: Misadaptation::BoundsInverted { floor, ceiling },
+        }
+    }
+
+    pub(crate) fn decrease_factor_out_of_range(factor: f64) -> Self {
+        InvalidAdaptation {
+            reason: Misadaptation::DecreaseFactorOutOfRange { factor },
+        }
+    }
+
+    pub(crate) fn floor_inconsistent(inconsistency: InconsistentCapacity) -> Self {
+        InvalidAdaptation {
+            reason: Misadaptation::FloorInconsistent(inconsistency),
+        }
+    }
+}
+
+impl fmt::Display for InvalidAdaptation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match &self.reason {
+            Misadaptation::BoundsInverted { floor, ceiling } => write!(
+                f,
+                "capacity ceiling {} is below the floor {}",
+                ceiling, floor
+            ),
+            Misadaptation::DecreaseFactorOutOfRange { factor } => {
+                write!(f, "decrease factor {} is not between 0 and 1", factor)
+            }
+            Misadaptation::FloorInconsistent(inconsistency) => {
+                write!(f, "capacity floor is unusable: {}", inconsistency)
+            }
+        }
+    }
+}
+
+/// An error that is returned when a [`Quota`](struct.Quota.html)
+/// can not be parsed from a string.
+#[derive(Debug, PartialEq)]
+pub struct InvalidQuota {
+    reason: QuotaSyntax,
+}
+
+#[derive(Debug, PartialEq)]
+enum QuotaSyntax {
+    InvalidNumber,
+    MissingTimeUnit,
+    UnknownUnit,
+    UnexpectedInput,
+}
+
+impl InvalidQuota {
+    pub(crate) fn invalid_number() -> Self {
+        InvalidQuota {
+            reason: QuotaSyntax::InvalidNumber,
+        }
+    }
+
+    pub(crate) fn missing_time_unit() -> Self {
+        InvalidQuota {
+            reason: QuotaSyntax::MissingTimeUnit,
+        }
+    }
+
+    pub(crate) fn unknown_unit() -> Self {
+        InvalidQuota {
+            reason: QuotaSyntax::UnknownUnit,
+        }
+    }
+
+    pub(crate) fn unexpected_input() -> Self {
+        InvalidQuota {
+            reason: QuotaSyntax::UnexpectedInput,
+        }
+    }
+}
+
+impl fmt::Display for InvalidQuota {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self.reason {
+            QuotaSyntax::InvalidNumber => write!(f, "quota contains an invalid number"),
+            QuotaSyntax::MissingTimeUnit => {
+                write!(
+                    f,
+                    "quota is missing a \"/<time unit>\" or \"per <time unit>\""
+                )
+            }
+            QuotaSyntax::UnknownUnit => write!(f, "quota contains an unknown time unit"),
+            QuotaSyntax::UnexpectedInput => write!(
+                f,
+                "quota can only be followed by a single \"weight <n>\" and \"burst <n>\""
+            ),
+        }
     }
 }
 
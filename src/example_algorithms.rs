@@ -2,7 +2,7 @@
 
 use crate::lib::*;
 use crate::{
-    algorithms::{Algorithm, RateLimitState},
+    algorithms::{Algorithm, RateLimitState, StateSnapshot},
     clock, DirectRateLimiter, InconsistentCapacity, NegativeMultiDecision,
 };
 
@@ -65,6 +65,21 @@ impl Algorithm<Always> for Allower {
     ) -> Result<(), NegativeMultiDecision<Impossible>> {
         Ok(())
     }
+
+    /// Allows all cells through unconditionally.
+    fn test_n(
+        &self,
+        _state: &Self::BucketState,
+        _n: u32,
+        _t0: Always,
+    ) -> Result<(), NegativeMultiDecision<Impossible>> {
+        Ok(())
+    }
+
+    /// Reports unlimited capacity.
+    fn snapshot(&self, _state: &Self::BucketState, t0: Always) -> StateSnapshot<Always> {
+        StateSnapshot::new(u32::MAX, u32::MAX, t0)
+    }
 }
 
 /// A pseudo-instant that never changes.
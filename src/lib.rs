@@ -11,14 +11,28 @@
 //! The simple (one limit per object) state tracker can be used in
 //! `no_std` environments, such as embedded systems.
 //!
+//! To cap the number of cells in flight at the same time, the crate
+//! also provides [concurrency limiters](state/concurrency/index.html)
+//! that can be checked together with a rate limit.
+//!
 //! ## Interface
 //!
-//! This crate implements two "serious" rate-limiting/traffic-shaping
+//! This crate implements a few "serious" rate-limiting/traffic-shaping
 //! algorithms:
-//! [GCRA](https://en.wikipedia.org/wiki/Generic_cell_rate_algorithm)
-//! and a [Leaky
-//! Bucket](https://en.wikipedia.org/wiki/Leaky_bucket#As_a_meter). An
-//! "unserious" implementation is provided also: The
+//! [GCRA](https://en.wikipedia.org/wiki/Generic_cell_rate_algorithm),
+//! a [Leaky
+//! Bucket](https://en.wikipedia.org/wiki/Leaky_bucket#As_a_meter), a
+//! [sliding window log](algorithms/sliding_window_log/struct.SlidingWindowLog.html),
+//! an approximate [sliding window
+//! counter](algorithms/sliding_window_counter/struct.SlidingWindowCounter.html)
+//! and a [fixed window counter](algorithms/fixed_window/struct.FixedWindow.html)
+//! that resets on wall-clock boundaries. Several GCRA limits (e.g.
+//! 10 cells per second and 1000 per hour) can be enforced together
+//! with a [`Composite`](algorithms/composite/struct.Composite.html),
+//! and the rate of a GCRA or leaky bucket can be adjusted at runtime
+//! based on feedback with an [`Adaptive`](algorithms/adaptive/struct.Adaptive.html)
+//! wrapper.
+//! An "unserious" implementation is provided also: The
 //! [`Allower`](example_algorithms/struct.Allower.html), which returns
 //! "Yes" to all rate-limiting queries.
 //!
@@ -107,6 +121,31 @@
 //! checked, and supports checking "batches" of cells in a single call
 //! with no problems.
 //!
+//! ### Design and implementation of the sliding window log
+//!
+//! Both of the above algorithms approximate a rate over time, which
+//! means that a window of `per_time_unit` that starts at an arbitrary
+//! point may see slightly more cells than the rate would suggest. If
+//! that is not acceptable (e.g. to stay within a contractual limit),
+//! the sliding window log provides an exact guarantee by remembering
+//! the time of every cell in the current window - at the cost of
+//! memory proportional to the bucket capacity.
+//!
+//! The sliding window counter is a compromise between the two: It
+//! only keeps counts of the cells in the current and the previous
+//! window, and estimates the number of cells in the sliding window
+//! from those. Its memory use is constant, which makes it suitable
+//! for keyed rate limiters with long windows.
+//!
+//! ### Design and implementation of the fixed window counter
+//!
+//! The fixed window counter doesn't pretend to slide at all: It
+//! counts cells in windows aligned to multiples of `per_time_unit`
+//! on the wall clock (e.g., every calendar minute), and resets the
+//! count when a new window begins. This matches quotas like "1000
+//! calls per calendar hour", and requires the
+//! [`SystemClock`](clock/struct.SystemClock.html) to work.
+//!
 //! ## Thread-safe operation
 //!
 //! The in-memory implementations in this crate use parking_lot
@@ -135,6 +174,44 @@
 //! # fn main() {}
 //! ```
 //!
+//! ### Lock-free variants
+//!
+//! Where many threads check cells against the same state, the mutex
+//! can become a point of contention. The
+//! [`AtomicGCRA`](algorithms/gcra/atomic/struct.AtomicGCRA.html) and the
+//! [`AtomicLeakyBucket`](algorithms/leaky_bucket/atomic/struct.AtomicLeakyBucket.html)
+//! keep their state in atomic integers instead, and update it in
+//! compare-and-swap loops, so threads never block each other.
+//!
+//! In exchange, they can't be constructed for every rate their
+//! mutex-based counterparts support. Their states keep time stamps
+//! as offsets from the first decision made on them, in 64-bit
+//! integers:
+//!
+//! * The `AtomicGCRA` counts in the same fractions of a nanosecond as
+//!   the `GCRA` does, so it makes the same decisions. That covers
+//!   about 584 years divided by the number of fractions: around 195
+//!   years at 3 cells per second. Rates for which that would be less
+//!   than a year can't be constructed, and a state that is still in
+//!   use once its span has passed turns away all further cells.
+//! * The `AtomicLeakyBucket` counts in whole nanoseconds (which
+//!   covers about 584 years), and rounds up to the next nanosecond
+//!   after each decision. Where a cell's weight isn't a whole number
+//!   of nanoseconds, it lets through slightly fewer cells over long
+//!   runs, and rates at which a cell takes up less than a nanosecond
+//!   can't be constructed at all.
+//!
+//! ## Serialization
+//!
+//! With the `"serde"` feature enabled, [`Quota`](quota/struct.Quota.html)s,
+//! [`GCRA`](algorithms/gcra/struct.GCRA.html)s and
+//! [`LeakyBucket`](algorithms/leaky_bucket/struct.LeakyBucket.html)s
+//! can be serialized and deserialized in their quota string form
+//! (e.g. `"100/min"`), so they can be read from configuration files.
+//! Deserializing an algorithm validates its parameters like
+//! [`Algorithm::construct`](algorithms/trait.Algorithm.html#tymethod.construct)
+//! does.
+//!
 //! ## Usage with `no_std`
 //!
 //! `ratelimit_meter` can be used in `no_std` crates, with a reduced
@@ -215,12 +292,15 @@
 // Allow using ratelimit_meter without std
 #![cfg_attr(not(feature = "std"), no_std)]
 // Deny warnings
-#![cfg_attr(feature = "cargo-clippy", deny(warnings))]
+#![cfg_attr(clippy, deny(warnings))]
 
 pub mod algorithms;
 pub mod clock;
 mod errors;
 pub mod example_algorithms;
+pub mod quota;
+#[cfg(feature = "serde")]
+mod serialization;
 pub mod state;
 pub mod test_utilities;
 mod thread_safety;
@@ -231,12 +311,26 @@ extern crate nonzero_ext;
 #[cfg(not(feature = "std"))]
 extern crate alloc;
 
+pub use self::algorithms::Adaptive;
+pub use self::algorithms::Composite;
+#[cfg(feature = "std")]
+pub use self::algorithms::FixedWindow;
 pub use self::algorithms::LeakyBucket;
 pub use self::algorithms::NonConformance;
+pub use self::algorithms::Reconfigurable;
+pub use self::algorithms::SlidingWindowCounter;
+pub use self::algorithms::SlidingWindowLog;
+pub use self::algorithms::StateSnapshot;
 pub use self::algorithms::GCRA;
 
+pub use self::quota::Quota;
+
+pub use self::state::ConcurrencyLimiter;
 pub use self::state::DirectRateLimiter;
+pub use self::state::NegativeConcurrencyDecision;
 
+#[cfg(feature = "std")]
+pub use self::state::KeyedConcurrencyLimiter;
 #[cfg(feature = "std")]
 pub use self::state::KeyedRateLimiter;
 
@@ -255,28 +349,36 @@ mod lib {
 
     pub use self::core::clone::Clone;
     pub use self::core::cmp::{Eq, Ord, PartialEq};
+    pub use self::core::convert::TryFrom;
     pub use self::core::default::Default;
     pub use self::core::fmt::Debug;
     pub use self::core::marker::{Copy, PhantomData, Send, Sized, Sync};
-    pub use self::core::num::NonZeroU32;
+    pub use self::core::num::{NonZeroU32, NonZeroU64};
     pub use self::core::ops::{Add, Sub};
+    pub use self::core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
     pub use self::core::time::Duration;
 
     pub use self::core::cmp;
     pub use self::core::fmt;
+    pub use self::core::str::FromStr;
 
     /// Imports that are only available on std.
     #[cfg(feature = "std")]
     mod std {
         pub use std::collections::hash_map::RandomState;
+        pub use std::collections::HashMap;
+        pub use std::collections::VecDeque;
         pub use std::hash::{BuildHasher, Hash};
+        pub use std::io;
         pub use std::sync::Arc;
         pub use std::time::Instant;
     }
 
     #[cfg(feature = "no_std")]
     mod no_std {
+        pub use alloc::collections::VecDeque;
         pub use alloc::sync::Arc;
+        pub use alloc::vec::Vec;
     }
 
     #[cfg(feature = "std")]
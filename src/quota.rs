@@ -0,0 +1,254 @@
+//! Rate limiting parameters, bundled into a single value.
+
+use crate::lib::*;
+
+use crate::{algorithms::Algorithm, clock, InconsistentCapacity, InvalidQuota};
+
+/// The time units that quotas can be written in, from the longest to
+/// the shortest, with their lengths in nanoseconds. The first name of
+/// each unit is the one it is formatted with.
+const UNITS: &[(u64, &[&str])] = &[
+    (86_400_000_000_000, &["d", "day", "days"]),
+    (3_600_000_000_000, &["h", "hr", "hrs", "hour", "hours"]),
+    (60_000_000_000, &["min", "m", "mins", "minute", "minutes"]),
+    (1_000_000_000, &["s", "sec", "secs", "second", "seconds"]),
+    (1_000_000, &["ms"]),
+    (1_000, &["us", "µs"]),
+    (1, &["ns"]),
+];
+
+/// The parameters of a rate limiter: `capacity` cells, each weighing
+/// `cell_weight`, are let through every `per_time_unit`, with bursts
+/// of at most `burst` units of weight.
+///
+/// Quotas can be parsed from and formatted as strings, so they can
+/// live in configuration files. The canonical form is
+/// `<capacity>/<time unit>`, optionally followed by
+/// `weight <cell weight>` and `burst <burst>`, e.g. `100/min`,
+/// `5/2s burst 20` or `10/500ms weight 2`. When parsing, `per` can be
+/// used in place of the slash, and the time unit can be any of
+/// `ns`, `us`, `ms`, `s`, `min`, `h` and `d` (or their spelled-out
+/// names), optionally preceded by a number.
+///
+/// Every quota formats to a string that parses back to it. This
+/// includes quotas that no algorithm can enforce, like `1/0ns`;
+/// those are rejected when an algorithm is constructed from them.
+///
+/// # Example
+/// ``` rust
+/// # use ratelimit_meter::{DirectRateLimiter, Quota, GCRA};
+/// # use std::time::Duration;
+/// # #[macro_use] extern crate nonzero_ext;
+/// # extern crate ratelimit_meter;
+/// # #[cfg(feature = "std")]
+/// # fn main () {
+/// let quota: Quota = "5 per 2s burst 20".parse().unwrap();
+/// assert_eq!(
+///     Quota::new(nonzero!(5u32), Duration::from_secs(2)).with_burst(nonzero!(20u32)),
+///     quota
+/// );
+/// assert_eq!("5/2s burst 20", quota.to_string());
+///
+/// let mut lim = DirectRateLimiter::<GCRA>::build_with_quota(quota)
+///     .build()
+///     .unwrap();
+/// assert_eq!(Ok(()), lim.check_n(20));
+/// # }
+/// # #[cfg(not(feature = "std"))] fn main() {}
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    capacity: NonZeroU32,
+    cell_weight: NonZeroU32,
+    per_time_unit: Duration,
+    burst: Option<NonZeroU32>,
+}
+
+impl Quota {
+    /// Constructs a quota that lets through `capacity` cells every
+    /// `per_time_unit`.
+    pub fn new(capacity: NonZeroU32, per_time_unit: Duration) -> Quota {
+        Quota {
+            capacity,
+            cell_weight: nonzero!(1u32),
+            per_time_unit,
+            burst: None,
+        }
+    }
+
+    /// Returns the quota that an algorithm constructed with the given
+    /// parameters enforces.
+    pub(crate) fn of(
+        capacity: NonZeroU32,
+        cell_weight: NonZeroU32,
+        per_time_unit: Duration,
+        burst: NonZeroU32,
+    ) -> Quota {
+        Quota {
+            capacity,
+            cell_weight,
+            per_time_unit,
+            burst: if burst == capacity { None } else { Some(burst) },
+        }
+    }
+
+    /// Constructs a quota that lets through `capacity` cells per
+    /// second.
+    pub fn per_second(capacity: NonZeroU32) -> Quota {
+        Quota::new(capacity, Duration::from_secs(1))
+    }
+
+    /// Constructs a quota that lets through `capacity` cells per
+    /// minute.
+    pub fn per_minute(capacity: NonZeroU32) -> Quota {
+        Quota::new(capacity, Duration::from_secs(60))
+    }
+
+    /// Constructs a quota that lets through `capacity` cells per
+    /// hour.
+    pub fn per_hour(capacity: NonZeroU32) -> Quota {
+        Quota::new(capacity, Duration::from_secs(3600))
+    }
+
+    /// Sets the weight of each cell.
+    pub fn with_cell_weight(self, cell_weight: NonZeroU32) -> Quota {
+        Quota {
+            cell_weight,
+            ..self
+        }
+    }
+
+    /// Sets the largest burst of cell weight that is accommodated at
+    /// once. See
+    /// [`Algorithm::construct_with_burst`](algorithms/trait.Algorithm.html#method.construct_with_burst).
+    pub fn with_burst(self, burst: NonZeroU32) -> Quota {
+        Quota {
+            burst: Some(burst),
+            ..self
+        }
+    }
+
+    /// Returns the number of cells let through per time unit.
+    pub fn capacity(&self) -> NonZeroU32 {
+        self.capacity
+    }
+
+    /// Returns the weight of each cell.
+    pub fn cell_weight(&self) -> NonZeroU32 {
+        self.cell_weight
+    }
+
+    /// Returns the time unit.
+    pub fn per_time_unit(&self) -> Duration {
+        self.per_time_unit
+    }
+
+    /// Returns the burst size, if one was set separately from the
+    /// capacity.
+    pub fn burst(&self) -> Option<NonZeroU32> {
+        self.burst
+    }
+
+    /// Constructs a rate limiting algorithm with the quota's
+    /// parameters, e.g. to add it to a
+    /// [`Composite`](algorithms/composite/struct.Composite.html).
+    pub fn construct<A: Algorithm<P>, P: clock::Reference>(
+        &self,
+    ) -> Result<A, InconsistentCapacity> {
+        match self.burst {
+            Some(burst) => {
+                A::construct_with_burst(self.capacity, self.cell_weight, self.per_time_unit, burst)
+            }
+            None => A::construct(self.capacity, self.cell_weight, self.per_time_unit),
+        }
+    }
+}
+
+impl fmt::Display for Quota {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{}/", self.capacity)?;
+        let nanos = self.per_time_unit.as_nanos();
+        let (length, names) = UNITS
+            .iter()
+            .find(|(length, _)| nanos >= u128::from(*length) && nanos % u128::from(*length) == 0)
+            .unwrap_or(&UNITS[UNITS.len() - 1]);
+        match nanos / u128::from(*length) {
+            1 => write!(f, "{}", names[0])?,
+            count => write!(f, "{}{}", count, names[0])?,
+        }
+        if self.cell_weight.get() != 1 {
+            write!(f, " weight {}", self.cell_weight)?;
+        }
+        if let Some(burst) = self.burst {
+            write!(f, " burst {}", burst)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Quota {
+    type Err = InvalidQuota;
+
+    fn from_str(s: &str) -> Result<Quota, InvalidQuota> {
+        let (capacity, rest) = match s.find('/') {
+            Some(slash) => (&s[..slash], &s[slash + 1..]),
+            None => {
+                let s = s.trim_start();
+                let end = s.find(char::is_whitespace).unwrap_or(s.len());
+                let rest = s[end..].trim_start();
+                match rest.find(char::is_whitespace) {
+                    Some(per) if &rest[..per] == "per" => (&s[..end], &rest[per..]),
+                    _ => return Err(InvalidQuota::missing_time_unit()),
+                }
+            }
+        };
+        let mut quota = Quota::new(parse_number(capacity)?, Duration::new(0, 0));
+        let mut words = rest.split_whitespace();
+        quota.per_time_unit = parse_time_unit(words.next())?;
+        let mut cell_weight = None;
+        while let Some(keyword) = words.next() {
+            let setting = match keyword {
+                "weight" => &mut cell_weight,
+                "burst" => &mut quota.burst,
+                _ => return Err(InvalidQuota::unexpected_input()),
+            };
+            if setting.is_some() {
+                return Err(InvalidQuota::unexpected_input());
+            }
+            *setting = Some(parse_number(words.next().unwrap_or(""))?);
+        }
+        quota.cell_weight = cell_weight.unwrap_or(quota.cell_weight);
+        Ok(quota)
+    }
+}
+
+fn parse_number(s: &str) -> Result<NonZeroU32, InvalidQuota> {
+    s.trim().parse().map_err(|_| InvalidQuota::invalid_number())
+}
+
+/// Parses a time unit like `min` or `500ms`.
+///
+/// Accepts every time unit that quotas are formatted with, even ones
+/// that no algorithm can represent (like `0ns`); those are rejected
+/// when the quota is used to construct an algorithm.
+fn parse_time_unit(word: Option<&str>) -> Result<Duration, InvalidQuota> {
+    let word = word.ok_or_else(InvalidQuota::missing_time_unit)?;
+    let digits = word
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(InvalidQuota::unknown_unit)?;
+    let count: u128 = match digits {
+        0 => 1,
+        _ => word[..digits]
+            .parse()
+            .map_err(|_| InvalidQuota::invalid_number())?,
+    };
+    let (length, _) = UNITS
+        .iter()
+        .find(|(_, names)| names.contains(&&word[digits..]))
+        .ok_or_else(InvalidQuota::unknown_unit)?;
+    let nanos = count
+        .checked_mul(u128::from(*length))
+        .ok_or_else(InvalidQuota::invalid_number)?;
+    let secs = u64::try_from(nanos / 1_000_000_000).map_err(|_| InvalidQuota::invalid_number())?;
+    Ok(Duration::new(secs, (nanos % 1_000_000_000) as u32))
+}
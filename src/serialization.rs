@@ -0,0 +1,93 @@
+//! Serialization of rate limiting parameters with serde, enabled by
+//! the `serde` feature.
+//!
+//! Parameters are (de)serialized in their [`Quota`](../quota/struct.Quota.html)
+//! string form, e.g. `"100/min"` or `"5/2s burst 20"`. Algorithms are
+//! deserialized through
+//! [`Algorithm::construct`](../algorithms/trait.Algorithm.html#tymethod.construct),
+//! so parameters that can't work together fail to deserialize.
+//! [Exported states](../algorithms/export/struct.ExportedState.html)
+//! are (de)serialized as the wall-clock time at which they drain.
+
+use crate::lib::*;
+
+use crate::{
+    algorithms::{Algorithm, ExportedState},
+    clock, LeakyBucket, Quota, GCRA,
+};
+use serde_crate::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+impl Serialize for Quota {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Quota {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(QuotaVisitor)
+    }
+}
+
+struct QuotaVisitor;
+
+impl<'de> de::Visitor<'de> for QuotaVisitor {
+    type Value = Quota;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a quota like \"100/min\"")
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<Quota, E> {
+        s.parse().map_err(E::custom)
+    }
+}
+
+/// Constructs an algorithm from a deserialized quota, reporting
+/// inconsistent parameters as a deserialization error.
+fn construct<'de, A, P, D>(deserializer: D) -> Result<A, D::Error>
+where
+    A: Algorithm<P>,
+    P: clock::Reference,
+    D: Deserializer<'de>,
+{
+    Quota::deserialize(deserializer)?
+        .construct()
+        .map_err(de::Error::custom)
+}
+
+impl<P: clock::Reference> Serialize for GCRA<P> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.quota().serialize(serializer)
+    }
+}
+
+impl<'de, P: clock::Reference> Deserialize<'de> for GCRA<P> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        construct(deserializer)
+    }
+}
+
+impl<P: clock::Reference> Serialize for LeakyBucket<P> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.quota().serialize(serializer)
+    }
+}
+
+impl<'de, P: clock::Reference> Deserialize<'de> for LeakyBucket<P> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        construct(deserializer)
+    }
+}
+
+impl Serialize for ExportedState {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.drained_at().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ExportedState {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Duration::deserialize(deserializer).map(ExportedState::new)
+    }
+}
@@ -1,11 +1,19 @@
 //! Data structures that keep rate-limiting state.
 
+pub mod concurrency;
 pub mod direct;
+pub mod reservation;
 
 #[cfg(feature = "std")]
 pub mod keyed;
 
+pub use self::concurrency::{
+    ConcurrencyGuard, ConcurrencyLimiter, NegativeConcurrencyDecision, TooManyInFlight,
+};
 pub use self::direct::DirectRateLimiter;
+pub use self::reservation::Reservation;
 
+#[cfg(feature = "std")]
+pub use self::concurrency::KeyedConcurrencyLimiter;
 #[cfg(feature = "std")]
 pub use self::keyed::KeyedRateLimiter;
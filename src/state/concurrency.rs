@@ -0,0 +1,217 @@
+//! Limiters for the number of cells that are in flight at the same
+//! time.
+
+use crate::lib::*;
+
+#[cfg(feature = "std")]
+use parking_lot::Mutex;
+
+/// Limits the number of cells that can be in flight (e.g., requests
+/// being processed) at the same time.
+///
+/// Acquiring a slot returns a [`ConcurrencyGuard`](struct.ConcurrencyGuard.html);
+/// the slot is released when the guard is dropped. Clones of a
+/// concurrency limiter share their slots.
+///
+/// To enforce a rate limit and a concurrency limit in one check, use
+/// [`DirectRateLimiter::check_and_acquire`](../direct/struct.DirectRateLimiter.html#method.check_and_acquire).
+///
+/// # Example
+/// ``` rust
+/// # use ratelimit_meter::ConcurrencyLimiter;
+/// # #[macro_use] extern crate nonzero_ext;
+/// # extern crate ratelimit_meter;
+/// # fn main () {
+/// let limiter = ConcurrencyLimiter::new(nonzero!(1u32));
+/// let guard = limiter.try_acquire().unwrap();
+/// assert!(limiter.try_acquire().is_err());
+/// drop(guard);
+/// assert!(limiter.try_acquire().is_ok());
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ConcurrencyLimiter {
+    max_in_flight: NonZeroU32,
+    in_flight: Arc<AtomicU32>,
+}
+
+impl ConcurrencyLimiter {
+    /// Construct a new concurrency limiter that allows up to
+    /// `max_in_flight` cells in flight at the same time.
+    pub fn new(max_in_flight: NonZeroU32) -> Self {
+        ConcurrencyLimiter {
+            max_in_flight,
+            in_flight: Default::default(),
+        }
+    }
+
+    /// Returns the number of cells that are currently in flight.
+    pub fn in_flight(&self) -> u32 {
+        self.in_flight.load(Ordering::Acquire)
+    }
+
+    /// Tries to acquire a slot for a cell. If the limiter is at
+    /// capacity, returns `Err` with the maximum number of cells
+    /// allowed in flight.
+    pub fn try_acquire(&self) -> Result<ConcurrencyGuard, TooManyInFlight> {
+        acquire(&self.in_flight, self.max_in_flight)
+    }
+}
+
+fn acquire(
+    in_flight: &Arc<AtomicU32>,
+    max_in_flight: NonZeroU32,
+) -> Result<ConcurrencyGuard, TooManyInFlight> {
+    in_flight
+        .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+            if n < max_in_flight.get() {
+                Some(n + 1)
+            } else {
+                None
+            }
+        })
+        .map_err(|_| TooManyInFlight(max_in_flight.get()))?;
+    Ok(ConcurrencyGuard {
+        in_flight: in_flight.clone(),
+    })
+}
+
+/// A slot acquired from a concurrency limiter. The slot is given back
+/// when the guard is dropped.
+#[derive(Debug)]
+#[must_use = "the slot is released as soon as the guard is dropped"]
+pub struct ConcurrencyGuard {
+    in_flight: Arc<AtomicU32>,
+}
+
+impl Drop for ConcurrencyGuard {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Returned when a concurrency limiter has no free slots. Contains the
+/// maximum number of cells allowed in flight.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct TooManyInFlight(pub u32);
+
+impl fmt::Display for TooManyInFlight {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "already {} cells in flight", self.0)
+    }
+}
+
+/// Gives information about the negative outcome of a check that
+/// enforces both a rate limit and a concurrency limit.
+#[derive(Debug, PartialEq)]
+pub enum NegativeConcurrencyDecision<E: fmt::Display> {
+    /// The cell is non-conforming to the rate limit. The argument
+    /// gives information about when it might be let through.
+    NonConforming(E),
+
+    /// The concurrency limit has no free slots.
+    TooManyInFlight(TooManyInFlight),
+}
+
+impl<E> fmt::Display for NegativeConcurrencyDecision<E>
+where
+    E: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            NegativeConcurrencyDecision::NonConforming(err) => write!(f, "{}", err),
+            NegativeConcurrencyDecision::TooManyInFlight(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+/// Limits the number of cells that can be in flight at the same time,
+/// separately for each key (e.g., per tenant).
+///
+/// Keys whose cells are all done can be removed with
+/// [`cleanup`](#method.cleanup).
+///
+/// # Example
+/// ``` rust
+/// # use ratelimit_meter::KeyedConcurrencyLimiter;
+/// # #[macro_use] extern crate nonzero_ext;
+/// # extern crate ratelimit_meter;
+/// # fn main () {
+/// let limiter = KeyedConcurrencyLimiter::<&str>::new(nonzero!(1u32));
+/// let _guard = limiter.try_acquire("tenant1").unwrap();
+/// assert!(limiter.try_acquire("tenant1").is_err());
+/// assert!(limiter.try_acquire("tenant2").is_ok());
+/// # }
+/// ```
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct KeyedConcurrencyLimiter<K: Eq + Hash, H: BuildHasher = RandomState> {
+    max_in_flight: NonZeroU32,
+    in_flight: Arc<Mutex<HashMap<K, Arc<AtomicU32>, H>>>,
+}
+
+#[cfg(feature = "std")]
+impl<K: Eq + Hash, H: BuildHasher> Clone for KeyedConcurrencyLimiter<K, H> {
+    fn clone(&self) -> Self {
+        KeyedConcurrencyLimiter {
+            max_in_flight: self.max_in_flight,
+            in_flight: self.in_flight.clone(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K: Eq + Hash> KeyedConcurrencyLimiter<K> {
+    /// Construct a new keyed concurrency limiter that allows up to
+    /// `max_in_flight` cells in flight per key.
+    pub fn new(max_in_flight: NonZeroU32) -> Self {
+        Self::with_hasher(max_in_flight, Default::default())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K: Eq + Hash, H: BuildHasher> KeyedConcurrencyLimiter<K, H> {
+    /// Construct a new keyed concurrency limiter that uses
+    /// `hash_builder` to hash keys.
+    pub fn with_hasher(max_in_flight: NonZeroU32, hash_builder: H) -> Self {
+        KeyedConcurrencyLimiter {
+            max_in_flight,
+            in_flight: Arc::new(Mutex::new(HashMap::with_hasher(hash_builder))),
+        }
+    }
+
+    /// Returns the number of cells that are currently in flight for
+    /// `key`.
+    pub fn in_flight(&self, key: &K) -> u32 {
+        self.in_flight
+            .lock()
+            .get(key)
+            .map_or(0, |n| n.load(Ordering::Acquire))
+    }
+
+    /// Tries to acquire a slot for a cell with the given key. If that
+    /// key is at capacity, returns `Err` with the maximum number of
+    /// cells allowed in flight.
+    pub fn try_acquire(&self, key: K) -> Result<ConcurrencyGuard, TooManyInFlight> {
+        let mut map = self.in_flight.lock();
+        acquire(map.entry(key).or_default(), self.max_in_flight)
+    }
+
+    /// Removes the keys that have no cells in flight, and returns
+    /// them.
+    pub fn cleanup(&self) -> Vec<K>
+    where
+        K: Clone,
+    {
+        let mut map = self.in_flight.lock();
+        let idle: Vec<K> = map
+            .iter()
+            .filter(|(_, n)| n.load(Ordering::Acquire) == 0)
+            .map(|(k, _)| k.clone())
+            .collect();
+        for key in idle.iter() {
+            map.remove(key);
+        }
+        idle
+    }
+}
@@ -4,10 +4,19 @@
 use crate::lib::*;
 
 use crate::{
-    algorithms::{Algorithm, DefaultAlgorithm},
-    clock, InconsistentCapacity, NegativeMultiDecision,
+    algorithms::{
+        gcra::Schedule, Algorithm, DefaultAlgorithm, Reconfigurable, StateSnapshot, GCRA,
+    },
+    clock,
+    state::concurrency::{ConcurrencyGuard, ConcurrencyLimiter, NegativeConcurrencyDecision},
+    state::reservation::Reservation,
+    InconsistentCapacity, NegativeMultiDecision, NonConformance, Quota,
 };
 
+/// The negative outcome of a decision on a batch of cells.
+type MultiDecision<A, C> =
+    NegativeMultiDecision<<A as Algorithm<<C as clock::Clock>::Instant>>::NegativeDecision>;
+
 /// An in-memory rate limiter that makes direct (un-keyed)
 /// rate-limiting decisions. Direct rate limiters can be used to
 /// e.g. regulate the transmission of packets on a single connection,
@@ -20,6 +29,7 @@ pub struct DirectRateLimiter<
 > {
     state: A::BucketState,
     algorithm: A,
+    debt_ceiling: u32,
     clock: C,
 }
 
@@ -62,10 +72,20 @@ where
                 per_time_unit,
             )
             .unwrap(),
+            debt_ceiling: 0,
             clock: Default::default(),
         }
     }
 
+    /// Construct a new rate limiter with the parameters of a
+    /// [`Quota`](../../quota/struct.Quota.html).
+    ///
+    /// Returns an error if the algorithm can't be constructed with
+    /// them.
+    pub fn with_quota(quota: Quota) -> Result<Self, InconsistentCapacity> {
+        Ok(Self::with_algorithm(quota.construct()?))
+    }
+
     /// Construct a new rate limiter that allows `capacity` cells per
     /// second.
     /// # Examples
@@ -94,6 +114,18 @@ where
         Self::new(capacity, Duration::from_secs(1))
     }
 
+    /// Construct a new rate limiter from an already-constructed
+    /// algorithm, e.g. a [`Composite`](../../algorithms/composite/struct.Composite.html)
+    /// of several bandwidths.
+    pub fn with_algorithm(algorithm: A) -> Self {
+        DirectRateLimiter {
+            state: <A as Algorithm<C::Instant>>::BucketState::default(),
+            algorithm,
+            debt_ceiling: 0,
+            clock: Default::default(),
+        }
+    }
+
     /// Return a builder that can be used to construct a rate limiter using
     /// the parameters passed to the Builder.
     pub fn build_with_capacity(capacity: NonZeroU32) -> Builder<C, A> {
@@ -101,6 +133,23 @@ where
             capacity,
             cell_weight: nonzero!(1u32),
             time_unit: Duration::from_secs(1),
+            burst: None,
+            debt_ceiling: 0,
+            end_result: PhantomData,
+            clock: Default::default(),
+        }
+    }
+
+    /// Return a builder that can be used to construct a rate limiter
+    /// using the parameters of a
+    /// [`Quota`](../../quota/struct.Quota.html).
+    pub fn build_with_quota(quota: Quota) -> Builder<C, A> {
+        Builder {
+            capacity: quota.capacity(),
+            cell_weight: quota.cell_weight(),
+            time_unit: quota.per_time_unit(),
+            burst: quota.burst(),
+            debt_ceiling: 0,
             end_result: PhantomData,
             clock: Default::default(),
         }
@@ -161,6 +210,368 @@ where
         self.algorithm
             .test_n_and_update(&self.state, n, self.clock.now())
     }
+
+    /// Tests if a single cell can be accommodated at the clock's
+    /// current reading, like [`check`](#method.check) does. If it
+    /// can be, returns a snapshot of the capacity left after
+    /// accommodating it, e.g. for reporting rate limit headers on
+    /// the allowed response.
+    ///
+    /// # Example
+    /// ``` rust
+    /// # use ratelimit_meter::{DirectRateLimiter, LeakyBucket};
+    /// # #[macro_use] extern crate nonzero_ext;
+    /// # extern crate ratelimit_meter;
+    /// # #[cfg(feature = "std")]
+    /// # fn main () {
+    /// let mut lim = DirectRateLimiter::<LeakyBucket>::per_second(nonzero!(10u32));
+    /// let info = lim.check_with_info().unwrap();
+    /// assert_eq!(9, info.remaining());
+    /// # }
+    /// # #[cfg(not(feature = "std"))] fn main() {}
+    /// ```
+    pub fn check_with_info(
+        &mut self,
+    ) -> Result<StateSnapshot<C::Instant>, <A as Algorithm<C::Instant>>::NegativeDecision> {
+        let at = self.clock.now();
+        self.check_with_info_at(at)
+    }
+
+    /// Tests if a single cell can be accommodated at the given time
+    /// stamp, returning a snapshot of the capacity left if so. See
+    /// [`check_with_info`](#method.check_with_info).
+    pub fn check_with_info_at(
+        &mut self,
+        at: C::Instant,
+    ) -> Result<StateSnapshot<C::Instant>, <A as Algorithm<C::Instant>>::NegativeDecision> {
+        self.algorithm.test_and_update_with_info(&self.state, at)
+    }
+
+    /// Tests if `n` cells can be accommodated at the clock's current
+    /// reading, like [`check_n`](#method.check_n) does. If they can
+    /// be, returns a snapshot of the capacity left after
+    /// accommodating them.
+    pub fn check_n_with_info(
+        &mut self,
+        n: u32,
+    ) -> Result<StateSnapshot<C::Instant>, MultiDecision<A, C>> {
+        let at = self.clock.now();
+        self.check_n_with_info_at(n, at)
+    }
+
+    /// Tests if `n` cells can be accommodated at the given time
+    /// stamp, returning a snapshot of the capacity left if so. See
+    /// [`check_n_with_info`](#method.check_n_with_info).
+    pub fn check_n_with_info_at(
+        &mut self,
+        n: u32,
+        at: C::Instant,
+    ) -> Result<StateSnapshot<C::Instant>, MultiDecision<A, C>> {
+        self.algorithm
+            .test_n_and_update_with_info(&self.state, n, at)
+    }
+
+    /// Lets through as many of `n` cells as can be accommodated at
+    /// the clock's current reading, and updates the rate limiter
+    /// state to account for them.
+    ///
+    /// Returns the number of cells that were let through. If that is
+    /// fewer than `n`, the second element holds the rate limiter's
+    /// negative outcome for the remaining cells. Unlike
+    /// [`check_n`](#method.check_n), batches larger than the bucket
+    /// capacity are let through up to that capacity.
+    ///
+    /// # Example
+    /// ``` rust
+    /// # use ratelimit_meter::{DirectRateLimiter, LeakyBucket};
+    /// # #[macro_use] extern crate nonzero_ext;
+    /// # extern crate ratelimit_meter;
+    /// # #[cfg(feature = "std")]
+    /// # fn main () {
+    /// let mut lim = DirectRateLimiter::<LeakyBucket>::per_second(nonzero!(10u32));
+    /// let (accepted, rest) = lim.check_up_to_n(15);
+    /// assert_eq!(10, accepted);
+    /// assert!(rest.is_some());
+    /// # }
+    /// # #[cfg(not(feature = "std"))] fn main() {}
+    /// ```
+    pub fn check_up_to_n(
+        &mut self,
+        n: u32,
+    ) -> (u32, Option<<A as Algorithm<C::Instant>>::NegativeDecision>) {
+        let at = self.clock.now();
+        self.check_up_to_n_at(n, at)
+    }
+
+    /// Lets through as many of `n` cells as can be accommodated at
+    /// the given time stamp. See
+    /// [`check_up_to_n`](#method.check_up_to_n).
+    pub fn check_up_to_n_at(
+        &mut self,
+        n: u32,
+        at: C::Instant,
+    ) -> (u32, Option<<A as Algorithm<C::Instant>>::NegativeDecision>) {
+        self.algorithm.test_up_to_n_and_update(&self.state, n, at)
+    }
+
+    /// Tests if a single cell could be accommodated at the clock's
+    /// current reading, without using up any capacity.
+    ///
+    /// The result is the same that [`check`](#method.check) would
+    /// return, but the rate limiter state is left unchanged.
+    ///
+    /// # Example
+    /// ``` rust
+    /// # use ratelimit_meter::{DirectRateLimiter, GCRA};
+    /// # #[macro_use] extern crate nonzero_ext;
+    /// # extern crate ratelimit_meter;
+    /// # #[cfg(feature = "std")]
+    /// # fn main () {
+    /// let mut lim = DirectRateLimiter::<GCRA>::per_second(nonzero!(1u32));
+    /// assert_eq!(Ok(()), lim.peek());
+    /// assert_eq!(Ok(()), lim.peek());
+    /// assert_eq!(Ok(()), lim.check());
+    /// # }
+    /// # #[cfg(not(feature = "std"))] fn main() {}
+    /// ```
+    pub fn peek(&self) -> Result<(), <A as Algorithm<C::Instant>>::NegativeDecision> {
+        self.peek_at(self.clock.now())
+    }
+
+    /// Tests if a single cell could be accommodated at the given time
+    /// stamp, without using up any capacity. See [`peek`](#method.peek).
+    pub fn peek_at(
+        &self,
+        at: C::Instant,
+    ) -> Result<(), <A as Algorithm<C::Instant>>::NegativeDecision> {
+        self.algorithm.test(&self.state, at)
+    }
+
+    /// Tests if `n` cells could be accommodated at the clock's
+    /// current reading, without using up any capacity.
+    ///
+    /// The result is the same that [`check_n`](#method.check_n)
+    /// would return, but the rate limiter state is left unchanged.
+    pub fn peek_n(
+        &self,
+        n: u32,
+    ) -> Result<(), NegativeMultiDecision<<A as Algorithm<C::Instant>>::NegativeDecision>> {
+        self.peek_n_at(n, self.clock.now())
+    }
+
+    /// Tests if `n` cells could be accommodated at the given time
+    /// stamp, without using up any capacity. See
+    /// [`peek_n`](#method.peek_n).
+    pub fn peek_n_at(
+        &self,
+        n: u32,
+        at: C::Instant,
+    ) -> Result<(), NegativeMultiDecision<<A as Algorithm<C::Instant>>::NegativeDecision>> {
+        self.algorithm.test_n(&self.state, n, at)
+    }
+
+    /// Returns a snapshot of the rate limiter's remaining capacity at
+    /// the clock's current reading, e.g. for reporting rate limit
+    /// headers. The rate limiter state is left unchanged.
+    ///
+    /// # Example
+    /// ``` rust
+    /// # use ratelimit_meter::{DirectRateLimiter, LeakyBucket};
+    /// # #[macro_use] extern crate nonzero_ext;
+    /// # extern crate ratelimit_meter;
+    /// # #[cfg(feature = "std")]
+    /// # fn main () {
+    /// let mut lim = DirectRateLimiter::<LeakyBucket>::per_second(nonzero!(10u32));
+    /// lim.check_n(3).unwrap();
+    /// let snapshot = lim.snapshot();
+    /// assert_eq!(10, snapshot.limit());
+    /// assert_eq!(7, snapshot.remaining());
+    /// # }
+    /// # #[cfg(not(feature = "std"))] fn main() {}
+    /// ```
+    pub fn snapshot(&self) -> StateSnapshot<C::Instant> {
+        self.snapshot_at(self.clock.now())
+    }
+
+    /// Returns a snapshot of the rate limiter's remaining capacity at
+    /// the given time stamp. See [`snapshot`](#method.snapshot).
+    pub fn snapshot_at(&self, at: C::Instant) -> StateSnapshot<C::Instant> {
+        self.algorithm.snapshot(&self.state, at)
+    }
+
+    /// Tests if a single cell can be accommodated at the clock's
+    /// current reading, and acquires a slot for it from the
+    /// `concurrency` limiter. The cell is only let through (and only
+    /// charged against the rate limit) if both limits allow it.
+    ///
+    /// The returned guard gives the slot back when it is dropped.
+    pub fn check_and_acquire(
+        &mut self,
+        concurrency: &ConcurrencyLimiter,
+    ) -> Result<
+        ConcurrencyGuard,
+        NegativeConcurrencyDecision<<A as Algorithm<C::Instant>>::NegativeDecision>,
+    > {
+        let at = self.clock.now();
+        self.check_and_acquire_at(concurrency, at)
+    }
+
+    /// Tests whether a single cell can be accommodated at the given
+    /// time stamp, and acquires a concurrency slot for it. See
+    /// [`check_and_acquire`](#method.check_and_acquire).
+    pub fn check_and_acquire_at(
+        &mut self,
+        concurrency: &ConcurrencyLimiter,
+        at: C::Instant,
+    ) -> Result<
+        ConcurrencyGuard,
+        NegativeConcurrencyDecision<<A as Algorithm<C::Instant>>::NegativeDecision>,
+    > {
+        // Acquire the slot first: If the rate limit denies the cell,
+        // dropping the guard gives the slot back.
+        let guard = concurrency
+            .try_acquire()
+            .map_err(NegativeConcurrencyDecision::TooManyInFlight)?;
+        self.check_at(at)
+            .map_err(NegativeConcurrencyDecision::NonConforming)?;
+        Ok(guard)
+    }
+
+    /// Charges `n` cells against the rate limit at the clock's current
+    /// reading, whether or not they conform. This is meant for work
+    /// whose cost is only known after it was done, e.g. the size of a
+    /// response.
+    ///
+    /// Charging can take the rate limiter past its capacity, up to the
+    /// debt ceiling (see [`Builder::debt_ceiling`](struct.Builder.html#method.debt_ceiling)):
+    /// Subsequent checks fail until the debt has been paid off.
+    ///
+    /// # Example
+    /// ``` rust
+    /// # use ratelimit_meter::{DirectRateLimiter, GCRA};
+    /// # #[macro_use] extern crate nonzero_ext;
+    /// # extern crate ratelimit_meter;
+    /// # #[cfg(feature = "std")]
+    /// # fn main () {
+    /// let mut lim = DirectRateLimiter::<GCRA>::build_with_capacity(nonzero!(10u32))
+    ///     .debt_ceiling(20)
+    ///     .build()
+    ///     .unwrap();
+    /// assert_eq!(Ok(()), lim.check());
+    /// // The request turned out to cost 25 cells:
+    /// lim.charge_n(25);
+    /// assert!(lim.check().is_err());
+    /// # }
+    /// # #[cfg(not(feature = "std"))] fn main() {}
+    /// ```
+    pub fn charge_n(&mut self, n: u32) {
+        let at = self.clock.now();
+        self.charge_n_at(n, at)
+    }
+
+    /// Charges `n` cells against the rate limit at the given time
+    /// stamp. See [`charge_n`](#method.charge_n).
+    pub fn charge_n_at(&mut self, n: u32, at: C::Instant) {
+        self.algorithm
+            .charge_n(&self.state, n, self.debt_ceiling, at)
+    }
+
+    /// Gives the capacity of `n` cells that were let through earlier
+    /// back to the rate limiter, at the clock's current reading. This
+    /// is meant for cells whose work was cancelled before it started.
+    ///
+    /// A refund never leaves the rate limiter with more capacity than
+    /// it holds when it is empty.
+    pub fn refund_n(&mut self, n: u32) {
+        let at = self.clock.now();
+        self.refund_n_at(n, at)
+    }
+
+    /// Gives the capacity of `n` cells back to the rate limiter at
+    /// the given time stamp. See [`refund_n`](#method.refund_n).
+    pub fn refund_n_at(&mut self, n: u32, at: C::Instant) {
+        self.algorithm.refund_n(&self.state, n, at)
+    }
+}
+
+impl<A, C> DirectRateLimiter<A, C>
+where
+    C: clock::Clock,
+    A: Algorithm<C::Instant> + Clone,
+    A::BucketState: Clone,
+    A::NegativeDecision: NonConformance<C::Instant>,
+{
+    /// Reserves capacity for `n` cells at the earliest instant from
+    /// the clock's current reading onwards at which they conform, and
+    /// returns a [`Reservation`](../reservation/struct.Reservation.html)
+    /// holding that instant.
+    ///
+    /// The cells are accounted for right away. If the reservation is
+    /// dropped before its start, the capacity is given back.
+    ///
+    /// If `n` exceeds the bucket capacity, returns
+    /// [`NegativeMultiDecision::InsufficientCapacity`](../../enum.NegativeMultiDecision.html#variant.InsufficientCapacity).
+    ///
+    /// # Example
+    /// ``` rust
+    /// # use ratelimit_meter::{DirectRateLimiter, GCRA};
+    /// # use std::time::Duration;
+    /// # #[macro_use] extern crate nonzero_ext;
+    /// # extern crate ratelimit_meter;
+    /// # #[cfg(feature = "std")]
+    /// # fn main () {
+    /// let mut lim = DirectRateLimiter::<GCRA>::per_second(nonzero!(2u32));
+    /// lim.check_n(2).unwrap();
+    /// let reservation = lim.reserve_n(1).unwrap();
+    /// assert!(reservation.wait_time() > Duration::from_millis(0));
+    /// // ...wait until the reservation starts, then do the work:
+    /// reservation.confirm();
+    /// # }
+    /// # #[cfg(not(feature = "std"))] fn main() {}
+    /// ```
+    pub fn reserve_n(&mut self, n: u32) -> Result<Reservation<A, C>, MultiDecision<A, C>> {
+        let at = self.clock.now();
+        self.reserve_n_at(n, at)
+    }
+
+    /// Reserves capacity for `n` cells at the earliest instant no
+    /// earlier than the given time stamp at which they conform. See
+    /// [`reserve_n`](#method.reserve_n).
+    pub fn reserve_n_at(
+        &mut self,
+        n: u32,
+        at: C::Instant,
+    ) -> Result<Reservation<A, C>, MultiDecision<A, C>> {
+        let start = self.algorithm.reserve_n(&self.state, n, at)?;
+        Ok(Reservation::new(
+            self.algorithm.clone(),
+            self.state.clone(),
+            self.clock.clone(),
+            n,
+            start,
+        ))
+    }
+}
+
+impl<C> DirectRateLimiter<GCRA<C::Instant>, C>
+where
+    C: clock::Clock,
+{
+    /// Schedules a batch of `n` cells for sending, starting at the
+    /// clock's current reading, and returns the instants (spaced
+    /// evenly apart) at which each of them may be sent. See
+    /// [`GCRA::schedule_n`](../../algorithms/gcra/struct.GCRA.html#method.schedule_n).
+    pub fn schedule_n(&mut self, n: u32) -> Schedule<C::Instant> {
+        let at = self.clock.now();
+        self.schedule_n_at(n, at)
+    }
+
+    /// Schedules a batch of `n` cells for sending, starting no
+    /// earlier than the given time stamp. See
+    /// [`schedule_n`](#method.schedule_n).
+    pub fn schedule_n_at(&mut self, n: u32, at: C::Instant) -> Schedule<C::Instant> {
+        self.algorithm.schedule_n(&self.state, n, at)
+    }
 }
 
 /// An object that allows incrementally constructing rate Limiter
@@ -173,6 +584,8 @@ where
     capacity: NonZeroU32,
     cell_weight: NonZeroU32,
     time_unit: Duration,
+    burst: Option<NonZeroU32>,
+    debt_ceiling: u32,
     end_result: PhantomData<A>,
     clock: C,
 }
@@ -204,6 +617,28 @@ where
         self
     }
 
+    /// Sets the largest burst of cell weight that the bucket
+    /// accommodates at once, independently of the sustained rate of
+    /// `capacity` per time unit.
+    ///
+    /// If no burst size is set, it is the same as the capacity. See
+    /// [`Algorithm::construct_with_burst`](../../algorithms/trait.Algorithm.html#method.construct_with_burst).
+    pub fn burst(&mut self, burst: NonZeroU32) -> &mut Builder<C, A> {
+        self.burst = Some(burst);
+        self
+    }
+
+    /// Sets the number of cells beyond its capacity that the rate
+    /// limiter can go into debt by, when cells are charged to it with
+    /// [`charge_n`](struct.DirectRateLimiter.html#method.charge_n).
+    ///
+    /// The default is 0, i.e. charging at most fills the rate limiter
+    /// up.
+    pub fn debt_ceiling(&mut self, cells: u32) -> &mut Builder<C, A> {
+        self.debt_ceiling = cells;
+        self
+    }
+
     /// Sets the clock used by the bucket.
     pub fn using_clock(&mut self, clock: C) -> &mut Builder<C, A> {
         self.clock = clock;
@@ -212,14 +647,46 @@ where
 
     /// Builds a rate limiter of the specified type.
     pub fn build(&self) -> Result<DirectRateLimiter<A, C>, InconsistentCapacity> {
-        Ok(DirectRateLimiter {
-            state: <A as Algorithm<C::Instant>>::BucketState::default(),
-            algorithm: <A as Algorithm<C::Instant>>::construct(
+        let algorithm = match self.burst {
+            Some(burst) => <A as Algorithm<C::Instant>>::construct_with_burst(
                 self.capacity,
                 self.cell_weight,
                 self.time_unit,
+                burst,
             )?,
+            None => <A as Algorithm<C::Instant>>::construct(
+                self.capacity,
+                self.cell_weight,
+                self.time_unit,
+            )?,
+        };
+        Ok(DirectRateLimiter {
+            state: <A as Algorithm<C::Instant>>::BucketState::default(),
+            algorithm,
+            debt_ceiling: self.debt_ceiling,
             clock: self.clock.clone(),
         })
     }
 }
+
+impl<A, C> DirectRateLimiter<Reconfigurable<A, C::Instant>, C>
+where
+    A: Algorithm<C::Instant>,
+    C: clock::Clock,
+{
+    /// Changes the rate limiter to let through `capacity` cells,
+    /// weighing `cell_weight`, every `per_time_unit`, keeping track of
+    /// the cells it let through so far. See
+    /// [`Reconfigurable`](../../algorithms/reconfigurable/struct.Reconfigurable.html).
+    ///
+    /// Clones of the rate limiter are reconfigured along with it.
+    pub fn reconfigure(
+        &self,
+        capacity: NonZeroU32,
+        cell_weight: NonZeroU32,
+        per_time_unit: Duration,
+    ) -> Result<(), InconsistentCapacity> {
+        self.algorithm
+            .reconfigure(capacity, cell_weight, per_time_unit)
+    }
+}
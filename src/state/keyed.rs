@@ -8,15 +8,25 @@ use evmap::{self, ReadHandle, WriteHandle};
 use parking_lot::Mutex;
 
 use crate::{
-    algorithms::{Algorithm, DefaultAlgorithm, KeyableRateLimitState, RateLimitState},
+    algorithms::{
+        from_nanos, gcra::Schedule, Algorithm, DefaultAlgorithm, Exportable, ExportedState,
+        KeyableRateLimitState, RateLimitState, Reconfigurable, StateSnapshot, WallClockAnchor,
+        GCRA,
+    },
     clock,
     clock::Reference,
-    InconsistentCapacity, NegativeMultiDecision,
+    state::concurrency::{ConcurrencyGuard, KeyedConcurrencyLimiter, NegativeConcurrencyDecision},
+    state::reservation::Reservation,
+    InconsistentCapacity, NegativeMultiDecision, NonConformance, Quota,
 };
 
 type MapWriteHandle<K, C, A, H> =
     Arc<Mutex<WriteHandle<K, <A as Algorithm<<C as clock::Clock>::Instant>>::BucketState, (), H>>>;
 
+/// The negative outcome of a decision on a batch of cells.
+type MultiDecision<A, C> =
+    NegativeMultiDecision<<A as Algorithm<<C as clock::Clock>::Instant>>::NegativeDecision>;
+
 /// An in-memory rate limiter that regulates a single rate limit for
 /// multiple keys.
 ///
@@ -79,6 +89,7 @@ pub struct KeyedRateLimiter<
     A::BucketState: KeyableRateLimitState<A, C::Instant>,
 {
     algorithm: A,
+    debt_ceiling: u32,
     map_reader: ReadHandle<K, A::BucketState, (), H>,
     map_writer: MapWriteHandle<K, C, A, H>,
     clock: C,
@@ -128,12 +139,40 @@ where
                 per_time_unit,
             )
             .unwrap(),
+            debt_ceiling: 0,
+            map_reader: r,
+            map_writer: Arc::new(Mutex::new(w)),
+            clock: Default::default(),
+        }
+    }
+
+    /// Construct a new keyed rate limiter from an already-constructed
+    /// algorithm, e.g. a [`Composite`](../../algorithms/composite/struct.Composite.html)
+    /// of several bandwidths.
+    pub fn with_algorithm(algorithm: A) -> Self {
+        let (r, mut w): (
+            ReadHandle<K, A::BucketState>,
+            WriteHandle<K, A::BucketState>,
+        ) = evmap::new();
+        w.refresh();
+        KeyedRateLimiter {
+            algorithm,
+            debt_ceiling: 0,
             map_reader: r,
             map_writer: Arc::new(Mutex::new(w)),
             clock: Default::default(),
         }
     }
 
+    /// Construct a new keyed rate limiter with the parameters of a
+    /// [`Quota`](../../quota/struct.Quota.html).
+    ///
+    /// Returns an error if the algorithm can't be constructed with
+    /// them.
+    pub fn with_quota(quota: Quota) -> Result<Self, InconsistentCapacity> {
+        Ok(Self::with_algorithm(quota.construct()?))
+    }
+
     /// Returns the number of non-empty keys present in the map.
     pub fn len(&self) -> usize {
         self.map_reader.len()
@@ -173,9 +212,22 @@ where
         }
     }
 
-    fn check_and_update_key<E, F>(&self, key: K, update: F) -> Result<(), E>
+    /// Return a constructor that can be used to construct a keyed
+    /// rate limiter with the parameters of a
+    /// [`Quota`](../../quota/struct.Quota.html).
+    pub fn build_with_quota(quota: Quota) -> Builder<K, C, A, RandomState> {
+        Builder {
+            capacity: quota.capacity(),
+            cell_weight: quota.cell_weight(),
+            per_time_unit: quota.per_time_unit(),
+            burst: quota.burst(),
+            ..Default::default()
+        }
+    }
+
+    fn check_and_update_key<T, F>(&self, key: K, update: F) -> T
     where
-        F: Fn(&A::BucketState) -> Result<(), E>,
+        F: Fn(&A::BucketState) -> T,
     {
         self.map_reader
             .get_and(&key, |v| {
@@ -196,6 +248,17 @@ where
             })
     }
 
+    /// Calls `test` with the key's state, or with a fresh state if the
+    /// key isn't present, without adding the key to the map.
+    fn test_key<T, F>(&self, key: &K, test: F) -> T
+    where
+        F: Fn(&A::BucketState) -> T,
+    {
+        self.map_reader
+            .get_and(key, |v| test(&v[0]))
+            .unwrap_or_else(|| test(&Default::default()))
+    }
+
     /// Tests if a single cell for the given key can be accommodated
     /// at `Instant::now()`. If it can be, `check` updates the rate
     /// limiter state on that key to account for the conforming cell
@@ -256,6 +319,232 @@ where
         self.check_and_update_key(key, |state| self.algorithm.test_n_and_update(state, n, at))
     }
 
+    /// Tests if a single cell for the given key can be accommodated
+    /// at the clock's current reading, like [`check`](#method.check)
+    /// does. If it can be, returns a snapshot of the capacity left
+    /// for the key after accommodating it, e.g. for reporting rate
+    /// limit headers on the allowed response.
+    pub fn check_with_info(
+        &mut self,
+        key: K,
+    ) -> Result<StateSnapshot<C::Instant>, <A as Algorithm<C::Instant>>::NegativeDecision> {
+        self.check_with_info_at(key, self.clock.now())
+    }
+
+    /// Tests if a single cell for the given key can be accommodated
+    /// at the given time stamp, returning a snapshot of the capacity
+    /// left if so. See [`check_with_info`](#method.check_with_info).
+    pub fn check_with_info_at(
+        &mut self,
+        key: K,
+        at: C::Instant,
+    ) -> Result<StateSnapshot<C::Instant>, <A as Algorithm<C::Instant>>::NegativeDecision> {
+        self.check_and_update_key(key, |state| {
+            self.algorithm.test_and_update_with_info(state, at)
+        })
+    }
+
+    /// Tests if `n` cells for the given key can be accommodated at
+    /// the clock's current reading, like [`check_n`](#method.check_n)
+    /// does. If they can be, returns a snapshot of the capacity left
+    /// for the key after accommodating them.
+    pub fn check_n_with_info(
+        &mut self,
+        key: K,
+        n: u32,
+    ) -> Result<StateSnapshot<C::Instant>, MultiDecision<A, C>> {
+        self.check_n_with_info_at(key, n, self.clock.now())
+    }
+
+    /// Tests if `n` cells for the given key can be accommodated at
+    /// the given time stamp, returning a snapshot of the capacity left
+    /// if so. See [`check_n_with_info`](#method.check_n_with_info).
+    pub fn check_n_with_info_at(
+        &mut self,
+        key: K,
+        n: u32,
+        at: C::Instant,
+    ) -> Result<StateSnapshot<C::Instant>, MultiDecision<A, C>> {
+        self.check_and_update_key(key, |state| {
+            self.algorithm.test_n_and_update_with_info(state, n, at)
+        })
+    }
+
+    /// Lets through as many of `n` cells for the given key as can be
+    /// accommodated at the clock's current reading, and updates the
+    /// key's rate limiting state to account for them.
+    ///
+    /// Returns the number of cells that were let through. If that is
+    /// fewer than `n`, the second element holds the rate limiter's
+    /// negative outcome for the remaining cells. Unlike
+    /// [`check_n`](#method.check_n), batches larger than the bucket
+    /// capacity are let through up to that capacity.
+    pub fn check_up_to_n(
+        &mut self,
+        key: K,
+        n: u32,
+    ) -> (u32, Option<<A as Algorithm<C::Instant>>::NegativeDecision>) {
+        self.check_up_to_n_at(key, n, self.clock.now())
+    }
+
+    /// Lets through as many of `n` cells for the given key as can be
+    /// accommodated at the given time stamp. See
+    /// [`check_up_to_n`](#method.check_up_to_n).
+    pub fn check_up_to_n_at(
+        &mut self,
+        key: K,
+        n: u32,
+        at: C::Instant,
+    ) -> (u32, Option<<A as Algorithm<C::Instant>>::NegativeDecision>) {
+        self.check_and_update_key(key, |state| {
+            self.algorithm.test_up_to_n_and_update(state, n, at)
+        })
+    }
+
+    /// Tests if a single cell for the given key could be accommodated
+    /// at the clock's current reading, without using up any capacity.
+    ///
+    /// The result is the same that [`check`](#method.check) would
+    /// return, but the key's rate limiter state is left unchanged. Keys
+    /// that aren't present yet are not added.
+    pub fn peek(&self, key: &K) -> Result<(), <A as Algorithm<C::Instant>>::NegativeDecision> {
+        self.peek_at(key, self.clock.now())
+    }
+
+    /// Tests if a single cell for the given key could be accommodated
+    /// at the given time stamp, without using up any capacity. See
+    /// [`peek`](#method.peek).
+    pub fn peek_at(
+        &self,
+        key: &K,
+        at: C::Instant,
+    ) -> Result<(), <A as Algorithm<C::Instant>>::NegativeDecision> {
+        self.test_key(key, |state| self.algorithm.test(state, at))
+    }
+
+    /// Tests if `n` cells for the given key could be accommodated at
+    /// the clock's current reading, without using up any capacity.
+    ///
+    /// The result is the same that [`check_n`](#method.check_n)
+    /// would return, but the key's rate limiter state is left
+    /// unchanged. Keys that aren't present yet are not added.
+    pub fn peek_n(
+        &self,
+        key: &K,
+        n: u32,
+    ) -> Result<(), NegativeMultiDecision<<A as Algorithm<C::Instant>>::NegativeDecision>> {
+        self.peek_n_at(key, n, self.clock.now())
+    }
+
+    /// Tests if `n` cells for the given key could be accommodated at
+    /// the given time stamp, without using up any capacity. See
+    /// [`peek_n`](#method.peek_n).
+    pub fn peek_n_at(
+        &self,
+        key: &K,
+        n: u32,
+        at: C::Instant,
+    ) -> Result<(), NegativeMultiDecision<<A as Algorithm<C::Instant>>::NegativeDecision>> {
+        self.test_key(key, |state| self.algorithm.test_n(state, n, at))
+    }
+
+    /// Returns a snapshot of the given key's remaining capacity at
+    /// the clock's current reading, e.g. for reporting rate limit
+    /// headers. The key's rate limiter state is left unchanged, and
+    /// keys that aren't present yet are not added.
+    pub fn snapshot(&self, key: &K) -> StateSnapshot<C::Instant> {
+        self.snapshot_at(key, self.clock.now())
+    }
+
+    /// Returns a snapshot of the given key's remaining capacity at the
+    /// given time stamp. See [`snapshot`](#method.snapshot).
+    pub fn snapshot_at(&self, key: &K, at: C::Instant) -> StateSnapshot<C::Instant> {
+        self.test_key(key, |state| self.algorithm.snapshot(state, at))
+    }
+
+    /// Tests if a single cell for the given key can be accommodated
+    /// at the clock's current reading, and acquires a slot for it
+    /// from the `concurrency` limiter under the same key. The cell is
+    /// only let through (and only charged against the rate limit) if
+    /// both limits allow it.
+    ///
+    /// The returned guard gives the slot back when it is dropped.
+    pub fn check_and_acquire<H2: BuildHasher>(
+        &mut self,
+        key: K,
+        concurrency: &KeyedConcurrencyLimiter<K, H2>,
+    ) -> Result<
+        ConcurrencyGuard,
+        NegativeConcurrencyDecision<<A as Algorithm<C::Instant>>::NegativeDecision>,
+    > {
+        let at = self.clock.now();
+        self.check_and_acquire_at(key, concurrency, at)
+    }
+
+    /// Tests whether a single cell for the given key can be
+    /// accommodated at the given time stamp, and acquires a
+    /// concurrency slot for it. See
+    /// [`check_and_acquire`](#method.check_and_acquire).
+    pub fn check_and_acquire_at<H2: BuildHasher>(
+        &mut self,
+        key: K,
+        concurrency: &KeyedConcurrencyLimiter<K, H2>,
+        at: C::Instant,
+    ) -> Result<
+        ConcurrencyGuard,
+        NegativeConcurrencyDecision<<A as Algorithm<C::Instant>>::NegativeDecision>,
+    > {
+        // Acquire the slot first: If the rate limit denies the cell,
+        // dropping the guard gives the slot back.
+        let guard = concurrency
+            .try_acquire(key.clone())
+            .map_err(NegativeConcurrencyDecision::TooManyInFlight)?;
+        self.check_at(key, at)
+            .map_err(NegativeConcurrencyDecision::NonConforming)?;
+        Ok(guard)
+    }
+
+    /// Charges `n` cells for the given key at the clock's current
+    /// reading, whether or not they conform. This is meant for work
+    /// whose cost is only known after it was done, e.g. the size of a
+    /// response.
+    ///
+    /// Charging can take the key's rate limiter past its capacity, up
+    /// to the debt ceiling (see [`Builder::with_debt_ceiling`](struct.Builder.html#method.with_debt_ceiling)):
+    /// Subsequent checks for the key fail until the debt has been
+    /// paid off.
+    pub fn charge_n(&mut self, key: K, n: u32) {
+        self.charge_n_at(key, n, self.clock.now())
+    }
+
+    /// Charges `n` cells for the given key at the given time stamp.
+    /// See [`charge_n`](#method.charge_n).
+    pub fn charge_n_at(&mut self, key: K, n: u32, at: C::Instant) {
+        self.check_and_update_key(key, |state| {
+            self.algorithm.charge_n(state, n, self.debt_ceiling, at)
+        })
+    }
+
+    /// Gives the capacity of `n` cells that were let through earlier
+    /// for the given key back to its rate limiter, at the clock's
+    /// current reading. This is meant for cells whose work was
+    /// cancelled before it started.
+    ///
+    /// A refund never leaves the key's rate limiter with more capacity
+    /// than it holds when it is empty. Refunding cells for a key that
+    /// isn't present has no effect.
+    pub fn refund_n(&mut self, key: K, n: u32) {
+        self.refund_n_at(key, n, self.clock.now())
+    }
+
+    /// Gives the capacity of `n` cells back to the given key's rate
+    /// limiter at the given time stamp. See
+    /// [`refund_n`](#method.refund_n).
+    pub fn refund_n_at(&mut self, key: K, n: u32, at: C::Instant) {
+        self.map_reader
+            .get_and(&key, |v| self.algorithm.refund_n(&v[0], n, at));
+    }
+
     /// Removes the keys from this rate limiter that can be expired
     /// safely and returns the keys that were removed.
     ///
@@ -299,7 +588,7 @@ where
 
         let mut expireable: Vec<K> = vec![];
         self.map_reader.for_each(|k, v| {
-            if let Some(state) = v.get(0) {
+            if let Some(state) = v.first() {
                 if state
                     .last_touched(params)
                     .unwrap_or_else(|| self.clock.now())
@@ -321,6 +610,232 @@ where
     }
 }
 
+impl<C, A, K> KeyedRateLimiter<K, A, C>
+where
+    C: clock::Clock,
+    A: Algorithm<C::Instant> + Clone,
+    A::BucketState: KeyableRateLimitState<A, C::Instant> + Clone,
+    A::NegativeDecision: NonConformance<C::Instant>,
+    K: Eq + Hash + Clone,
+{
+    /// Reserves capacity for `n` cells for the given key, at the
+    /// earliest instant from the clock's current reading onwards at
+    /// which they conform, and returns a
+    /// [`Reservation`](../reservation/struct.Reservation.html) holding
+    /// that instant.
+    ///
+    /// The cells are accounted for right away. If the reservation is
+    /// dropped before its start, the capacity is given back to the
+    /// key's rate limiter.
+    pub fn reserve_n(&mut self, key: K, n: u32) -> Result<Reservation<A, C>, MultiDecision<A, C>> {
+        self.reserve_n_at(key, n, self.clock.now())
+    }
+
+    /// Reserves capacity for `n` cells for the given key, at the
+    /// earliest instant no earlier than the given time stamp at which
+    /// they conform. See [`reserve_n`](#method.reserve_n).
+    pub fn reserve_n_at(
+        &mut self,
+        key: K,
+        n: u32,
+        at: C::Instant,
+    ) -> Result<Reservation<A, C>, MultiDecision<A, C>> {
+        let (state, start) = self.check_and_update_key(key, |state| {
+            let start = self.algorithm.reserve_n(state, n, at)?;
+            Ok((state.clone(), start))
+        })?;
+        Ok(Reservation::new(
+            self.algorithm.clone(),
+            state,
+            self.clock.clone(),
+            n,
+            start,
+        ))
+    }
+}
+
+impl<C, K> KeyedRateLimiter<K, GCRA<C::Instant>, C>
+where
+    C: clock::Clock,
+    K: Eq + Hash + Clone,
+{
+    /// Schedules a batch of `n` cells for the given key, starting at
+    /// the clock's current reading, and returns the instants (spaced
+    /// evenly apart) at which each of them may be sent. See
+    /// [`GCRA::schedule_n`](../../algorithms/gcra/struct.GCRA.html#method.schedule_n).
+    pub fn schedule_n(&mut self, key: K, n: u32) -> Schedule<C::Instant> {
+        self.schedule_n_at(key, n, self.clock.now())
+    }
+
+    /// Schedules a batch of `n` cells for the given key, starting no
+    /// earlier than the given time stamp. See
+    /// [`schedule_n`](#method.schedule_n).
+    pub fn schedule_n_at(&mut self, key: K, n: u32, at: C::Instant) -> Schedule<C::Instant> {
+        self.check_and_update_key(key, |state| self.algorithm.schedule_n(state, n, at))
+    }
+}
+
+impl<C, A, K> KeyedRateLimiter<K, A, C>
+where
+    C: clock::Clock,
+    A: Exportable<C::Instant>,
+    A::BucketState: KeyableRateLimitState<A, C::Instant>,
+    K: Eq + Hash + Clone,
+{
+    /// Exports the states of all keys that haven't drained yet, so
+    /// they can be restored with [`import`](#method.import), e.g.
+    /// after a restart.
+    ///
+    /// The states are anchored to the current wall-clock time, so
+    /// they can be restored even if the rate limiter's clock (like
+    /// the [`MonotonicClock`](../../clock/struct.MonotonicClock.html))
+    /// doesn't survive a restart.
+    pub fn export(&self) -> Vec<(K, ExportedState)> {
+        self.export_at(&WallClockAnchor::now(self.clock.now()))
+    }
+
+    /// Exports the states of all keys that haven't drained yet at the
+    /// anchor's instant. See [`export`](#method.export).
+    pub fn export_at(&self, anchor: &WallClockAnchor<C::Instant>) -> Vec<(K, ExportedState)> {
+        let mut exported = vec![];
+        self.map_reader.for_each(|k, v| {
+            if let Some(state) = v.first() {
+                if let Some(state) = self.algorithm.export_state(state, anchor) {
+                    exported.push((k.clone(), state));
+                }
+            }
+        });
+        exported
+    }
+
+    /// Restores the states of keys that were exported with
+    /// [`export`](#method.export), replacing the states these keys
+    /// have in this rate limiter.
+    pub fn import<I: IntoIterator<Item = (K, ExportedState)>>(&mut self, states: I) {
+        self.import_at(states, &WallClockAnchor::now(self.clock.now()))
+    }
+
+    /// Restores the states of exported keys as of the anchor's
+    /// instant. See [`import`](#method.import).
+    pub fn import_at<I: IntoIterator<Item = (K, ExportedState)>>(
+        &mut self,
+        states: I,
+        anchor: &WallClockAnchor<C::Instant>,
+    ) {
+        let mut w = self.map_writer.lock();
+        for (key, exported) in states {
+            w.update(key, self.algorithm.import_state(&exported, anchor));
+        }
+        w.refresh();
+    }
+
+    /// Writes the [exported](#method.export) states of all keys to
+    /// `writer`, one line per key, so they can be restored with
+    /// [`load`](#method.load).
+    ///
+    /// Each line holds the wall-clock time at which the key's state
+    /// drains (in nanoseconds since the UNIX epoch), followed by a
+    /// space and the key. Returns an error of kind `InvalidInput` if a
+    /// key is displayed with a line break.
+    ///
+    /// # Example
+    /// ``` rust
+    /// # use ratelimit_meter::KeyedRateLimiter;
+    /// # #[macro_use] extern crate nonzero_ext;
+    /// # extern crate ratelimit_meter;
+    /// # fn main () {
+    /// let mut lim = KeyedRateLimiter::<String>::per_second(nonzero!(1u32));
+    /// assert_eq!(Ok(()), lim.check("foo".to_string()));
+    ///
+    /// let mut dump = vec![];
+    /// lim.dump(&mut dump).unwrap();
+    ///
+    /// // ...and after a restart:
+    /// let mut lim = KeyedRateLimiter::<String>::per_second(nonzero!(1u32));
+    /// lim.load(&dump[..]).unwrap();
+    /// assert!(lim.check("foo".to_string()).is_err());
+    /// # }
+    /// ```
+    pub fn dump<W: io::Write>(&self, mut writer: W) -> io::Result<()>
+    where
+        K: fmt::Display,
+    {
+        for (key, state) in self.export() {
+            let key = key.to_string();
+            if key.contains(['\n', '\r']) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "key contains a line break",
+                ));
+            }
+            writeln!(writer, "{} {}", state.drained_at().as_nanos(), key)?;
+        }
+        writer.flush()
+    }
+
+    /// Restores the states of the keys that were written to `reader`
+    /// with [`dump`](#method.dump). Returns an error of kind
+    /// `InvalidData` if a line can't be parsed; the keys on the lines
+    /// before it are restored nonetheless.
+    pub fn load<R: io::BufRead>(&mut self, reader: R) -> io::Result<()>
+    where
+        K: FromStr,
+    {
+        let anchor = WallClockAnchor::now(self.clock.now());
+        let invalid = |what| io::Error::new(io::ErrorKind::InvalidData, what);
+        let mut states = vec![];
+        let mut result = Ok(());
+        for line in reader.lines() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let parsed = line
+                .split_once(' ')
+                .ok_or_else(|| invalid("missing key"))
+                .and_then(|(nanos, key)| {
+                    let nanos = nanos.parse().map_err(|_| invalid("invalid time"))?;
+                    let key = key.parse().map_err(|_| invalid("invalid key"))?;
+                    Ok((key, ExportedState::new(from_nanos(nanos))))
+                });
+            match parsed {
+                Ok(state) => states.push(state),
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+        self.import_at(states, &anchor);
+        result
+    }
+}
+
+impl<C, A, K> KeyedRateLimiter<K, Reconfigurable<A, C::Instant>, C>
+where
+    C: clock::Clock,
+    A: Algorithm<C::Instant>,
+    A::BucketState: KeyableRateLimitState<A, C::Instant>,
+    K: Eq + Hash + Clone,
+{
+    /// Changes the rate limiter to let through `capacity` cells,
+    /// weighing `cell_weight`, every `per_time_unit` for each key,
+    /// keeping track of the cells that every key was let through so
+    /// far. See
+    /// [`Reconfigurable`](../../algorithms/reconfigurable/struct.Reconfigurable.html).
+    ///
+    /// Clones of the rate limiter are reconfigured along with it.
+    pub fn reconfigure(
+        &self,
+        capacity: NonZeroU32,
+        cell_weight: NonZeroU32,
+        per_time_unit: Duration,
+    ) -> Result<(), InconsistentCapacity> {
+        self.algorithm
+            .reconfigure(capacity, cell_weight, per_time_unit)
+    }
+}
+
 /// A constructor for keyed rate limiters.
 pub struct Builder<K: Eq + Hash + Clone, C: clock::Clock, A: Algorithm<C::Instant>, H: BuildHasher>
 {
@@ -329,6 +844,8 @@ pub struct Builder<K: Eq + Hash + Clone, C: clock::Clock, A: Algorithm<C::Instan
     capacity: NonZeroU32,
     cell_weight: NonZeroU32,
     per_time_unit: Duration,
+    burst: Option<NonZeroU32>,
+    debt_ceiling: u32,
     hasher: H,
     map_capacity: Option<usize>,
 }
@@ -348,6 +865,8 @@ where
             capacity: nonzero!(1u32),
             cell_weight: nonzero!(1u32),
             per_time_unit: Duration::from_secs(1),
+            burst: None,
+            debt_ceiling: 0,
             hasher: RandomState::new(),
         }
     }
@@ -370,6 +889,8 @@ where
             capacity: self.capacity,
             cell_weight: self.cell_weight,
             per_time_unit: self.per_time_unit,
+            burst: self.burst,
+            debt_ceiling: self.debt_ceiling,
             map_capacity: self.map_capacity,
         }
     }
@@ -386,6 +907,32 @@ where
         })
     }
 
+    /// Sets the largest burst of cell weight that each key's bucket
+    /// accommodates at once, independently of the sustained rate of
+    /// `capacity` per time unit.
+    ///
+    /// If no burst size is set, it is the same as the capacity. See
+    /// [`Algorithm::construct_with_burst`](../../algorithms/trait.Algorithm.html#method.construct_with_burst).
+    pub fn with_burst(self, burst: NonZeroU32) -> Self {
+        Builder {
+            burst: Some(burst),
+            ..self
+        }
+    }
+
+    /// Sets the number of cells beyond its capacity that each key's
+    /// rate limiter can go into debt by, when cells are charged to it
+    /// with [`charge_n`](struct.KeyedRateLimiter.html#method.charge_n).
+    ///
+    /// The default is 0, i.e. charging at most fills the rate limiter
+    /// up.
+    pub fn with_debt_ceiling(self, cells: u32) -> Self {
+        Builder {
+            debt_ceiling: cells,
+            ..self
+        }
+    }
+
     /// Sets the initial number of keys that the map can hold before
     /// rehashing.
     pub fn with_map_capacity(self, map_capacity: usize) -> Self {
@@ -406,21 +953,29 @@ where
         H: Clone,
     {
         let map_opts = evmap::Options::default().with_hasher(self.hasher);
-        let (r, mut w) = if self.map_capacity.is_some() {
-            map_opts
-                .with_capacity(self.map_capacity.unwrap())
-                .construct()
+        let (r, mut w) = if let Some(map_capacity) = self.map_capacity {
+            map_opts.with_capacity(map_capacity).construct()
         } else {
             map_opts.construct()
         };
 
         w.refresh();
-        Ok(KeyedRateLimiter {
-            algorithm: <A as Algorithm<C::Instant>>::construct(
+        let algorithm = match self.burst {
+            Some(burst) => <A as Algorithm<C::Instant>>::construct_with_burst(
                 self.capacity,
                 self.cell_weight,
                 self.per_time_unit,
+                burst,
             )?,
+            None => <A as Algorithm<C::Instant>>::construct(
+                self.capacity,
+                self.cell_weight,
+                self.per_time_unit,
+            )?,
+        };
+        Ok(KeyedRateLimiter {
+            algorithm,
+            debt_ceiling: self.debt_ceiling,
             clock: self.clock,
             map_reader: r,
             map_writer: Arc::new(Mutex::new(w)),
@@ -0,0 +1,119 @@
+//! Capacity that was reserved for cells ahead of time.
+
+use crate::lib::*;
+
+use crate::{algorithms::Algorithm, clock, clock::Reference};
+
+/// Capacity for a batch of cells that was reserved ahead of time, as
+/// returned by
+/// [`DirectRateLimiter::reserve_n`](../direct/struct.DirectRateLimiter.html#method.reserve_n)
+/// and
+/// [`KeyedRateLimiter::reserve_n`](../keyed/struct.KeyedRateLimiter.html#method.reserve_n).
+///
+/// The cells are accounted for in the rate limiter as soon as the
+/// reservation is made. The work they stand for may start at the
+/// instant returned by [`start`](#method.start).
+///
+/// If the reservation is dropped (or [cancelled](#method.cancel))
+/// before that instant, its capacity is given back to the rate
+/// limiter. To keep the capacity accounted for in any case, e.g. when
+/// the work was started early for reasons outside of the rate
+/// limiter's control, [`confirm`](#method.confirm) the reservation.
+#[must_use = "the capacity is given back if the reservation is dropped before it starts"]
+pub struct Reservation<A: Algorithm<C::Instant>, C: clock::Clock> {
+    algorithm: A,
+    state: A::BucketState,
+    clock: C,
+    cells: u32,
+    start: C::Instant,
+    settled: bool,
+}
+
+impl<A: Algorithm<C::Instant>, C: clock::Clock> Reservation<A, C> {
+    pub(crate) fn new(
+        algorithm: A,
+        state: A::BucketState,
+        clock: C,
+        cells: u32,
+        start: C::Instant,
+    ) -> Self {
+        Reservation {
+            algorithm,
+            state,
+            clock,
+            cells,
+            start,
+            settled: false,
+        }
+    }
+
+    /// Returns the number of cells that were reserved.
+    pub fn cells(&self) -> u32 {
+        self.cells
+    }
+
+    /// Returns the instant at which the reserved cells conform to the
+    /// rate limit.
+    pub fn start(&self) -> C::Instant {
+        self.start
+    }
+
+    /// Returns the amount of time from the clock's current reading
+    /// until the reserved cells conform to the rate limit. A zero
+    /// duration is returned if they already do.
+    pub fn wait_time(&self) -> Duration {
+        self.wait_time_from(self.clock.now())
+    }
+
+    /// Returns the amount of time from `from` until the reserved
+    /// cells conform to the rate limit. See
+    /// [`wait_time`](#method.wait_time).
+    pub fn wait_time_from(&self, from: C::Instant) -> Duration {
+        self.start.duration_since(cmp::min(self.start, from))
+    }
+
+    /// Keeps the reserved capacity accounted for, even if the
+    /// reservation's start hasn't been reached yet.
+    pub fn confirm(mut self) {
+        self.settled = true;
+    }
+
+    /// Gives the reserved capacity back to the rate limiter, if the
+    /// reservation's start hasn't been reached at the clock's current
+    /// reading. This is the same as dropping the reservation.
+    pub fn cancel(self) {}
+
+    /// Gives the reserved capacity back to the rate limiter, if the
+    /// reservation's start hasn't been reached at the given time
+    /// stamp. See [`cancel`](#method.cancel).
+    pub fn cancel_at(mut self, at: C::Instant) {
+        self.settle(at);
+    }
+
+    fn settle(&mut self, at: C::Instant) {
+        if self.settled {
+            return;
+        }
+        self.settled = true;
+        if at < self.start {
+            self.algorithm.refund_n(&self.state, self.cells, at);
+        }
+    }
+}
+
+impl<A: Algorithm<C::Instant>, C: clock::Clock> Drop for Reservation<A, C> {
+    fn drop(&mut self) {
+        let now = self.clock.now();
+        self.settle(now);
+    }
+}
+
+impl<A: Algorithm<C::Instant>, C: clock::Clock> fmt::Debug for Reservation<A, C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(
+            f,
+            "Reservation{{cells: {}, start: {:?}}}",
+            self.cells, self.start
+        )
+    }
+}
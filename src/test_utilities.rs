@@ -2,6 +2,7 @@
 //! A module for code shared between integration tests & benchmarks in this crate.
 
 pub mod algorithms;
+pub mod fuzz;
 pub mod variants;
 
 use crate::lib::*;
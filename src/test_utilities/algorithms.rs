@@ -73,6 +73,13 @@ macro_rules! bench_with_algorithm_variants {
                 >::default();
                 $code
             }
+            $crate::test_utilities::variants::Variant::AtomicGCRA => {
+                let mut $var = $crate::test_utilities::algorithms::AlgorithmForTest::<
+                    $crate::algorithms::gcra::AtomicGCRA<Instant>,
+                    $crate::clock::DefaultClock,
+                >::default();
+                $code
+            }
             $crate::test_utilities::variants::Variant::LeakyBucket => {
                 let mut $var = $crate::test_utilities::algorithms::AlgorithmForTest::<
                     $crate::LeakyBucket<Instant>,
@@ -80,6 +87,13 @@ macro_rules! bench_with_algorithm_variants {
                 >::default();
                 $code
             }
+            $crate::test_utilities::variants::Variant::AtomicLeakyBucket => {
+                let mut $var = $crate::test_utilities::algorithms::AlgorithmForTest::<
+                    $crate::algorithms::leaky_bucket::AtomicLeakyBucket<Instant>,
+                    $crate::clock::DefaultClock,
+                >::default();
+                $code
+            }
         }
     };
 }
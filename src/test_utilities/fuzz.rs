@@ -0,0 +1,95 @@
+//! Pseudo-random sequences of decisions, for checking that rate
+//! limiters behave the same no matter which cells arrive when.
+
+use crate::lib::*;
+use crate::{algorithms::Algorithm, clock, clock::Reference, state::DirectRateLimiter};
+
+/// A cheap pseudo-random number generator (a linear congruential
+/// one), so that the sequences tests check stay reproducible.
+#[derive(Debug, Clone)]
+pub struct Lcg(u32);
+
+impl Lcg {
+    pub fn new(seed: u32) -> Self {
+        Lcg(seed)
+    }
+
+    /// Returns a number below `bound`.
+    pub fn below(&mut self, bound: u32) -> u32 {
+        self.0 = self.0.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        // The low bits of a linear congruential generator cycle
+        // quickly, so only use the high ones:
+        (self.0 >> 16) % bound
+    }
+
+    /// Returns a duration below `bound`, in steps of a thousandth of
+    /// it.
+    pub fn delay(&mut self, bound: Duration) -> Duration {
+        bound * self.below(1000) / 1000
+    }
+}
+
+/// Peeks at pseudo-random batches of fewer than `max_batch` cells,
+/// at instants that are fewer than `max_delay` apart (and that
+/// occasionally look back by a little), and asserts that each peek
+/// predicts the decision that checking the same batch makes right
+/// after it.
+pub fn assert_peek_predicts_check<A, C>(
+    lim: &mut DirectRateLimiter<A, C>,
+    start: C::Instant,
+    max_batch: u32,
+    max_delay: Duration,
+) where
+    A: Algorithm<C::Instant>,
+    C: clock::Clock,
+{
+    let mut rng = Lcg::new(5);
+    let mut now = start;
+    for i in 0..2000 {
+        let n = rng.below(max_batch);
+        let offset = rng.delay(max_delay);
+        let at = if rng.below(11) == 0 {
+            cmp::max(start, now.saturating_sub(offset / 2))
+        } else {
+            now
+        };
+        let peeked = lim.peek_n_at(n, at);
+        assert_eq!(peeked, lim.peek_n_at(n, at), "at step {}", i);
+        assert_eq!(peeked, lim.check_n_at(n, at), "at step {}", i);
+        now = now + offset;
+    }
+}
+
+/// Checks pseudo-random batches of fewer than `max_batch` cells at
+/// instants that are fewer than `max_delay` apart, asking `lim` for
+/// the capacity left after each batch. Asserts that `lim` decides
+/// like `plain` (a rate limiter with the same parameters) does
+/// without reporting the capacity, and that the capacity it reports
+/// matches a snapshot taken right after.
+pub fn assert_info_matches_snapshot<A, C>(
+    lim: &mut DirectRateLimiter<A, C>,
+    plain: &mut DirectRateLimiter<A, C>,
+    start: C::Instant,
+    max_batch: u32,
+    max_delay: Duration,
+) where
+    A: Algorithm<C::Instant>,
+    C: clock::Clock,
+{
+    let mut rng = Lcg::new(9);
+    let mut now = start;
+    for i in 0..2000 {
+        let n = rng.below(max_batch);
+        let decision = lim.check_n_with_info_at(n, now);
+        assert_eq!(
+            decision.as_ref().err(),
+            plain.check_n_at(n, now).as_ref().err(),
+            "at step {}",
+            i
+        );
+        if let Ok(info) = decision {
+            assert_eq!(info, lim.snapshot_at(now), "at step {}", i);
+        }
+        now = now + rng.delay(max_delay);
+    }
+}
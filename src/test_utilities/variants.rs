@@ -5,11 +5,18 @@ use crate::state::DirectRateLimiter;
 #[derive(Debug)]
 pub enum Variant {
     GCRA,
+    AtomicGCRA,
     LeakyBucket,
+    AtomicLeakyBucket,
 }
 
 impl Variant {
-    pub const ALL: &'static [Variant; 2] = &[Variant::GCRA, Variant::LeakyBucket];
+    pub const ALL: &'static [Variant; 4] = &[
+        Variant::GCRA,
+        Variant::AtomicGCRA,
+        Variant::LeakyBucket,
+        Variant::AtomicLeakyBucket,
+    ];
 }
 
 pub struct DirectBucket<A: Algorithm<C::Instant>, C: clock::Clock>(DirectRateLimiter<A, C>);
@@ -81,6 +88,16 @@ macro_rules! bench_with_variants {
                 .limiter();
                 $code
             }
+            $crate::test_utilities::variants::Variant::AtomicGCRA => {
+                let mut $var = $bucket::<
+                    ::ratelimit_meter::algorithms::gcra::AtomicGCRA<
+                        <clock::DefaultClock as clock::Clock>::Instant,
+                    >,
+                    clock::DefaultClock,
+                >::default()
+                .limiter();
+                $code
+            }
             $crate::test_utilities::variants::Variant::LeakyBucket => {
                 let mut $var = $bucket::<
                     ::ratelimit_meter::LeakyBucket<<clock::DefaultClock as clock::Clock>::Instant>,
@@ -89,6 +106,16 @@ macro_rules! bench_with_variants {
                 .limiter();
                 $code
             }
+            $crate::test_utilities::variants::Variant::AtomicLeakyBucket => {
+                let mut $var = $bucket::<
+                    ::ratelimit_meter::algorithms::leaky_bucket::AtomicLeakyBucket<
+                        <clock::DefaultClock as clock::Clock>::Instant,
+                    >,
+                    clock::DefaultClock,
+                >::default()
+                .limiter();
+                $code
+            }
         }
     };
 }
@@ -6,6 +6,12 @@ use parking_lot::Mutex;
 #[cfg(not(feature = "std"))]
 use spin::Mutex;
 
+#[cfg(feature = "std")]
+use ::std::sync::OnceLock as Once;
+
+#[cfg(not(feature = "std"))]
+use spin::Once;
+
 #[derive(Clone)]
 /// Wraps the atomic operations on a Decider's state in a threadsafe
 /// fashion.
@@ -32,7 +38,7 @@ where
     T: fmt::Debug + Default + Clone + PartialEq + Eq,
 {
     fn eq(&self, other: &Self) -> bool {
-        if self as *const _ == other as *const _ {
+        if core::ptr::eq(self, other) {
             return true;
         }
         let mine = self.data.lock();
@@ -96,6 +102,38 @@ where
         decision
     }
 
+    #[inline]
+    /// Like [`measure_and_replace`](#method.measure_and_replace),
+    /// but hands the decision closure mutable access to the bucket
+    /// state. This is useful for states that are expensive to copy.
+    ///
+    /// The closure is expected to leave the state unchanged if it
+    /// makes a negative decision.
+    ///
+    /// # Panics
+    /// Panics if an error occurs in acquiring any locks.
+    pub(crate) fn measure_and_update<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        let mut data = self.data.lock();
+        f(&mut *data)
+    }
+
+    #[inline]
+    /// Hands the bucket state to a closure that makes a decision
+    /// without changing it.
+    ///
+    /// # Panics
+    /// Panics if an error occurs in acquiring any locks.
+    pub(crate) fn measure<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&T) -> R,
+    {
+        let data = self.data.lock();
+        f(&*data)
+    }
+
     /// Retrieves and returns a snapshot of the bucket state. This
     /// isn't thread safe, but can be used to restore an old copy of
     /// the bucket if necessary.
@@ -110,6 +148,50 @@ where
     }
 }
 
+/// A value that is set exactly once, by the first thread to get to
+/// it, and can be read without locking afterwards.
+pub(crate) struct SetOnce<T>(Once<T>);
+
+impl<T> Default for SetOnce<T> {
+    fn default() -> Self {
+        SetOnce(Once::new())
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for SetOnce<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        self.get().fmt(f)
+    }
+}
+
+impl<T> SetOnce<T> {
+    /// Returns the value, setting it to the result of `f` if no value
+    /// was set yet.
+    #[cfg(feature = "std")]
+    pub(crate) fn get_or_init<F: FnOnce() -> T>(&self, f: F) -> &T {
+        self.0.get_or_init(f)
+    }
+
+    /// Returns the value, setting it to the result of `f` if no value
+    /// was set yet.
+    #[cfg(not(feature = "std"))]
+    pub(crate) fn get_or_init<F: FnOnce() -> T>(&self, f: F) -> &T {
+        self.0.call_once(f)
+    }
+
+    /// Returns the value, if it was set.
+    #[cfg(feature = "std")]
+    pub(crate) fn get(&self) -> Option<&T> {
+        self.0.get()
+    }
+
+    /// Returns the value, if it was set.
+    #[cfg(not(feature = "std"))]
+    pub(crate) fn get(&self) -> Option<&T> {
+        self.0.r#try()
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -0,0 +1,258 @@
+extern crate ratelimit_meter;
+#[macro_use]
+extern crate nonzero_ext;
+
+use ratelimit_meter::{
+    algorithms::{gcra::AtomicGCRA, Adaptive, Algorithm},
+    test_utilities::{current_moment, fuzz::Lcg},
+    DirectRateLimiter, LeakyBucket, GCRA,
+};
+use std::thread;
+use std::time::Duration;
+
+fn adaptive_gcra(capacity: std::num::NonZeroU32) -> Adaptive<GCRA> {
+    <Adaptive<GCRA> as Algorithm>::construct(capacity, nonzero!(1u32), Duration::from_secs(1))
+        .unwrap()
+}
+
+#[test]
+fn increases_additively_up_to_ceiling() {
+    let adaptive = adaptive_gcra(nonzero!(10u32))
+        .with_bounds(nonzero!(2u32), nonzero!(14u32))
+        .unwrap()
+        .with_increase(3);
+    assert_eq!(nonzero!(10u32), adaptive.capacity());
+    adaptive.on_success();
+    assert_eq!(nonzero!(13u32), adaptive.capacity());
+    adaptive.on_success();
+    assert_eq!(nonzero!(14u32), adaptive.capacity());
+}
+
+#[test]
+fn decreases_multiplicatively_down_to_floor() {
+    let adaptive = adaptive_gcra(nonzero!(40u32))
+        .with_bounds(nonzero!(3u32), nonzero!(40u32))
+        .unwrap()
+        .with_decrease_factor(0.25)
+        .unwrap();
+    adaptive.on_backoff();
+    assert_eq!(nonzero!(10u32), adaptive.capacity());
+    adaptive.on_backoff();
+    assert_eq!(nonzero!(3u32), adaptive.capacity());
+    adaptive.on_backoff();
+    assert_eq!(nonzero!(3u32), adaptive.capacity());
+}
+
+#[test]
+fn repeated_decrease_reaches_floor() {
+    // Slight decreases still make progress on small rates:
+    let adaptive = adaptive_gcra(nonzero!(50u32))
+        .with_bounds(nonzero!(2u32), nonzero!(50u32))
+        .unwrap()
+        .with_decrease_factor(0.99)
+        .unwrap();
+    let mut previous = adaptive.capacity();
+    while previous > nonzero!(2u32) {
+        adaptive.on_backoff();
+        assert!(adaptive.capacity() < previous, "stuck at {}", previous);
+        previous = adaptive.capacity();
+    }
+    adaptive.on_backoff();
+    assert_eq!(nonzero!(2u32), adaptive.capacity());
+}
+
+#[test]
+fn extreme_decrease_factors_stay_within_bounds() {
+    let adaptive = adaptive_gcra(nonzero!(20u32))
+        .with_bounds(nonzero!(4u32), nonzero!(20u32))
+        .unwrap()
+        .with_decrease_factor(0.0)
+        .unwrap();
+    adaptive.on_backoff();
+    assert_eq!(nonzero!(4u32), adaptive.capacity());
+
+    let adaptive = adaptive_gcra(nonzero!(20u32))
+        .with_decrease_factor(1.0)
+        .unwrap();
+    for _i in 0..10 {
+        adaptive.on_backoff();
+    }
+    assert_eq!(nonzero!(20u32), adaptive.capacity());
+}
+
+#[test]
+fn feedback_from_clones_stays_within_bounds() {
+    let adaptive = adaptive_gcra(nonzero!(30u32))
+        .with_bounds(nonzero!(5u32), nonzero!(30u32))
+        .unwrap()
+        .with_increase(7);
+    let mut children = vec![];
+    for i in 0..4u32 {
+        let adaptive = adaptive.clone();
+        children.push(thread::spawn(move || {
+            let mut rng = Lcg::new(i);
+            for _j in 0..1000 {
+                if rng.below(3) == 0 {
+                    adaptive.on_backoff();
+                } else {
+                    adaptive.on_success();
+                }
+                let capacity = adaptive.capacity().get();
+                assert!((5..=30).contains(&capacity), "{}", capacity);
+            }
+        }));
+    }
+    for child in children {
+        child.join().unwrap();
+    }
+}
+
+#[test]
+fn bounds_clamp_current_rate() {
+    let adaptive = adaptive_gcra(nonzero!(40u32))
+        .with_bounds(nonzero!(1u32), nonzero!(20u32))
+        .unwrap();
+    assert_eq!(nonzero!(20u32), adaptive.capacity());
+    let err = adaptive_gcra(nonzero!(40u32))
+        .with_bounds(nonzero!(20u32), nonzero!(10u32))
+        .unwrap_err();
+    assert!(err.to_string().contains("below the floor"), "{}", err);
+}
+
+#[test]
+fn rejects_floors_the_algorithm_cannot_use() {
+    let adaptive = <Adaptive<GCRA> as Algorithm>::construct(
+        nonzero!(10u32),
+        nonzero!(2u32),
+        Duration::from_secs(1),
+    )
+    .unwrap();
+    let err = adaptive
+        .with_bounds(nonzero!(1u32), nonzero!(10u32))
+        .unwrap_err();
+    assert!(err.to_string().contains("floor"), "{}", err);
+    assert!(err.to_string().contains("weight 2"), "{}", err);
+}
+
+#[test]
+fn rejects_decrease_factors_out_of_range() {
+    for factor in [1.5, -0.5, f64::NAN].iter() {
+        let err = adaptive_gcra(nonzero!(10u32))
+            .with_decrease_factor(*factor)
+            .unwrap_err();
+        assert!(err.to_string().contains("decrease factor"), "{}", err);
+    }
+}
+
+#[test]
+fn rate_changes_apply_to_limiter() {
+    let adaptive = <Adaptive<LeakyBucket> as Algorithm>::construct(
+        nonzero!(4u32),
+        nonzero!(1u32),
+        Duration::from_secs(1),
+    )
+    .unwrap();
+    let mut lim = DirectRateLimiter::<Adaptive<LeakyBucket>>::with_algorithm(adaptive.clone());
+    let now = current_moment();
+    let ms = Duration::from_millis(1);
+    assert_eq!(Ok(()), lim.check_n_at(4, now));
+
+    adaptive.on_backoff();
+    assert_eq!(nonzero!(2u32), adaptive.capacity());
+    assert!(lim.check_n_at(3, now + ms * 2000).is_err());
+    assert_eq!(Ok(()), lim.check_n_at(2, now + ms * 2000));
+    assert!(lim.check_at(now + ms * 2100).is_err());
+    assert_eq!(Ok(()), lim.check_at(now + ms * 2500));
+
+    adaptive.on_success();
+    adaptive.on_success();
+    assert_eq!(Ok(()), lim.check_n_at(4, now + ms * 4000));
+}
+
+#[test]
+fn small_increases_apply_together() {
+    let adaptive = <Adaptive<LeakyBucket> as Algorithm>::construct(
+        nonzero!(40u32),
+        nonzero!(1u32),
+        Duration::from_secs(1),
+    )
+    .unwrap()
+    .with_bounds(nonzero!(1u32), nonzero!(100u32))
+    .unwrap();
+    let mut lim = DirectRateLimiter::<Adaptive<LeakyBucket>>::with_algorithm(adaptive.clone());
+    let now = current_moment();
+    assert_eq!(Ok(()), lim.check_n_at(40, now));
+
+    // The limiter picks up increases once they add up to an eighth of
+    // its rate:
+    for capacity in 41..45 {
+        adaptive.on_success();
+        assert_eq!(capacity, adaptive.capacity().get());
+        assert!(lim.check_at(now).is_err());
+    }
+    adaptive.on_success();
+    assert_eq!(Ok(()), lim.check_n_at(5, now));
+    assert!(lim.check_at(now).is_err());
+
+    // Decreases apply right away:
+    adaptive.on_success();
+    adaptive.on_backoff();
+    assert_eq!(nonzero!(23u32), adaptive.capacity());
+    assert_eq!(Ok(()), lim.check_n_at(22, now + Duration::from_secs(1)));
+    assert!(lim.check_n_at(2, now + Duration::from_secs(1)).is_err());
+}
+
+/// Fills a limiter at the first of `capacities`, then moves through
+/// the others, and returns how many cells got through right after
+/// each change.
+fn admitted_after_changes<A: Algorithm>(capacities: &[u32]) -> Vec<u32> {
+    let capacity = std::num::NonZeroU32::new(capacities[0]).unwrap();
+    let floor = std::num::NonZeroU32::new(*capacities.iter().min().unwrap()).unwrap();
+    let ceiling = std::num::NonZeroU32::new(*capacities.iter().max().unwrap()).unwrap();
+    let adaptive =
+        <Adaptive<A> as Algorithm>::construct(capacity, nonzero!(1u32), Duration::from_secs(1))
+            .unwrap()
+            .with_bounds(floor, ceiling)
+            .unwrap();
+    let mut lim = DirectRateLimiter::<Adaptive<A>>::with_algorithm(adaptive.clone());
+    let now = current_moment();
+    while lim.check_at(now).is_ok() {}
+
+    let mut admitted = vec![];
+    for pair in capacities.windows(2) {
+        // Jump straight to the next capacity: up in a single step,
+        // and down to the floor.
+        if pair[1] > pair[0] {
+            adaptive
+                .clone()
+                .with_increase(pair[1] - pair[0])
+                .on_success();
+        } else {
+            adaptive
+                .clone()
+                .with_decrease_factor(0.0)
+                .unwrap()
+                .on_backoff();
+        }
+        assert_eq!(pair[1], adaptive.capacity().get());
+        let mut n = 0;
+        while lim.check_at(now).is_ok() {
+            n += 1;
+        }
+        admitted.push(n);
+    }
+    admitted
+}
+
+fn rate_changes_keep_full_buckets_full_for<A: Algorithm>() {
+    assert_eq!(vec![6], admitted_after_changes::<A>(&[48, 54]));
+    assert_eq!(vec![44, 0, 0], admitted_after_changes::<A>(&[7, 51, 7, 51]));
+    assert_eq!(vec![0, 0], admitted_after_changes::<A>(&[51, 7, 51]));
+}
+
+#[test]
+fn rate_changes_keep_full_buckets_full() {
+    rate_changes_keep_full_buckets_full_for::<GCRA>();
+    rate_changes_keep_full_buckets_full_for::<LeakyBucket>();
+    rate_changes_keep_full_buckets_full_for::<AtomicGCRA>();
+}
@@ -0,0 +1,107 @@
+#![cfg(feature = "std")]
+
+extern crate ratelimit_meter;
+#[macro_use]
+extern crate nonzero_ext;
+
+use ratelimit_meter::{
+    clock::{AnchoredClock, CachedClock, Clock, Nanos, Reference},
+    DirectRateLimiter, KeyedRateLimiter, LeakyBucket, GCRA,
+};
+use std::mem::size_of;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[test]
+fn cached_clock_follows_the_monotonic_clock() {
+    let granularity = Duration::from_millis(5);
+    let clock = CachedClock::with_granularity(granularity);
+    let clone = clock.clone();
+    let first = clock.now();
+    assert!(first <= Instant::now());
+
+    thread::sleep(granularity * 10);
+    let later = clock.now();
+    assert!(later > first);
+    assert!(later <= Instant::now());
+    assert!(clone.now() >= later);
+}
+
+#[test]
+fn cached_clock_granularity_is_clamped() {
+    let clock = CachedClock::with_granularity(Duration::new(0, 0));
+    assert_eq!(CachedClock::MIN_GRANULARITY, clock.granularity());
+    let clock = CachedClock::with_granularity(Duration::from_millis(3));
+    assert_eq!(Duration::from_millis(3), clock.granularity());
+}
+
+#[test]
+fn cached_clock_in_rate_limiters() {
+    let mut lim = DirectRateLimiter::<GCRA, CachedClock>::per_second(nonzero!(5u32));
+    assert_eq!(Ok(()), lim.check_n(5));
+    assert!(lim.check().is_err());
+
+    let mut lim = KeyedRateLimiter::<&str, GCRA, CachedClock>::build_with_capacity(nonzero!(5u32))
+        .using_clock(CachedClock::with_granularity(Duration::from_millis(1)))
+        .build()
+        .unwrap();
+    assert_eq!(Ok(()), lim.check_n("foo", 5));
+    assert!(lim.check("foo").is_err());
+    assert_eq!(Ok(()), lim.check("bar"));
+}
+
+#[test]
+fn nanos_are_compact() {
+    assert_eq!(8, size_of::<Nanos>());
+    assert_eq!(8, size_of::<Option<Nanos>>());
+    assert!(size_of::<Option<Nanos>>() < size_of::<Option<Instant>>());
+}
+
+#[test]
+fn nanos_saturate() {
+    let start = Nanos::new(10);
+    assert_eq!(Nanos::new(1_000_000_010), start + Duration::from_secs(1));
+    assert_eq!(
+        Duration::from_nanos(10),
+        start.duration_since(Nanos::new(0))
+    );
+    assert_eq!(Duration::from_secs(0), Nanos::new(0).duration_since(start));
+    assert_eq!(Nanos::new(4), start.saturating_sub(Duration::from_nanos(6)));
+    assert_eq!(start, start.saturating_sub(Duration::from_nanos(11)));
+
+    assert_eq!(Nanos::MAX, Nanos::new(u64::MAX));
+    assert_eq!(Nanos::MAX, start + Duration::from_secs(u64::MAX));
+    assert_eq!(u64::MAX - 1, Nanos::MAX.as_u64());
+}
+
+#[test]
+fn anchored_clock_measures_from_its_anchor() {
+    let anchor = Instant::now();
+    let clock = AnchoredClock::with_anchor(anchor);
+    let first = clock.now();
+    assert!(clock.instant_at(first) >= anchor);
+    assert!(clock.instant_at(first) <= Instant::now());
+
+    thread::sleep(Duration::from_millis(5));
+    assert!(clock.now().duration_since(first) >= Duration::from_millis(5));
+
+    let earlier = AnchoredClock::with_anchor(anchor + Duration::from_secs(60));
+    assert_eq!(Nanos::new(0), earlier.now());
+
+    assert_eq!(
+        AnchoredClock::default().anchor(),
+        AnchoredClock::default().anchor()
+    );
+}
+
+#[test]
+fn anchored_clock_in_rate_limiters() {
+    let mut lim = DirectRateLimiter::<LeakyBucket<_>, AnchoredClock>::per_second(nonzero!(5u32));
+    assert_eq!(Ok(()), lim.check_n(5));
+    assert!(lim.check().is_err());
+
+    let mut lim = KeyedRateLimiter::<&str, GCRA<_>, AnchoredClock>::per_second(nonzero!(5u32));
+    assert_eq!(Ok(()), lim.check_n("foo", 5));
+    assert!(lim.check("foo").is_err());
+    assert_eq!(Ok(()), lim.check("bar"));
+}
@@ -0,0 +1,171 @@
+extern crate ratelimit_meter;
+#[macro_use]
+extern crate nonzero_ext;
+
+use ratelimit_meter::{
+    algorithms::Algorithm, test_utilities::current_moment, Composite, DirectRateLimiter,
+    NegativeMultiDecision, NonConformance, Reconfigurable, GCRA,
+};
+use std::time::Duration;
+
+#[test]
+fn denied_cells_charge_no_bandwidth() {
+    let algorithm = Composite::construct(nonzero!(3u32), nonzero!(1u32), Duration::from_secs(10))
+        .unwrap()
+        .and(GCRA::construct(nonzero!(1u32), nonzero!(1u32), Duration::from_secs(1)).unwrap());
+    let mut lim = DirectRateLimiter::<Composite>::with_algorithm(algorithm);
+    let now = current_moment() + Duration::from_secs(10);
+    let s = Duration::from_secs(1);
+    assert_eq!(Ok(()), lim.check_at(now));
+    assert_eq!(Ok(()), lim.check_at(now));
+
+    // The second bandwidth is exhausted, and the first one must not
+    // be charged for the denied cell:
+    assert!(lim.check_at(now).is_err());
+    assert_eq!(Ok(()), lim.check_at(now + s * 2));
+    assert_eq!(Ok(()), lim.check_at(now + s * 3));
+    assert_eq!(Ok(()), lim.check_at(now + s * 4));
+}
+
+#[test]
+fn reports_longest_wait() {
+    let algorithm = Composite::construct(nonzero!(1u32), nonzero!(1u32), Duration::from_secs(10))
+        .unwrap()
+        .and(GCRA::construct(nonzero!(1u32), nonzero!(1u32), Duration::from_secs(1)).unwrap());
+    let mut lim = DirectRateLimiter::<Composite>::with_algorithm(algorithm);
+    let now = current_moment() + Duration::from_secs(10);
+    assert_eq!(Ok(()), lim.check_at(now));
+    assert_eq!(Ok(()), lim.check_at(now));
+    match lim.check_at(now) {
+        Err(nc) => assert_eq!(Duration::from_secs(20), nc.wait_time_from(now)),
+        other => panic!("Did not expect {:?}", other),
+    }
+}
+
+#[test]
+fn never_allows_more_than_smallest_capacity() {
+    let algorithm = Composite::construct(nonzero!(3u32), nonzero!(1u32), Duration::from_secs(10))
+        .unwrap()
+        .and(GCRA::construct(nonzero!(10u32), nonzero!(1u32), Duration::from_secs(1)).unwrap());
+    let mut lim = DirectRateLimiter::<Composite>::with_algorithm(algorithm);
+    let now = current_moment() + Duration::from_secs(10);
+    assert_eq!(
+        Err(NegativeMultiDecision::InsufficientCapacity(5)),
+        lim.check_n_at(5, now)
+    );
+}
+
+#[test]
+fn snapshot_reports_most_restrictive_bandwidth() {
+    let algorithm = Composite::construct(nonzero!(4u32), nonzero!(1u32), Duration::from_secs(1))
+        .unwrap()
+        .and(GCRA::construct(nonzero!(10u32), nonzero!(1u32), Duration::from_secs(10)).unwrap());
+    let mut lim = DirectRateLimiter::<Composite>::with_algorithm(algorithm);
+    let now = current_moment();
+    let s = Duration::from_secs(1);
+    lim.check_at(now).unwrap();
+    lim.check_at(now).unwrap();
+
+    // The per-second bandwidth has less capacity left, but the
+    // per-10-seconds one takes longer to replenish:
+    let snapshot = lim.snapshot_at(now);
+    assert_eq!(
+        (4, 2, now + s * 2),
+        (snapshot.limit(), snapshot.remaining(), snapshot.reset_at())
+    );
+    let snapshot = lim.snapshot_at(now + s);
+    assert_eq!(
+        (4, 4, now + s * 2),
+        (snapshot.limit(), snapshot.remaining(), snapshot.reset_at())
+    );
+}
+
+#[test]
+fn refunds_every_bandwidth() {
+    let algorithm = Composite::construct(nonzero!(2u32), nonzero!(1u32), Duration::from_secs(1))
+        .unwrap()
+        .and(GCRA::construct(nonzero!(5u32), nonzero!(1u32), Duration::from_secs(10)).unwrap());
+    let mut lim = DirectRateLimiter::<Composite>::with_algorithm(algorithm);
+    let now = current_moment();
+    let s = Duration::from_secs(1);
+    lim.check_at(now).unwrap();
+    lim.check_at(now).unwrap();
+    lim.refund_n_at(2, now);
+
+    // Neither bandwidth remembers the refunded cells:
+    let snapshot = lim.snapshot_at(now);
+    assert_eq!((2, now), (snapshot.remaining(), snapshot.reset_at()));
+    lim.check_at(now).unwrap();
+    assert_eq!(now + s * 2, lim.snapshot_at(now).reset_at());
+}
+
+#[test]
+fn reserves_at_latest_conforming_instant() {
+    let algorithm = Composite::construct(nonzero!(1u32), nonzero!(1u32), Duration::from_secs(1))
+        .unwrap()
+        .and(GCRA::construct(nonzero!(1u32), nonzero!(1u32), Duration::from_secs(4)).unwrap());
+    let mut lim = DirectRateLimiter::<Composite>::with_algorithm(algorithm);
+    let now = current_moment();
+    let s = Duration::from_secs(1);
+    lim.check_at(now).unwrap();
+    lim.check_at(now).unwrap();
+
+    // The first bandwidth lets the cell through after 1s, the second
+    // one only after 4s:
+    let reservation = lim.reserve_n_at(1, now).unwrap();
+    assert_eq!(now + s * 4, reservation.start());
+    reservation.confirm();
+    assert!(lim.check_at(now + s * 4).is_err());
+}
+
+#[test]
+fn lets_through_as_many_as_every_bandwidth_fits() {
+    let per_second =
+        GCRA::construct(nonzero!(3u32), nonzero!(1u32), Duration::from_secs(1)).unwrap();
+    let per_10s = GCRA::construct(nonzero!(5u32), nonzero!(1u32), Duration::from_secs(10)).unwrap();
+    let mut lim = DirectRateLimiter::<Composite>::with_algorithm(
+        Composite::construct(nonzero!(3u32), nonzero!(1u32), Duration::from_secs(1))
+            .unwrap()
+            .and(per_10s.clone()),
+    );
+    let now = current_moment();
+    lim.check_at(now).unwrap();
+
+    let expected = [per_second, per_10s]
+        .iter()
+        .map(|gcra| {
+            let mut plain = DirectRateLimiter::<GCRA>::with_algorithm(gcra.clone());
+            plain.check_at(now).unwrap();
+            plain.check_up_to_n_at(10, now).0
+        })
+        .min()
+        .unwrap();
+    let (accepted, rest) = lim.check_up_to_n_at(10, now);
+    assert_eq!(expected, accepted);
+    assert!(rest.is_some());
+    assert!(lim.check_at(now).is_err());
+}
+
+#[test]
+fn replacing_rescales_every_bandwidth() {
+    let composite = |per_second, per_minute| {
+        Composite::construct(per_second, nonzero!(1u32), Duration::from_secs(1))
+            .unwrap()
+            .and(GCRA::construct(per_minute, nonzero!(1u32), Duration::from_secs(60)).unwrap())
+    };
+    let algorithm = Reconfigurable::new(composite(nonzero!(10u32), nonzero!(60u32)));
+    let mut lim = DirectRateLimiter::<Reconfigurable<Composite>>::with_algorithm(algorithm.clone());
+    let now = current_moment();
+    let ms = Duration::from_millis(1);
+    for _i in 0..5 {
+        lim.check_at(now).unwrap();
+    }
+
+    // Both bandwidths keep counting the five cells at their new rates:
+    algorithm.replace(composite(nonzero!(20u32), nonzero!(120u32)));
+    let snapshot = lim.snapshot_at(now);
+    assert_eq!(
+        (20, 15, now + ms * 2500),
+        (snapshot.limit(), snapshot.remaining(), snapshot.reset_at())
+    );
+}
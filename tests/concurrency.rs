@@ -0,0 +1,104 @@
+extern crate ratelimit_meter;
+#[macro_use]
+extern crate nonzero_ext;
+
+use ratelimit_meter::{
+    state::TooManyInFlight, test_utilities::current_moment, ConcurrencyLimiter, DirectRateLimiter,
+    NegativeConcurrencyDecision, GCRA,
+};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn releases_slots_on_drop() {
+    let lim = ConcurrencyLimiter::new(nonzero!(2u32));
+    let first = lim.try_acquire().unwrap();
+    let second = lim.try_acquire().unwrap();
+    assert_eq!(2, lim.in_flight());
+    assert_eq!(Err(TooManyInFlight(2)), lim.try_acquire().map(|_| ()));
+
+    drop(first);
+    assert_eq!(1, lim.in_flight());
+    let _third = lim.try_acquire().unwrap();
+    drop(second);
+    assert_eq!(1, lim.in_flight());
+}
+
+#[test]
+fn clones_share_slots() {
+    let lim = ConcurrencyLimiter::new(nonzero!(1u32));
+    let other = lim.clone();
+    let guard = lim.try_acquire().unwrap();
+    assert!(other.try_acquire().is_err());
+    drop(guard);
+    assert!(other.try_acquire().is_ok());
+}
+
+#[test]
+fn rate_denial_releases_slot() {
+    let concurrency = ConcurrencyLimiter::new(nonzero!(5u32));
+    let mut lim = DirectRateLimiter::<GCRA>::per_second(nonzero!(1u32));
+    let now = current_moment() + Duration::from_secs(10);
+    let _guard = lim.check_and_acquire_at(&concurrency, now).unwrap();
+    let _guard = lim.check_and_acquire_at(&concurrency, now).unwrap();
+    match lim.check_and_acquire_at(&concurrency, now) {
+        Err(NegativeConcurrencyDecision::NonConforming(_)) => {}
+        other => panic!("Did not expect {:?}", other),
+    }
+    assert_eq!(2, concurrency.in_flight());
+}
+
+#[test]
+fn concurrency_denial_does_not_charge_rate() {
+    let concurrency = ConcurrencyLimiter::new(nonzero!(1u32));
+    let mut lim = DirectRateLimiter::<GCRA>::per_second(nonzero!(1u32));
+    let now = current_moment() + Duration::from_secs(10);
+    let guard = lim.check_and_acquire_at(&concurrency, now).unwrap();
+    match lim.check_and_acquire_at(&concurrency, now) {
+        Err(NegativeConcurrencyDecision::TooManyInFlight(TooManyInFlight(1))) => {}
+        other => panic!("Did not expect {:?}", other),
+    }
+    drop(guard);
+    assert!(lim.check_and_acquire_at(&concurrency, now).is_ok());
+}
+
+#[test]
+fn never_exceeds_limit_under_contention() {
+    let lim = ConcurrencyLimiter::new(nonzero!(3u32));
+    let holders = Arc::new(AtomicU32::new(0));
+    let peak = Arc::new(AtomicU32::new(0));
+    let mut children = vec![];
+    for _i in 0..8 {
+        let (lim, holders, peak) = (lim.clone(), holders.clone(), peak.clone());
+        children.push(thread::spawn(move || {
+            for _j in 0..1000 {
+                if let Ok(_guard) = lim.try_acquire() {
+                    let now_holding = holders.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now_holding, Ordering::SeqCst);
+                    holders.fetch_sub(1, Ordering::SeqCst);
+                }
+            }
+        }));
+    }
+    for child in children {
+        child.join().unwrap();
+    }
+    assert!(peak.load(Ordering::SeqCst) <= 3);
+    assert_eq!(0, lim.in_flight());
+}
+
+#[test]
+fn releases_slot_when_holder_panics() {
+    let lim = ConcurrencyLimiter::new(nonzero!(1u32));
+    let holder = lim.clone();
+    let result = thread::spawn(move || {
+        let _guard = holder.try_acquire().unwrap();
+        panic!("the cell's work failed");
+    })
+    .join();
+    assert!(result.is_err());
+    assert_eq!(0, lim.in_flight());
+    assert!(lim.try_acquire().is_ok());
+}
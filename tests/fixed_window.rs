@@ -0,0 +1,189 @@
+#![cfg(feature = "std")]
+
+extern crate ratelimit_meter;
+#[macro_use]
+extern crate nonzero_ext;
+
+use ratelimit_meter::{
+    algorithms::Algorithm, clock::SystemClock, test_utilities::fuzz::assert_peek_predicts_check,
+    DirectRateLimiter, FixedWindow, KeyedRateLimiter, NegativeMultiDecision, NonConformance,
+};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+fn minute() -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(60 * 25_000_000)
+}
+
+#[test]
+fn rejects_too_many_in_window() {
+    let mut lim =
+        DirectRateLimiter::<FixedWindow, SystemClock>::new(nonzero!(2u32), Duration::from_secs(60));
+    let start = minute();
+    let s = Duration::from_secs(1);
+    assert_eq!(Ok(()), lim.check_at(start + s * 10));
+    assert_eq!(Ok(()), lim.check_at(start + s * 20));
+    assert_ne!(Ok(()), lim.check_at(start + s * 59));
+
+    // The next calendar minute resets the count:
+    assert_eq!(Ok(()), lim.check_at(start + s * 60));
+    assert_eq!(Ok(()), lim.check_at(start + s * 60));
+    assert_ne!(Ok(()), lim.check_at(start + s * 61));
+}
+
+#[test]
+fn rejects_zero_windows() {
+    assert!(<FixedWindow as Algorithm<SystemTime>>::construct(
+        nonzero!(2u32),
+        nonzero!(1u32),
+        Duration::new(0, 0)
+    )
+    .is_err());
+}
+
+#[test]
+fn wait_time_is_next_window() {
+    let lim =
+        FixedWindow::construct(nonzero!(10u32), nonzero!(1u32), Duration::from_secs(3600)).unwrap();
+    let state = <FixedWindow as Algorithm<SystemTime>>::BucketState::default();
+    let hour = UNIX_EPOCH + Duration::from_secs(3600 * 400_000);
+    let at = hour + Duration::from_secs(1234);
+    lim.test_n_and_update(&state, 8, at).unwrap();
+    match lim.test_n_and_update(&state, 3, at) {
+        Err(NegativeMultiDecision::BatchNonConforming(3, nc)) => {
+            assert_eq!(hour + Duration::from_secs(3600), nc.earliest_possible());
+            assert_eq!(Duration::from_secs(3600 - 1234), nc.wait_time_from(at));
+        }
+        other => panic!("Did not expect {:?}", other),
+    }
+    assert_eq!(Ok(()), lim.test_n_and_update(&state, 2, at));
+}
+
+#[test]
+fn aligns_windows_to_epoch_multiples() {
+    // 7 seconds don't divide a minute, so windows only line up with
+    // multiples of 7 seconds since the epoch:
+    let window = Duration::from_secs(7);
+    let mut lim = DirectRateLimiter::<FixedWindow, SystemClock>::new(nonzero!(2u32), window);
+    let boundary = UNIX_EPOCH + window * 200_000_000;
+    let ms = Duration::from_millis(1);
+
+    // A first cell arriving late in a window doesn't move the window:
+    assert_eq!(Ok(()), lim.check_n_at(2, boundary - ms * 1000));
+    match lim.check_at(boundary - ms) {
+        Err(nc) => assert_eq!(boundary, nc.earliest_possible()),
+        other => panic!("Did not expect {:?}", other),
+    }
+    assert_eq!(Ok(()), lim.check_n_at(2, boundary));
+    assert!(lim.check_at(boundary + window - ms).is_err());
+    assert_eq!(Ok(()), lim.check_at(boundary + window));
+}
+
+#[test]
+fn resets_on_the_hour() {
+    let mut lim = DirectRateLimiter::<FixedWindow, SystemClock>::new(
+        nonzero!(1u32),
+        Duration::from_secs(3600),
+    );
+    // 2020-09-13T12:00:00Z:
+    let noon = UNIX_EPOCH + Duration::from_secs(1_599_998_400);
+    let s = Duration::from_secs(1);
+    assert_eq!(Ok(()), lim.check_at(noon - s));
+    assert!(lim.check_at(noon - s).is_err());
+    assert_eq!(Ok(()), lim.check_at(noon));
+    assert!(lim.check_at(noon + s * 3599).is_err());
+    assert_eq!(Ok(()), lim.check_at(noon + s * 3600));
+}
+
+#[test]
+fn counts_pre_epoch_times_in_first_window() {
+    let window = Duration::from_secs(60);
+    let mut lim = DirectRateLimiter::<FixedWindow, SystemClock>::new(nonzero!(1u32), window);
+    let s = Duration::from_secs(1);
+    assert_eq!(Ok(()), lim.check_at(UNIX_EPOCH - s * 3600));
+    assert!(lim.check_at(UNIX_EPOCH - s * 10).is_err());
+    assert!(lim.check_at(UNIX_EPOCH + s * 59).is_err());
+    assert_eq!(Ok(()), lim.check_at(UNIX_EPOCH + window));
+}
+
+#[test]
+fn prevents_time_travel() {
+    let mut lim =
+        DirectRateLimiter::<FixedWindow, SystemClock>::new(nonzero!(2u32), Duration::from_secs(60));
+    let start = minute();
+    let s = Duration::from_secs(1);
+
+    assert_eq!(Ok(()), lim.check_at(start + s * 61));
+    // The clock was set back into the previous minute:
+    assert_eq!(Ok(()), lim.check_at(start + s * 30));
+    assert_ne!(Ok(()), lim.check_at(start + s * 31));
+}
+
+#[test]
+fn rejects_cells_after_charging_to_the_limit() {
+    let lim =
+        FixedWindow::construct(nonzero!(10u32), nonzero!(1u32), Duration::from_secs(60)).unwrap();
+    let state = <FixedWindow as Algorithm<SystemTime>>::BucketState::default();
+    let at = minute() + Duration::from_secs(10);
+    lim.charge_n(&state, u32::MAX, u32::MAX, at);
+    match lim.test_n_and_update(&state, 1, at) {
+        Err(NegativeMultiDecision::BatchNonConforming(1, nc)) => {
+            assert_eq!(minute() + Duration::from_secs(60), nc.earliest_possible())
+        }
+        other => panic!("Did not expect {:?}", other),
+    }
+    assert!(lim.test_n(&state, 10, at).is_err());
+    assert_eq!(
+        Ok(()),
+        lim.test_and_update(&state, minute() + Duration::from_secs(60))
+    );
+}
+
+#[test]
+fn snapshot_resets_at_the_end_of_the_window() {
+    let mut lim =
+        DirectRateLimiter::<FixedWindow, SystemClock>::new(nonzero!(5u32), Duration::from_secs(60));
+    let start = minute();
+    let s = Duration::from_secs(1);
+    lim.check_n_at(2, start + s * 10).unwrap();
+
+    // However late in the window the snapshot is taken, the count
+    // resets with the next window:
+    for &at in &[start + s * 10, start + s * 30, start + s * 59] {
+        let snapshot = lim.snapshot_at(at);
+        assert_eq!(
+            (5, 3, start + s * 60),
+            (snapshot.limit(), snapshot.remaining(), snapshot.reset_at())
+        );
+    }
+    // Nothing was let through in the next window yet:
+    let snapshot = lim.snapshot_at(start + s * 70);
+    assert_eq!(
+        (5, start + s * 70),
+        (snapshot.remaining(), snapshot.reset_at())
+    );
+}
+
+#[test]
+fn peek_predicts_check() {
+    // Delays of up to 300ms cross plenty of window boundaries:
+    let mut lim =
+        DirectRateLimiter::<FixedWindow, SystemClock>::new(nonzero!(5u32), Duration::from_secs(1));
+    assert_peek_predicts_check(&mut lim, minute(), 7, Duration::from_millis(300));
+}
+
+#[test]
+fn keyed() {
+    let mut lim = KeyedRateLimiter::<&str, FixedWindow, SystemClock>::new(
+        nonzero!(1u32),
+        Duration::from_secs(60),
+    );
+    let start = minute();
+    let s = Duration::from_secs(1);
+    assert_eq!(Ok(()), lim.check_at("foo", start + s * 59));
+    assert_eq!(Ok(()), lim.check_at("bar", start + s * 59));
+    assert_ne!(Ok(()), lim.check_at("foo", start + s * 59));
+    assert_eq!(Ok(()), lim.check_at("foo", start + s * 60));
+
+    let removed = lim.cleanup_at(None, start + s * 61);
+    assert_eq!(vec!["bar"], removed);
+}
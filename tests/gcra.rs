@@ -3,8 +3,13 @@ extern crate ratelimit_meter;
 extern crate nonzero_ext;
 
 use ratelimit_meter::{
-    algorithms::Algorithm, test_utilities::current_moment, NegativeMultiDecision, NonConformance,
-    GCRA,
+    algorithms::{gcra::AtomicGCRA, Algorithm},
+    clock::FakeRelativeClock,
+    test_utilities::{
+        current_moment,
+        fuzz::{assert_info_matches_snapshot, assert_peek_predicts_check, Lcg},
+    },
+    DirectRateLimiter, NegativeMultiDecision, NonConformance, Reconfigurable, GCRA,
 };
 use std::thread;
 use std::time::Duration;
@@ -54,7 +59,7 @@ fn allows_n_after_interval() {
     let now = current_moment() + Duration::from_secs(1);
     let ms = Duration::from_millis(1);
     assert_eq!(Ok(()), gcra.test_n_and_update(&state, 2, now));
-    assert!(!gcra.test_n_and_update(&state, 2, now + ms).is_ok());
+    assert!(gcra.test_n_and_update(&state, 2, now + ms).is_err());
     // should be ok again in 1.5s:
     let next = now + Duration::from_secs(1);
     assert_eq!(
@@ -77,7 +82,7 @@ fn correctly_handles_per() {
 
     assert_eq!(Ok(()), gcra.test_and_update(&state, now));
     assert_eq!(Ok(()), gcra.test_and_update(&state, now + ms));
-    assert!(!gcra.test_and_update(&state, now + ms * 10).is_ok());
+    assert!(gcra.test_and_update(&state, now + ms * 10).is_err());
     assert_eq!(Ok(()), gcra.test_and_update(&state, now + ms * 20));
 }
 
@@ -148,7 +153,7 @@ fn actual_threadsafety() {
     let results: Vec<Result<(), <ratelimit_meter::GCRA as Algorithm>::NegativeDecision>> = children
         .into_iter()
         .enumerate()
-        .map(|(n, c)| c.join().expect(&format!("thread {} panicked", n)))
+        .map(|(n, c)| c.join().unwrap_or_else(|_| panic!("thread {} panicked", n)))
         .collect();
     let expected: Vec<Result<(), <ratelimit_meter::GCRA as Algorithm>::NegativeDecision>> =
         results.iter().map(|_| Ok(())).collect();
@@ -171,6 +176,589 @@ fn nonconformance_wait_time_from() {
         assert_eq!(Duration::new(0, 0), failure.wait_time_from(now + ms * 2000));
         assert_eq!(Duration::new(0, 0), failure.wait_time_from(now + ms * 2001));
     } else {
-        assert!(false, "Second attempt should fail");
+        panic!("Second attempt should fail");
     }
 }
+
+#[test]
+fn burst_independent_of_rate() {
+    let ms = Duration::from_millis(1);
+    let gcra = GCRA::construct_with_burst(
+        nonzero!(10u32),
+        nonzero!(1u32),
+        Duration::from_secs(1),
+        nonzero!(2u32),
+    )
+    .unwrap();
+    let state = <GCRA as Algorithm>::BucketState::default();
+    let now = current_moment() + Duration::from_secs(10);
+
+    // The first cell is free, then the burst of 2:
+    for _i in 0..3 {
+        assert_eq!(Ok(()), gcra.test_and_update(&state, now));
+    }
+    assert!(gcra.test_and_update(&state, now + ms).is_err());
+    // ...but the sustained rate is still 10/s:
+    assert_eq!(Ok(()), gcra.test_and_update(&state, now + ms * 100));
+    assert!(gcra.test_and_update(&state, now + ms * 101).is_err());
+    assert_eq!(
+        Err(NegativeMultiDecision::InsufficientCapacity(3)),
+        gcra.test_n_and_update(&state, 3, now + ms * 5000)
+    );
+
+    let mut lim = DirectRateLimiter::<GCRA>::build_with_capacity(nonzero!(10u32))
+        .burst(nonzero!(50u32))
+        .build()
+        .unwrap();
+    for _i in 0..51 {
+        assert_eq!(Ok(()), lim.check_at(now));
+    }
+    assert!(lim.check_at(now + ms * 99).is_err());
+    assert_eq!(Ok(()), lim.check_at(now + ms * 100));
+}
+
+#[test]
+fn rejects_burst_smaller_than_cell() {
+    assert!(<GCRA as Algorithm>::construct_with_burst(
+        nonzero!(10u32),
+        nonzero!(5u32),
+        Duration::from_secs(1),
+        nonzero!(4u32),
+    )
+    .is_err());
+}
+
+#[test]
+fn atomic_gcra_decides_like_gcra() {
+    let gcra = GCRA::construct(nonzero!(5u32), nonzero!(1u32), Duration::from_secs(1)).unwrap();
+    let atomic =
+        AtomicGCRA::construct(nonzero!(5u32), nonzero!(1u32), Duration::from_secs(1)).unwrap();
+    let state = <GCRA as Algorithm>::BucketState::default();
+    let atomic_state = <AtomicGCRA as Algorithm>::BucketState::default();
+    let mut now = current_moment() + Duration::from_secs(10);
+
+    // A pseudo-random sequence of batch sizes and delays:
+    let mut rng = Lcg::new(7);
+    for i in 0..2000 {
+        let n = rng.below(7);
+        let offset = rng.delay(Duration::from_millis(150));
+        // Occasionally look back in time a little:
+        let at = if rng.below(11) == 0 {
+            now - offset / 2
+        } else {
+            now
+        };
+        assert_eq!(
+            gcra.test_n_and_update(&state, n, at),
+            atomic.test_n_and_update(&atomic_state, n, at),
+            "at step {}",
+            i
+        );
+        now += offset;
+    }
+}
+
+#[test]
+fn atomic_gcra_admits_like_gcra_at_fractional_intervals() {
+    // A cell takes up 333_333_333⅓ns at 3 cells per second:
+    let mut lim = DirectRateLimiter::<GCRA>::per_second(nonzero!(3u32));
+    let mut atomic = DirectRateLimiter::<AtomicGCRA>::per_second(nonzero!(3u32));
+    let start = current_moment();
+
+    // Retrying at the earliest possible instant every time, rounding
+    // up the theoretical arrival times would add up to a lag of a
+    // nanosecond after a few cells:
+    let mut at = start;
+    for i in 0..100_000 {
+        let decision = lim.check_at(at);
+        assert_eq!(decision, atomic.check_at(at), "at step {}", i);
+        if let Err(nc) = decision {
+            at = nc.earliest_possible();
+        }
+    }
+    // That covers more than seven hours:
+    assert!(at > start + Duration::from_secs(7 * 3600));
+}
+
+#[test]
+fn atomic_gcra_limits_past_its_span() {
+    // At 577 cells per second, a state keeps track of a little more
+    // than a year after its first decision:
+    let mut atomic = DirectRateLimiter::<AtomicGCRA>::per_second(nonzero!(577u32));
+    let now = current_moment();
+    assert_eq!(Ok(()), atomic.check_at(now));
+
+    let later = now + Duration::from_secs(2 * 365 * 24 * 60 * 60);
+    let admitted = (0..1000).filter(|_| atomic.check_at(later).is_ok()).count();
+    assert!(admitted <= 577, "admitted {} cells", admitted);
+    assert!(atomic.check_at(later + Duration::from_secs(1)).is_err());
+}
+
+#[test]
+fn atomic_gcra_threadsafety() {
+    let mut lim = DirectRateLimiter::<AtomicGCRA>::per_second(nonzero!(20u32));
+    let now = current_moment() + Duration::from_secs(1);
+    let ms = Duration::from_millis(1);
+    let mut children = vec![];
+
+    lim.check_at(now).unwrap();
+    for _i in 0..20 {
+        let mut lim = lim.clone();
+        children.push(thread::spawn(move || lim.check_at(now).unwrap()));
+    }
+    for child in children {
+        child.join().unwrap();
+    }
+    assert!(lim.check_at(now + ms * 2).is_err());
+    assert_eq!(Ok(()), lim.check_at(now + ms * 1000));
+}
+
+#[test]
+fn schedule_spaces_cells_evenly() {
+    let mut lim = DirectRateLimiter::<GCRA>::per_second(nonzero!(10u32));
+    let now = current_moment() + Duration::from_secs(10);
+    let ms = Duration::from_millis(1);
+    let schedule = lim.schedule_n_at(4, now);
+    assert_eq!(4, schedule.len());
+    assert_eq!(
+        vec![now, now + ms * 100, now + ms * 200, now + ms * 300],
+        schedule.collect::<Vec<_>>()
+    );
+    assert_eq!(0, lim.schedule_n_at(0, now).count());
+}
+
+#[test]
+fn schedule_conforms_cell_by_cell() {
+    let gcra = GCRA::construct(nonzero!(10u32), nonzero!(1u32), Duration::from_secs(1)).unwrap();
+    let scheduled = <GCRA as Algorithm>::BucketState::default();
+    let checked = <GCRA as Algorithm>::BucketState::default();
+    let now = current_moment() + Duration::from_secs(10);
+    let ms = Duration::from_millis(1);
+    for _i in 0..11 {
+        gcra.test_and_update(&scheduled, now).unwrap();
+        gcra.test_and_update(&checked, now).unwrap();
+    }
+    assert!(gcra.test_and_update(&scheduled, now).is_err());
+
+    // The bucket is full, so the schedule starts once the next cell
+    // conforms:
+    let schedule: Vec<_> = gcra.schedule_n(&scheduled, 5, now).collect();
+    assert_eq!(now + ms * 100, schedule[0]);
+    for at in schedule.iter() {
+        assert_eq!(Ok(()), gcra.test_and_update(&checked, *at));
+    }
+    assert_eq!(checked, scheduled);
+    assert!(gcra.test_and_update(&scheduled, now + ms * 500).is_err());
+}
+
+#[test]
+fn charge_goes_into_debt() {
+    let mut lim = DirectRateLimiter::<GCRA>::build_with_capacity(nonzero!(10u32))
+        .debt_ceiling(20)
+        .build()
+        .unwrap();
+    let now = current_moment() + Duration::from_secs(10);
+    let ms = Duration::from_millis(1);
+    lim.charge_n_at(25, now);
+    assert!(lim.check_at(now + ms * 1499).is_err());
+    assert_eq!(Ok(()), lim.check_at(now + ms * 1500));
+
+    // Debt beyond the ceiling is forgiven:
+    let later = now + Duration::from_secs(10);
+    lim.charge_n_at(100, later);
+    assert!(lim.check_at(later + ms * 1999).is_err());
+    assert_eq!(Ok(()), lim.check_at(later + ms * 2000));
+}
+
+#[test]
+fn charge_without_debt_fills_up() {
+    let mut lim = DirectRateLimiter::<GCRA>::per_second(nonzero!(10u32));
+    let now = current_moment() + Duration::from_secs(10);
+    let ms = Duration::from_millis(1);
+    lim.charge_n_at(100, now);
+    assert_eq!(Ok(()), lim.check_at(now));
+    assert!(lim.check_at(now).is_err());
+    assert_eq!(Ok(()), lim.check_at(now + ms * 200));
+}
+
+#[test]
+fn atomic_gcra_charges_and_refunds_like_gcra() {
+    let gcra = GCRA::construct(nonzero!(10u32), nonzero!(1u32), Duration::from_secs(1)).unwrap();
+    let atomic =
+        AtomicGCRA::construct(nonzero!(10u32), nonzero!(1u32), Duration::from_secs(1)).unwrap();
+    let state = <GCRA as Algorithm>::BucketState::default();
+    let atomic_state = <AtomicGCRA as Algorithm>::BucketState::default();
+    let mut now = current_moment() + Duration::from_secs(10);
+
+    let mut rng = Lcg::new(3);
+    for i in 0..2000 {
+        let n = rng.below(30);
+        let action = rng.below(35);
+        if action % 5 == 0 {
+            gcra.charge_n(&state, n, 15, now);
+            atomic.charge_n(&atomic_state, n, 15, now);
+        } else if action % 7 == 0 {
+            gcra.refund_n(&state, n % 8, now);
+            atomic.refund_n(&atomic_state, n % 8, now);
+        } else {
+            assert_eq!(
+                gcra.test_n_and_update(&state, n % 5, now),
+                atomic.test_n_and_update(&atomic_state, n % 5, now),
+                "at step {}",
+                i
+            );
+        }
+        now += rng.delay(Duration::from_millis(400));
+    }
+}
+
+#[test]
+fn refund_gives_capacity_back() {
+    let mut lim = DirectRateLimiter::<GCRA>::per_second(nonzero!(10u32));
+    let now = current_moment() + Duration::from_secs(10);
+    for _i in 0..11 {
+        lim.check_at(now).unwrap();
+    }
+    assert!(lim.check_at(now).is_err());
+
+    lim.refund_n_at(3, now);
+    for _i in 0..3 {
+        assert_eq!(Ok(()), lim.check_at(now));
+    }
+    assert!(lim.check_at(now).is_err());
+
+    // Refunds can't create more capacity than an empty bucket has:
+    lim.refund_n_at(100, now);
+    for _i in 0..11 {
+        assert_eq!(Ok(()), lim.check_at(now));
+    }
+    assert!(lim.check_at(now).is_err());
+}
+
+#[test]
+fn peek_predicts_check() {
+    let now = current_moment() + Duration::from_secs(10);
+    let ms = Duration::from_millis(1);
+    let mut lim = DirectRateLimiter::<GCRA>::per_second(nonzero!(10u32));
+    assert_peek_predicts_check(&mut lim, now, 12, ms * 300);
+    let mut atomic = DirectRateLimiter::<AtomicGCRA>::per_second(nonzero!(10u32));
+    assert_peek_predicts_check(&mut atomic, now, 12, ms * 300);
+}
+
+#[test]
+fn snapshot_resets_at_the_theoretical_arrival_time() {
+    // A burst of 2 cells on top of the first, at 10 cells per second:
+    let gcra = GCRA::construct_with_burst(
+        nonzero!(10u32),
+        nonzero!(1u32),
+        Duration::from_secs(1),
+        nonzero!(2u32),
+    )
+    .unwrap();
+    let atomic = AtomicGCRA::construct_with_burst(
+        nonzero!(10u32),
+        nonzero!(1u32),
+        Duration::from_secs(1),
+        nonzero!(2u32),
+    )
+    .unwrap();
+    let state = <GCRA as Algorithm>::BucketState::default();
+    let atomic_state = <AtomicGCRA as Algorithm>::BucketState::default();
+    let now = current_moment() + Duration::from_secs(10);
+    let ms = Duration::from_millis(1);
+    for _i in 0..3 {
+        gcra.test_and_update(&state, now).unwrap();
+        atomic.test_and_update(&atomic_state, now).unwrap();
+    }
+
+    // Three cells push the theoretical arrival time out by 300ms,
+    // further than the burst reaches, whenever the snapshot is taken:
+    let snapshot = gcra.snapshot(&state, now);
+    assert_eq!(
+        (2, 0, now + ms * 300),
+        (snapshot.limit(), snapshot.remaining(), snapshot.reset_at())
+    );
+    for &at in &[now, now + ms * 150, now + ms * 200, now + ms * 299] {
+        let snapshot = gcra.snapshot(&state, at);
+        assert_eq!(now + ms * 300, snapshot.reset_at());
+        assert_eq!(snapshot, atomic.snapshot(&atomic_state, at));
+    }
+    assert_eq!(0, gcra.snapshot(&state, now + ms * 199).remaining());
+    assert_eq!(1, gcra.snapshot(&state, now + ms * 200).remaining());
+
+    // A theoretical arrival time between two nanoseconds resets at
+    // the later one:
+    let gcra = GCRA::construct(nonzero!(3u32), nonzero!(1u32), Duration::from_secs(1)).unwrap();
+    let state = <GCRA as Algorithm>::BucketState::default();
+    gcra.test_and_update(&state, now).unwrap();
+    assert_eq!(
+        now + Duration::from_nanos(333_333_334),
+        gcra.snapshot(&state, now).reset_at()
+    );
+}
+
+#[test]
+fn info_matches_snapshot() {
+    let now = current_moment() + Duration::from_secs(10);
+    let ms = Duration::from_millis(1);
+    let (mut lim, mut plain) = (
+        DirectRateLimiter::<GCRA>::per_second(nonzero!(10u32)),
+        DirectRateLimiter::<GCRA>::per_second(nonzero!(10u32)),
+    );
+    assert_info_matches_snapshot(&mut lim, &mut plain, now, 6, ms * 300);
+    let (mut atomic, mut plain) = (
+        DirectRateLimiter::<AtomicGCRA>::per_second(nonzero!(10u32)),
+        DirectRateLimiter::<AtomicGCRA>::per_second(nonzero!(10u32)),
+    );
+    assert_info_matches_snapshot(&mut atomic, &mut plain, now, 6, ms * 300);
+}
+
+#[test]
+fn reservation_starts_when_cells_conform() {
+    let mut lim = DirectRateLimiter::<GCRA>::per_second(nonzero!(2u32));
+    let now = current_moment() + Duration::from_secs(10);
+    let ms = Duration::from_millis(1);
+    for _i in 0..3 {
+        lim.check_at(now).unwrap();
+    }
+
+    let reservation = lim.reserve_n_at(1, now).unwrap();
+    assert_eq!(now + ms * 500, reservation.start());
+    assert_eq!(ms * 500, reservation.wait_time_from(now));
+    assert!(lim.check_at(now + ms * 500).is_err());
+
+    // Cancelling gives the capacity back:
+    reservation.cancel_at(now);
+    assert_eq!(Ok(()), lim.check_at(now + ms * 500));
+
+    let reservation = lim.reserve_n_at(1, now + ms * 500).unwrap();
+    assert_eq!(now + ms * 1000, reservation.start());
+    reservation.confirm();
+    assert!(lim.check_at(now + ms * 1000).is_err());
+
+    assert_eq!(
+        NegativeMultiDecision::InsufficientCapacity(3),
+        lim.reserve_n_at(3, now).unwrap_err()
+    );
+}
+
+#[test]
+fn dropped_reservation_gives_capacity_back() {
+    let now = Duration::from_secs(10);
+    let mut clock = FakeRelativeClock::default();
+    clock.advance(now);
+    let mut lim =
+        DirectRateLimiter::<GCRA<Duration>, FakeRelativeClock>::build_with_capacity(nonzero!(2u32))
+            .using_clock(clock)
+            .build()
+            .unwrap();
+    let ms = Duration::from_millis(1);
+    for _i in 0..3 {
+        lim.check_at(now).unwrap();
+    }
+    {
+        // The fake clock stands still before the reservation starts:
+        let _reservation = lim.reserve_n_at(1, now).unwrap();
+        assert!(lim.check_at(now + ms * 500).is_err());
+    }
+    assert_eq!(Ok(()), lim.check_at(now + ms * 500));
+}
+
+#[test]
+fn check_up_to_n_accepts_what_fits() {
+    let mut lim = DirectRateLimiter::<GCRA>::per_second(nonzero!(10u32));
+    let mut atomic = DirectRateLimiter::<AtomicGCRA>::per_second(nonzero!(10u32));
+    let now = current_moment() + Duration::from_secs(10);
+    let ms = Duration::from_millis(1);
+
+    let (accepted, rest) = lim.check_up_to_n_at(15, now);
+    assert_eq!(10, accepted);
+    assert!(rest.unwrap().earliest_possible() > now);
+    assert!(lim.check_at(now).is_err());
+    assert_eq!((0, lim.check_at(now).err()), lim.check_up_to_n_at(3, now));
+
+    assert_eq!((3, None), lim.check_up_to_n_at(3, now + ms * 2000));
+    let (accepted, rest) = lim.check_up_to_n_at(10, now + ms * 2000);
+    assert_eq!(7, accepted);
+    assert!(rest.is_some());
+
+    // The lock-free variant makes the same decisions:
+    assert_eq!((10, None), atomic.check_up_to_n_at(10, now));
+    assert_eq!(
+        (0, atomic.check_at(now).err()),
+        atomic.check_up_to_n_at(3, now)
+    );
+    assert_eq!((3, None), atomic.check_up_to_n_at(3, now + ms * 2000));
+    assert_eq!(7, atomic.check_up_to_n_at(10, now + ms * 2000).0);
+}
+
+#[test]
+fn reconfigure_rescales_tat() {
+    let lim = DirectRateLimiter::<Reconfigurable<GCRA>>::per_second(nonzero!(10u32));
+    let now = current_moment() + Duration::from_secs(10);
+    let mut clone = lim.clone();
+    for _i in 0..5 {
+        clone.check_at(now).unwrap();
+    }
+    assert_eq!(5, lim.snapshot_at(now).remaining());
+
+    // The cells let through so far keep counting against the new limit:
+    lim.reconfigure(nonzero!(20u32), nonzero!(1u32), Duration::from_secs(1))
+        .unwrap();
+    let snapshot = clone.snapshot_at(now);
+    assert_eq!((20, 15), (snapshot.limit(), snapshot.remaining()));
+
+    clone
+        .reconfigure(nonzero!(5u32), nonzero!(1u32), Duration::from_secs(1))
+        .unwrap();
+    assert_eq!(0, lim.snapshot_at(now).remaining());
+
+    // Inconsistent parameters leave the current ones alone:
+    assert!(lim
+        .reconfigure(nonzero!(1u32), nonzero!(2u32), Duration::from_secs(1))
+        .is_err());
+    assert_eq!(5, lim.snapshot_at(now).limit());
+
+    let mut atomic = DirectRateLimiter::<Reconfigurable<AtomicGCRA>>::per_second(nonzero!(10u32));
+    for _i in 0..5 {
+        atomic.check_at(now).unwrap();
+    }
+    atomic
+        .reconfigure(nonzero!(20u32), nonzero!(1u32), Duration::from_secs(1))
+        .unwrap();
+    assert_eq!(15, atomic.snapshot_at(now).remaining());
+}
+
+#[test]
+fn reconfigure_keeps_cells_past_a_cut_capacity() {
+    let mut lim = DirectRateLimiter::<Reconfigurable<GCRA>>::per_second(nonzero!(10u32));
+    let now = current_moment();
+    let ms = Duration::from_millis(1);
+    for _i in 0..10 {
+        lim.check_at(now).unwrap();
+    }
+
+    // Ten cells take up five seconds at the new rate, more than the
+    // bucket holds:
+    lim.reconfigure(nonzero!(2u32), nonzero!(1u32), Duration::from_secs(1))
+        .unwrap();
+    let snapshot = lim.snapshot_at(now);
+    assert_eq!(
+        (0, now + ms * 5000),
+        (snapshot.remaining(), snapshot.reset_at())
+    );
+    assert!(lim.check_at(now).is_err());
+    assert!(lim.check_at(now + ms * 3999).is_err());
+    assert_eq!(Ok(()), lim.check_n_at(2, now + ms * 5000));
+}
+
+#[test]
+fn peeking_leaves_a_reconfigured_state_alone() {
+    let lim = Reconfigurable::new(
+        GCRA::construct(nonzero!(10u32), nonzero!(1u32), Duration::from_secs(1)).unwrap(),
+    );
+    let peeked = <Reconfigurable<GCRA> as Algorithm>::BucketState::default();
+    let untouched = <Reconfigurable<GCRA> as Algorithm>::BucketState::default();
+    let now = current_moment();
+    for _i in 0..5 {
+        lim.test_and_update(&peeked, now).unwrap();
+        lim.test_and_update(&untouched, now).unwrap();
+    }
+
+    lim.reconfigure(nonzero!(20u32), nonzero!(1u32), Duration::from_secs(1))
+        .unwrap();
+    assert_eq!(15, lim.snapshot(&peeked, now).remaining());
+    assert_eq!(Ok(()), lim.test(&peeked, now));
+    assert_eq!(untouched, peeked);
+
+    // Checking a cell rescales the state for good:
+    lim.test_and_update(&peeked, now).unwrap();
+    lim.test_and_update(&untouched, now).unwrap();
+    assert_eq!(untouched, peeked);
+    assert_eq!(14, lim.snapshot(&peeked, now).remaining());
+}
+
+#[test]
+fn uneven_rates_do_not_drift() {
+    // 3 cells every 10ns make for an emission interval of 3⅓ns, which
+    // must not be truncated to 3ns:
+    let mut lim = DirectRateLimiter::<GCRA>::new(nonzero!(3u32), Duration::from_nanos(10));
+    let now = current_moment();
+    let accepted = (0..=1_000_000u64)
+        .filter(|ns| lim.check_at(now + Duration::from_nanos(*ns)).is_ok())
+        .count();
+    // One cell per emission interval, plus the initial burst (which
+    // the GCRA lets exceed the capacity by one cell):
+    assert_eq!(300_000 + 3 + 1, accepted);
+}
+
+#[test]
+fn sub_nanosecond_intervals() {
+    // 4 cells per nanosecond:
+    let mut lim = DirectRateLimiter::<GCRA>::new(nonzero!(4u32), Duration::from_nanos(1));
+    let now = current_moment();
+    let mut accepted = 0;
+    for ns in 0..=1_000u64 {
+        while lim.check_at(now + Duration::from_nanos(ns)).is_ok() {
+            accepted += 1;
+        }
+    }
+    assert_eq!(4_000 + 4 + 1, accepted);
+
+    // The lock-free variant keeps track of fractions of a nanosecond,
+    // too:
+    let mut atomic = DirectRateLimiter::<AtomicGCRA>::new(nonzero!(4u32), Duration::from_nanos(1));
+    let mut atomic_accepted = 0;
+    for ns in 0..=1_000u64 {
+        while atomic.check_at(now + Duration::from_nanos(ns)).is_ok() {
+            atomic_accepted += 1;
+        }
+    }
+    assert_eq!(accepted, atomic_accepted);
+
+    // ...unless it'd have to split a nanosecond into so many
+    // fractions that its state couldn't cover a year:
+    assert!(<AtomicGCRA as Algorithm>::construct(
+        nonzero!(999_999_937u32),
+        nonzero!(1u32),
+        Duration::from_secs(1)
+    )
+    .is_err());
+}
+
+#[test]
+fn rejects_unrepresentable_rates() {
+    assert!(
+        <GCRA as Algorithm>::construct(nonzero!(1u32), nonzero!(1u32), Duration::new(0, 0))
+            .is_err()
+    );
+    assert!(<GCRA as Algorithm>::construct_with_burst(
+        nonzero!(1u32),
+        nonzero!(1u32),
+        Duration::new(u64::MAX, 0),
+        nonzero!(10u32)
+    )
+    .is_err());
+}
+
+#[test]
+fn reconfigure_keeps_huge_states() {
+    // A cell takes up 10^27ns here, so multiplying the time it takes
+    // up by the new interval would overflow 128 bits:
+    let per = Duration::from_secs(1_000_000_000_000_000_000);
+    let mut lim = DirectRateLimiter::<Reconfigurable<GCRA>>::new(nonzero!(2u32), per);
+    let mut plain = DirectRateLimiter::<GCRA>::new(nonzero!(2u32), per);
+    let now = current_moment();
+    lim.check_n_at(2, now).unwrap();
+    plain.check_n_at(2, now).unwrap();
+
+    lim.reconfigure(nonzero!(2u32), nonzero!(1u32), per)
+        .unwrap();
+    let later = now + Duration::from_secs(86_400);
+    let (snapshot, expected) = (lim.snapshot_at(later), plain.snapshot_at(later));
+    assert_eq!(
+        (expected.remaining(), expected.reset_at()),
+        (snapshot.remaining(), snapshot.reset_at())
+    );
+    assert!(lim.check_n_at(2, later).is_err());
+}
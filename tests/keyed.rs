@@ -4,7 +4,13 @@ extern crate ratelimit_meter;
 #[macro_use]
 extern crate nonzero_ext;
 
-use ratelimit_meter::{KeyedRateLimiter, GCRA};
+use ratelimit_meter::{
+    algorithms::{
+        gcra::AtomicGCRA, leaky_bucket::AtomicLeakyBucket, Adaptive, Algorithm, WallClockAnchor,
+    },
+    Composite, KeyedConcurrencyLimiter, KeyedRateLimiter, LeakyBucket, Reconfigurable,
+    SlidingWindowCounter, SlidingWindowLog, GCRA,
+};
 use std::thread;
 use std::time::{Duration, Instant};
 
@@ -80,6 +86,337 @@ fn actual_threadsafety() {
     for child in children {
         child.join().unwrap();
     }
-    assert!(!lim.check_at("foo", now + ms * 2).is_ok());
+    assert!(lim.check_at("foo", now + ms * 2).is_err());
     assert_eq!(Ok(()), lim.check_at("foo", now + ms * 1000));
 }
+
+#[test]
+fn sliding_window_log_per_key() {
+    let mut lim =
+        KeyedRateLimiter::<&str, SlidingWindowLog>::new(nonzero!(2u32), Duration::from_secs(1));
+    let now = Instant::now();
+    let ms = Duration::from_millis(1);
+    assert_eq!(Ok(()), lim.check_n_at("foo", 2, now));
+    assert_eq!(Ok(()), lim.check_at("bar", now + ms));
+    assert_ne!(Ok(()), lim.check_at("foo", now + ms * 999));
+    assert_eq!(Ok(()), lim.check_at("bar", now + ms * 999));
+
+    let mut removed = lim.cleanup_at(None, now + ms * 2000);
+    removed.sort();
+    assert_eq!(vec!["bar", "foo"], removed);
+}
+
+#[test]
+fn sliding_window_counter_per_key() {
+    let mut lim =
+        KeyedRateLimiter::<&str, SlidingWindowCounter>::new(nonzero!(2u32), Duration::from_secs(1));
+    let now = Instant::now();
+    let ms = Duration::from_millis(1);
+    assert_eq!(Ok(()), lim.check_n_at("foo", 2, now));
+    assert_eq!(Ok(()), lim.check_at("bar", now + ms));
+    assert_ne!(Ok(()), lim.check_at("foo", now + ms * 999));
+    assert_eq!(Ok(()), lim.check_at("bar", now + ms * 999));
+
+    let mut removed = lim.cleanup_at(None, now + ms * 2001);
+    removed.sort();
+    assert_eq!(vec!["foo"], removed);
+}
+
+#[test]
+fn burst_per_key() {
+    let mut lim = KeyedRateLimiter::<&str, GCRA>::build_with_capacity(nonzero!(10u32))
+        .with_burst(nonzero!(1u32))
+        .build()
+        .unwrap();
+    let now = Instant::now();
+    let ms = Duration::from_millis(1);
+    assert_eq!(Ok(()), lim.check_at("foo", now));
+    assert_eq!(Ok(()), lim.check_at("foo", now));
+    assert_ne!(Ok(()), lim.check_at("foo", now + ms * 50));
+    assert_eq!(Ok(()), lim.check_at("foo", now + ms * 100));
+}
+
+#[test]
+fn composite_per_key() {
+    let algorithm = Composite::construct(nonzero!(1u32), nonzero!(1u32), Duration::from_secs(10))
+        .unwrap()
+        .and(GCRA::construct(nonzero!(1u32), nonzero!(1u32), Duration::from_secs(1)).unwrap());
+    let mut lim = KeyedRateLimiter::<&str, Composite>::with_algorithm(algorithm);
+    let now = Instant::now();
+    let s = Duration::from_secs(1);
+    assert_eq!(Ok(()), lim.check_at("foo", now));
+    assert_eq!(Ok(()), lim.check_at("foo", now));
+    assert_ne!(Ok(()), lim.check_at("foo", now));
+    assert_eq!(Ok(()), lim.check_at("bar", now));
+
+    // Keys expire once their slowest bandwidth has recovered:
+    assert_eq!(vec!["bar"], lim.cleanup_at(None, now + s * 25));
+    assert_eq!(vec!["foo"], lim.cleanup_at(None, now + s * 31));
+}
+
+#[test]
+fn concurrency_per_key() {
+    let concurrency = KeyedConcurrencyLimiter::<&str>::new(nonzero!(1u32));
+    let mut lim = KeyedRateLimiter::<&str, GCRA>::per_second(nonzero!(10u32));
+    let now = Instant::now();
+    let foo = lim.check_and_acquire_at("foo", &concurrency, now).unwrap();
+    assert!(lim.check_and_acquire_at("foo", &concurrency, now).is_err());
+    let _bar = lim.check_and_acquire_at("bar", &concurrency, now).unwrap();
+    assert_eq!(1, concurrency.in_flight(&"foo"));
+
+    drop(foo);
+    assert_eq!(vec!["foo"], concurrency.cleanup());
+    assert_eq!(0, concurrency.in_flight(&"foo"));
+    assert!(lim.check_and_acquire_at("foo", &concurrency, now).is_ok());
+}
+
+#[test]
+fn adaptive_per_key() {
+    let adaptive = <Adaptive<GCRA> as Algorithm>::construct(
+        nonzero!(10u32),
+        nonzero!(1u32),
+        Duration::from_secs(1),
+    )
+    .unwrap();
+    let mut lim = KeyedRateLimiter::<&str, Adaptive<GCRA>>::with_algorithm(adaptive.clone());
+    let now = Instant::now();
+    let ms = Duration::from_millis(1);
+    assert_eq!(Ok(()), lim.check_at("foo", now));
+    adaptive.on_backoff();
+    adaptive.on_backoff();
+    adaptive.on_backoff();
+    adaptive.on_backoff();
+    assert_eq!(nonzero!(1u32), adaptive.capacity());
+    assert_eq!(Ok(()), lim.check_at("bar", now));
+
+    // Keys expire at the pace of the slowest possible rate:
+    assert_eq!(vec!["foo"], lim.cleanup_at(None, now + ms * 1500));
+    assert_eq!(Vec::<&str>::new(), lim.cleanup_at(None, now + ms * 1900));
+    assert_eq!(vec!["bar"], lim.cleanup_at(None, now + ms * 2100));
+}
+
+#[test]
+fn atomic_gcra_per_key() {
+    let mut lim = KeyedRateLimiter::<&str, AtomicGCRA>::new(nonzero!(1u32), Duration::from_secs(1));
+    let now = Instant::now();
+    let ms = Duration::from_millis(1);
+    assert_eq!(Ok(()), lim.check_at("foo", now));
+    assert_eq!(Ok(()), lim.check_at("foo", now));
+    assert_eq!(Ok(()), lim.check_at("bar", now + ms * 500));
+    assert_ne!(Ok(()), lim.check_at("foo", now + ms * 500));
+
+    assert_eq!(vec!["bar"], lim.cleanup_at(None, now + ms * 2600));
+    assert_eq!(vec!["foo"], lim.cleanup_at(None, now + ms * 3100));
+}
+
+#[test]
+fn atomic_leaky_bucket_per_key() {
+    let mut lim =
+        KeyedRateLimiter::<&str, AtomicLeakyBucket>::new(nonzero!(2u32), Duration::from_secs(1));
+    let now = Instant::now();
+    let ms = Duration::from_millis(1);
+    assert_eq!(Ok(()), lim.check_n_at("foo", 2, now));
+    assert_eq!(Ok(()), lim.check_at("bar", now + ms * 600));
+    assert_ne!(Ok(()), lim.check_at("foo", now + ms * 400));
+
+    assert_eq!(vec!["foo"], lim.cleanup_at(None, now + ms * 1001));
+    assert_eq!(vec!["bar"], lim.cleanup_at(None, now + ms * 1101));
+}
+
+#[test]
+fn schedule_per_key() {
+    let mut lim = KeyedRateLimiter::<&str, GCRA>::per_second(nonzero!(2u32));
+    let now = Instant::now();
+    let ms = Duration::from_millis(1);
+    assert_eq!(
+        vec![now, now + ms * 500, now + ms * 1000],
+        lim.schedule_n_at("foo", 3, now).collect::<Vec<_>>()
+    );
+    assert_eq!(
+        vec![now + ms * 500],
+        lim.schedule_n_at("foo", 1, now).collect::<Vec<_>>()
+    );
+    assert_eq!(
+        vec![now],
+        lim.schedule_n_at("bar", 1, now).collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn charge_per_key() {
+    let mut lim = KeyedRateLimiter::<&str, GCRA>::build_with_capacity(nonzero!(2u32))
+        .with_debt_ceiling(2)
+        .build()
+        .unwrap();
+    let now = Instant::now();
+    let ms = Duration::from_millis(1);
+    lim.charge_n_at("foo", 4, now);
+    assert!(lim.check_at("foo", now + ms * 999).is_err());
+    assert_eq!(Ok(()), lim.check_at("foo", now + ms * 1000));
+    assert_eq!(Ok(()), lim.check_at("bar", now));
+}
+
+#[test]
+fn refund_per_key() {
+    let mut lim = KeyedRateLimiter::<&str>::per_second(nonzero!(2u32));
+    let now = Instant::now();
+    lim.check_n_at("foo", 2, now).unwrap();
+    assert!(lim.check_at("foo", now).is_err());
+    lim.refund_n_at("foo", 1, now);
+    assert_eq!(Ok(()), lim.check_at("foo", now));
+    assert!(lim.check_at("foo", now).is_err());
+
+    // Refunds don't add keys:
+    lim.refund_n_at("bar", 1, now);
+    assert_eq!(1, lim.len());
+}
+
+#[test]
+fn peek_per_key() {
+    let mut lim = KeyedRateLimiter::<&str, GCRA>::per_second(nonzero!(2u32));
+    let now = Instant::now();
+    let ms = Duration::from_millis(1);
+    assert_eq!(Ok(()), lim.peek_n_at(&"foo", 2, now));
+    assert_eq!(0, lim.len());
+
+    lim.check_n_at("foo", 2, now).unwrap();
+    let peeked = lim.peek_at(&"foo", now + ms);
+    assert!(peeked.is_err());
+    assert_eq!(peeked, lim.check_at("foo", now + ms));
+    assert_eq!(Ok(()), lim.peek_at(&"bar", now));
+    assert_eq!(1, lim.len());
+}
+
+#[test]
+fn snapshot_per_key() {
+    let mut lim = KeyedRateLimiter::<&str>::per_second(nonzero!(2u32));
+    let now = Instant::now();
+    assert_eq!(2, lim.snapshot_at(&"foo", now).remaining());
+    assert_eq!(0, lim.len());
+
+    lim.check_at("foo", now).unwrap();
+    assert_eq!(1, lim.snapshot_at(&"foo", now).remaining());
+    assert_eq!(1, lim.len());
+}
+
+#[test]
+fn check_with_info_per_key() {
+    let mut lim = KeyedRateLimiter::<&str>::per_second(nonzero!(2u32));
+    let now = Instant::now();
+    assert_eq!(1, lim.check_with_info_at("foo", now).unwrap().remaining());
+    assert_eq!(0, lim.check_with_info_at("foo", now).unwrap().remaining());
+    assert!(lim.check_with_info_at("foo", now).is_err());
+    assert_eq!(
+        0,
+        lim.check_n_with_info_at("bar", 2, now).unwrap().remaining()
+    );
+}
+
+#[test]
+fn reservation_per_key() {
+    let mut lim = KeyedRateLimiter::<&str, GCRA>::per_second(nonzero!(2u32));
+    let now = Instant::now() + Duration::from_secs(10);
+    let ms = Duration::from_millis(1);
+    lim.check_n_at("foo", 2, now).unwrap();
+    let reservation = lim.reserve_n_at("foo", 1, now).unwrap();
+    assert!(reservation.start() > now);
+    assert!(lim.check_at("foo", reservation.start()).is_err());
+    reservation.cancel_at(now);
+    assert_eq!(Ok(()), lim.check_at("foo", now + ms * 1000));
+
+    let reservation = lim.reserve_n_at("bar", 2, now).unwrap();
+    assert_eq!(now, reservation.start());
+    reservation.confirm();
+}
+
+#[test]
+fn check_up_to_n_per_key() {
+    let mut lim = KeyedRateLimiter::<&str>::per_second(nonzero!(5u32));
+    let now = Instant::now();
+    assert_eq!(5, lim.check_up_to_n_at("foo", 7, now).0);
+    assert_eq!(0, lim.check_up_to_n_at("foo", 1, now).0);
+    assert_eq!((3, None), lim.check_up_to_n_at("bar", 3, now));
+}
+
+#[test]
+fn reconfigure_keeps_every_key() {
+    let mut lim = KeyedRateLimiter::<&str, Reconfigurable<GCRA>>::per_second(nonzero!(10u32));
+    let now = Instant::now();
+    for _i in 0..5 {
+        lim.check_at("foo", now).unwrap();
+    }
+    lim.reconfigure(nonzero!(20u32), nonzero!(1u32), Duration::from_secs(1))
+        .unwrap();
+    assert_eq!(15, lim.snapshot_at(&"foo", now).remaining());
+    assert_eq!(20, lim.snapshot_at(&"bar", now).remaining());
+}
+
+#[test]
+fn builds_with_quota() {
+    let quota = "1/s burst 2".parse().unwrap();
+    let now = Instant::now();
+    let mut lim = KeyedRateLimiter::<&str>::build_with_quota(quota)
+        .build()
+        .unwrap();
+    assert_eq!(Ok(()), lim.check_n_at("foo", 2, now));
+    assert!(lim.check_at("foo", now).is_err());
+    assert_eq!(Ok(()), lim.check_n_at("bar", 2, now));
+
+    let mut lim = KeyedRateLimiter::<&str>::with_quota(quota).unwrap();
+    assert_eq!(Ok(()), lim.check_n_at("foo", 2, now));
+}
+
+#[test]
+fn exported_states_survive_a_restart() {
+    let ms = Duration::from_millis(1);
+    let wall_clock = Duration::from_secs(1_500_000_000);
+
+    let mut gcra = KeyedRateLimiter::<&str, GCRA>::per_second(nonzero!(2u32));
+    let mut lb = KeyedRateLimiter::<&str, LeakyBucket>::per_second(nonzero!(2u32));
+    let before = Instant::now();
+    gcra.check_n_at("foo", 2, before).unwrap();
+    lb.check_n_at("foo", 2, before).unwrap();
+    gcra.check_at("drained", before - ms * 1000).unwrap();
+    let anchor = WallClockAnchor::new(before, wall_clock);
+    let gcra_states = gcra.export_at(&anchor);
+    let lb_states = lb.export_at(&anchor);
+    assert_eq!(1, gcra_states.len());
+    assert_eq!(1, lb_states.len());
+
+    // After a restart that took 500ms, the monotonic clock reads a
+    // different instant:
+    let after = Instant::now() + ms * 12345;
+    let anchor = WallClockAnchor::new(after, wall_clock + ms * 500);
+    let mut gcra = KeyedRateLimiter::<&str, GCRA>::per_second(nonzero!(2u32));
+    let mut lb = KeyedRateLimiter::<&str, LeakyBucket>::per_second(nonzero!(2u32));
+    gcra.import_at(gcra_states, &anchor);
+    lb.import_at(lb_states, &anchor);
+
+    assert_eq!(Ok(()), gcra.check_at("foo", after));
+    assert!(gcra.check_at("foo", after).is_err());
+    assert_eq!(Ok(()), lb.check_at("foo", after));
+    assert!(lb.check_at("foo", after).is_err());
+    assert_eq!(Ok(()), gcra.check_n_at("bar", 2, after));
+}
+
+#[test]
+fn dump_and_load() {
+    let mut lim = KeyedRateLimiter::<String>::per_second(nonzero!(1u32));
+    lim.check("foo bar".to_string()).unwrap();
+    lim.check("baz".to_string()).unwrap();
+    let mut dump = vec![];
+    lim.dump(&mut dump).unwrap();
+    assert_eq!(2, dump.iter().filter(|b| **b == b'\n').count());
+
+    let mut lim = KeyedRateLimiter::<String>::per_second(nonzero!(1u32));
+    lim.load(&dump[..]).unwrap();
+    assert_eq!(2, lim.len());
+    assert!(lim.check("foo bar".to_string()).is_err());
+    assert!(lim.check("baz".to_string()).is_err());
+    assert_eq!(Ok(()), lim.check("quux".to_string()));
+
+    let mut lim = KeyedRateLimiter::<String>::per_second(nonzero!(1u32));
+    lim.check("foo\nbar".to_string()).unwrap();
+    assert!(lim.dump(vec![]).is_err());
+    assert!(lim.load(&b"tomorrow foo\n"[..]).is_err());
+}
@@ -3,9 +3,15 @@ extern crate ratelimit_meter;
 extern crate nonzero_ext;
 
 use ratelimit_meter::{
-    algorithms::Algorithm, test_utilities::current_moment, DirectRateLimiter, LeakyBucket,
-    NegativeMultiDecision, NonConformance,
+    algorithms::{leaky_bucket::AtomicLeakyBucket, Algorithm},
+    clock::Reference,
+    test_utilities::{
+        current_moment,
+        fuzz::{assert_info_matches_snapshot, assert_peek_predicts_check, Lcg},
+    },
+    DirectRateLimiter, LeakyBucket, NegativeMultiDecision, NonConformance, Reconfigurable,
 };
+use std::sync::{Arc, Barrier};
 use std::thread;
 use std::time::Duration;
 
@@ -86,6 +92,24 @@ fn prevents_time_travel() {
     assert!(lb.check_at(now - ms * 500).is_ok());
 }
 
+#[test]
+fn atomic_leaky_bucket_drains_only_up_to_a_decisions_own_instant() {
+    let mut lb = DirectRateLimiter::<LeakyBucket>::per_second(nonzero!(2u32));
+    let mut atomic = DirectRateLimiter::<AtomicLeakyBucket>::per_second(nonzero!(2u32));
+    let now = current_moment() + Duration::from_secs(1);
+    let ms = Duration::from_millis(1);
+
+    assert_eq!(Ok(()), lb.check_at(now));
+    assert_eq!(Ok(()), atomic.check_at(now));
+    assert_eq!(Ok(()), lb.check_at(now + ms * 600));
+    assert_eq!(Ok(()), atomic.check_at(now + ms * 600));
+    // The leaky bucket answers from its last update onwards, when
+    // half the bucket has drained; the atomic one doesn't know when
+    // that was, and answers from the decision's own instant:
+    assert_eq!(Ok(()), lb.check_at(now + ms * 100));
+    assert!(atomic.check_at(now + ms * 100).is_err());
+}
+
 #[test]
 fn actual_threadsafety() {
     let mut lim = DirectRateLimiter::<LeakyBucket>::per_second(nonzero!(20u32));
@@ -101,7 +125,7 @@ fn actual_threadsafety() {
     for child in children {
         child.join().unwrap();
     }
-    assert!(!lim.check_at(now + ms * 2).is_ok());
+    assert!(lim.check_at(now + ms * 2).is_err());
     assert_eq!(Ok(()), lim.check_at(now + ms * 1000));
 }
 
@@ -118,6 +142,476 @@ fn tooearly_wait_time_from() {
         assert_eq!(Duration::new(0, 0), failure.wait_time_from(now + ms * 1000));
         assert_eq!(Duration::new(0, 0), failure.wait_time_from(now + ms * 2001));
     } else {
-        assert!(false, "Second attempt should fail");
+        panic!("Second attempt should fail");
+    }
+}
+
+#[test]
+fn burst_independent_of_rate() {
+    let ms = Duration::from_millis(1);
+    let mut lb = DirectRateLimiter::<LeakyBucket>::build_with_capacity(nonzero!(10u32))
+        .burst(nonzero!(2u32))
+        .build()
+        .unwrap();
+    let now = current_moment();
+
+    assert_eq!(Ok(()), lb.check_n_at(2, now));
+    assert!(lb.check_at(now + ms).is_err());
+    // The bucket still drips at 10/s:
+    assert_eq!(Ok(()), lb.check_at(now + ms * 100));
+    assert!(lb.check_at(now + ms * 101).is_err());
+    assert_eq!(
+        Err(NegativeMultiDecision::InsufficientCapacity(3)),
+        lb.check_n_at(3, now + ms * 5000)
+    );
+
+    let mut lb = DirectRateLimiter::<LeakyBucket>::build_with_capacity(nonzero!(10u32))
+        .burst(nonzero!(500u32))
+        .build()
+        .unwrap();
+    assert_eq!(Ok(()), lb.check_n_at(500, now));
+    assert!(lb.check_at(now + ms * 99).is_err());
+    assert_eq!(Ok(()), lb.check_at(now + ms * 100));
+}
+
+#[test]
+fn atomic_leaky_bucket_decides_like_leaky_bucket() {
+    let lb =
+        LeakyBucket::construct(nonzero!(5u32), nonzero!(1u32), Duration::from_secs(1)).unwrap();
+    let atomic =
+        AtomicLeakyBucket::construct(nonzero!(5u32), nonzero!(1u32), Duration::from_secs(1))
+            .unwrap();
+    let state = <LeakyBucket as Algorithm>::BucketState::default();
+    let atomic_state = <AtomicLeakyBucket as Algorithm>::BucketState::default();
+    let mut now = current_moment() + Duration::from_secs(10);
+
+    // A pseudo-random sequence of batch sizes and delays, in order
+    // (see `atomic_leaky_bucket_drains_only_up_to_a_decisions_own_instant`
+    // for decisions out of order):
+    let mut rng = Lcg::new(7);
+    for i in 0..2000 {
+        let n = rng.below(7);
+        assert_eq!(
+            lb.test_n_and_update(&state, n, now),
+            atomic.test_n_and_update(&atomic_state, n, now),
+            "at step {}",
+            i
+        );
+        now += rng.delay(Duration::from_millis(300));
+    }
+}
+
+#[test]
+fn atomic_leaky_bucket_threadsafety() {
+    let mut lim = DirectRateLimiter::<AtomicLeakyBucket>::per_second(nonzero!(20u32));
+    let now = current_moment();
+    let ms = Duration::from_millis(1);
+    let mut children = vec![];
+
+    lim.check_at(now).unwrap();
+    for _i in 0..19 {
+        let mut lim = lim.clone();
+        children.push(thread::spawn(move || lim.check_at(now).unwrap()));
+    }
+    for child in children {
+        child.join().unwrap();
+    }
+    assert!(lim.check_at(now + ms * 2).is_err());
+    assert_eq!(Ok(()), lim.check_at(now + ms * 1000));
+}
+
+/// Lets several threads check cells at once, at instants within a few
+/// microseconds of each other and out of order, and returns the
+/// number of cells let through.
+fn admitted_under_contention<A>() -> usize
+where
+    A: Algorithm,
+    DirectRateLimiter<A>: Clone + Send + 'static,
+{
+    let lim = DirectRateLimiter::<A>::per_second(nonzero!(10u32));
+    let now = current_moment() + Duration::from_secs(10);
+    let threads = 8;
+    let barrier = Arc::new(Barrier::new(threads));
+    let children: Vec<_> = (0..threads as u32)
+        .map(|seed| {
+            let (mut lim, barrier) = (lim.clone(), barrier.clone());
+            thread::spawn(move || {
+                let mut rng = Lcg::new(seed);
+                barrier.wait();
+                (0..2000)
+                    .filter(|_| {
+                        let at = now + rng.delay(Duration::from_micros(5));
+                        lim.check_at(at).is_ok()
+                    })
+                    .count()
+            })
+        })
+        .collect();
+    children.into_iter().map(|c| c.join().unwrap()).sum()
+}
+
+#[test]
+fn atomic_leaky_bucket_admits_like_leaky_bucket_under_contention() {
+    // Looking back and forth by a few microseconds never drains a
+    // whole cell's worth, however the decisions are ordered:
+    for _i in 0..5 {
+        assert_eq!(10, admitted_under_contention::<LeakyBucket>());
+        assert_eq!(10, admitted_under_contention::<AtomicLeakyBucket>());
+    }
+}
+
+#[test]
+fn charge_goes_into_debt() {
+    let mut lb = DirectRateLimiter::<LeakyBucket>::build_with_capacity(nonzero!(10u32))
+        .debt_ceiling(20)
+        .build()
+        .unwrap();
+    let now = current_moment() + Duration::from_secs(10);
+    let ms = Duration::from_millis(1);
+    lb.charge_n_at(25, now);
+    assert_eq!(
+        now + ms * 1600,
+        lb.check_at(now).unwrap_err().earliest_possible()
+    );
+    assert!(lb.check_at(now + ms * 1599).is_err());
+    assert_eq!(Ok(()), lb.check_at(now + ms * 1600));
+
+    // Debt beyond the ceiling is forgiven:
+    let later = now + Duration::from_secs(10);
+    lb.charge_n_at(100, later);
+    assert!(lb.check_at(later + ms * 2099).is_err());
+    assert_eq!(Ok(()), lb.check_at(later + ms * 2100));
+}
+
+#[test]
+fn atomic_leaky_bucket_charges_and_refunds_like_leaky_bucket() {
+    let lb =
+        LeakyBucket::construct(nonzero!(10u32), nonzero!(1u32), Duration::from_secs(1)).unwrap();
+    let atomic =
+        AtomicLeakyBucket::construct(nonzero!(10u32), nonzero!(1u32), Duration::from_secs(1))
+            .unwrap();
+    let state = <LeakyBucket as Algorithm>::BucketState::default();
+    let atomic_state = <AtomicLeakyBucket as Algorithm>::BucketState::default();
+    let mut now = current_moment() + Duration::from_secs(10);
+
+    let mut rng = Lcg::new(3);
+    for i in 0..2000 {
+        let n = rng.below(30);
+        let action = rng.below(35);
+        if action % 5 == 0 {
+            lb.charge_n(&state, n, 15, now);
+            atomic.charge_n(&atomic_state, n, 15, now);
+        } else if action % 7 == 0 {
+            lb.refund_n(&state, n % 8, now);
+            atomic.refund_n(&atomic_state, n % 8, now);
+        } else {
+            assert_eq!(
+                lb.test_n_and_update(&state, n % 5, now),
+                atomic.test_n_and_update(&atomic_state, n % 5, now),
+                "at step {}",
+                i
+            );
+        }
+        now += rng.delay(Duration::from_millis(400));
     }
 }
+
+#[test]
+fn refund_gives_capacity_back() {
+    let mut lb = DirectRateLimiter::<LeakyBucket>::per_second(nonzero!(10u32));
+    let now = current_moment() + Duration::from_secs(10);
+    lb.check_n_at(10, now).unwrap();
+    assert!(lb.check_at(now).is_err());
+
+    lb.refund_n_at(3, now);
+    assert_eq!(Ok(()), lb.check_n_at(3, now));
+    assert!(lb.check_at(now).is_err());
+
+    // Refunds can't create more capacity than an empty bucket has:
+    lb.refund_n_at(100, now);
+    assert_eq!(Ok(()), lb.check_n_at(10, now));
+    assert!(lb.check_at(now).is_err());
+}
+
+#[test]
+fn peek_predicts_check() {
+    let now = current_moment() + Duration::from_secs(10);
+    let ms = Duration::from_millis(1);
+    let mut lb = DirectRateLimiter::<LeakyBucket>::per_second(nonzero!(10u32));
+    assert_peek_predicts_check(&mut lb, now, 12, ms * 300);
+    let mut atomic = DirectRateLimiter::<AtomicLeakyBucket>::per_second(nonzero!(10u32));
+    assert_peek_predicts_check(&mut atomic, now, 12, ms * 300);
+}
+
+#[test]
+fn snapshot_resets_when_the_bucket_drains() {
+    let mut lb = DirectRateLimiter::<LeakyBucket>::per_second(nonzero!(10u32));
+    let mut atomic = DirectRateLimiter::<AtomicLeakyBucket>::per_second(nonzero!(10u32));
+    let now = current_moment() + Duration::from_secs(10);
+    let ms = Duration::from_millis(1);
+    lb.check_n_at(3, now).unwrap();
+    atomic.check_n_at(3, now).unwrap();
+
+    // The bucket drains at the same instant however much of it has
+    // leaked by the time the snapshot is taken:
+    for &(at, remaining) in &[(now, 7), (now + ms * 100, 8)] {
+        let snapshot = lb.snapshot_at(at);
+        assert_eq!(
+            (10, remaining, now + ms * 300),
+            (snapshot.limit(), snapshot.remaining(), snapshot.reset_at())
+        );
+        assert_eq!(snapshot, atomic.snapshot_at(at));
+    }
+    // Snapshots from before the last update see the leaky bucket as
+    // of that update, and the atomic one as of their own instant:
+    let (snapshot, atomic) = (
+        lb.snapshot_at(now - ms * 50),
+        atomic.snapshot_at(now - ms * 50),
+    );
+    assert_eq!(
+        (7, now + ms * 300),
+        (snapshot.remaining(), snapshot.reset_at())
+    );
+    assert_eq!((6, now + ms * 300), (atomic.remaining(), atomic.reset_at()));
+
+    // Debt takes longer to drain than a full bucket:
+    let mut lb = DirectRateLimiter::<LeakyBucket>::build_with_capacity(nonzero!(10u32))
+        .debt_ceiling(20)
+        .build()
+        .unwrap();
+    lb.charge_n_at(25, now);
+    let snapshot = lb.snapshot_at(now + ms * 500);
+    assert_eq!(
+        (0, now + ms * 2500),
+        (snapshot.remaining(), snapshot.reset_at())
+    );
+}
+
+#[test]
+fn info_matches_snapshot() {
+    let now = current_moment() + Duration::from_secs(10);
+    let ms = Duration::from_millis(1);
+    let (mut lb, mut plain) = (
+        DirectRateLimiter::<LeakyBucket>::per_second(nonzero!(10u32)),
+        DirectRateLimiter::<LeakyBucket>::per_second(nonzero!(10u32)),
+    );
+    assert_info_matches_snapshot(&mut lb, &mut plain, now, 6, ms * 300);
+    let (mut atomic, mut plain) = (
+        DirectRateLimiter::<AtomicLeakyBucket>::per_second(nonzero!(10u32)),
+        DirectRateLimiter::<AtomicLeakyBucket>::per_second(nonzero!(10u32)),
+    );
+    assert_info_matches_snapshot(&mut atomic, &mut plain, now, 6, ms * 300);
+}
+
+#[test]
+fn reservation_starts_when_cells_conform() {
+    let mut lb = DirectRateLimiter::<LeakyBucket>::per_second(nonzero!(10u32));
+    let now = current_moment() + Duration::from_secs(10);
+    let ms = Duration::from_millis(1);
+    lb.check_n_at(10, now).unwrap();
+
+    let reservation = lb.reserve_n_at(2, now).unwrap();
+    assert_eq!(now + ms * 200, reservation.start());
+    assert!(lb.check_at(now + ms * 200).is_err());
+
+    // Cancelling gives the capacity back:
+    reservation.cancel_at(now + ms * 100);
+    assert_eq!(Ok(()), lb.check_n_at(2, now + ms * 200));
+
+    let mut atomic = DirectRateLimiter::<AtomicLeakyBucket>::per_second(nonzero!(10u32));
+    atomic.check_n_at(10, now).unwrap();
+    let reservation = atomic.reserve_n_at(2, now).unwrap();
+    assert_eq!(now + ms * 200, reservation.start());
+    reservation.cancel_at(now + ms * 100);
+    assert_eq!(Ok(()), atomic.check_n_at(2, now + ms * 200));
+}
+
+#[test]
+fn check_up_to_n_accepts_what_fits() {
+    let mut lb = DirectRateLimiter::<LeakyBucket>::per_second(nonzero!(10u32));
+    let mut atomic = DirectRateLimiter::<AtomicLeakyBucket>::per_second(nonzero!(10u32));
+    let now = current_moment();
+    let ms = Duration::from_millis(1);
+
+    let (accepted, rest) = lb.check_up_to_n_at(15, now);
+    assert_eq!(10, accepted);
+    assert_eq!(now + ms * 100, rest.unwrap().earliest_possible());
+    let (accepted, rest) = lb.check_up_to_n_at(5, now + ms * 250);
+    assert_eq!(2, accepted);
+    assert_eq!(now + ms * 300, rest.unwrap().earliest_possible());
+    assert_eq!((0, None), lb.check_up_to_n_at(0, now + ms * 250));
+
+    let (accepted, rest) = atomic.check_up_to_n_at(15, now);
+    assert_eq!(10, accepted);
+    assert_eq!(now + ms * 100, rest.unwrap().earliest_possible());
+    let (accepted, rest) = atomic.check_up_to_n_at(5, now + ms * 250);
+    assert_eq!(2, accepted);
+    assert_eq!(now + ms * 300, rest.unwrap().earliest_possible());
+}
+
+#[test]
+fn reconfigure_rescales_level() {
+    let mut lb = DirectRateLimiter::<Reconfigurable<LeakyBucket>>::per_second(nonzero!(10u32));
+    let mut atomic =
+        DirectRateLimiter::<Reconfigurable<AtomicLeakyBucket>>::per_second(nonzero!(10u32));
+    let now = current_moment();
+    let ms = Duration::from_millis(1);
+    lb.check_n_at(5, now).unwrap();
+    atomic.check_n_at(5, now).unwrap();
+
+    lb.reconfigure(nonzero!(20u32), nonzero!(1u32), Duration::from_secs(1))
+        .unwrap();
+    atomic
+        .reconfigure(nonzero!(20u32), nonzero!(1u32), Duration::from_secs(1))
+        .unwrap();
+    assert_eq!(15, lb.snapshot_at(now).remaining());
+    assert_eq!(15, atomic.snapshot_at(now).remaining());
+
+    // The bucket drains at the new rate once a decision rescaled it:
+    assert_eq!(Ok(()), lb.check_at(now));
+    assert_eq!(Ok(()), atomic.check_at(now));
+    assert_eq!(Ok(()), lb.check_n_at(19, now + ms * 250));
+    assert_eq!(Ok(()), atomic.check_n_at(19, now + ms * 250));
+}
+
+#[test]
+fn reconfigure_keeps_cells_past_a_cut_capacity() {
+    let mut lb = DirectRateLimiter::<Reconfigurable<LeakyBucket>>::per_second(nonzero!(10u32));
+    let now = current_moment();
+    let ms = Duration::from_millis(1);
+    lb.check_n_at(10, now).unwrap();
+
+    // Ten cells take five seconds to drain at the new rate, more than
+    // the bucket holds:
+    lb.reconfigure(nonzero!(2u32), nonzero!(1u32), Duration::from_secs(1))
+        .unwrap();
+    let snapshot = lb.snapshot_at(now);
+    assert_eq!(
+        (0, now + ms * 5000),
+        (snapshot.remaining(), snapshot.reset_at())
+    );
+    assert!(lb.check_at(now).is_err());
+    assert!(lb.check_at(now + ms * 4499).is_err());
+    assert_eq!(Ok(()), lb.check_n_at(2, now + ms * 5000));
+}
+
+#[test]
+fn uneven_rates_do_not_drift() {
+    // 3 cells every 10ns make for a cell weight of 3⅓ns, which must
+    // not be truncated to 3ns:
+    let mut lim = DirectRateLimiter::<LeakyBucket>::new(nonzero!(3u32), Duration::from_nanos(10));
+    let now = current_moment();
+    let accepted = (0..=1_000_000u64)
+        .filter(|ns| lim.check_at(now + Duration::from_nanos(*ns)).is_ok())
+        .count();
+    // One cell per 3⅓ns, plus the bucket's initial capacity:
+    assert_eq!(300_000 + 3, accepted);
+}
+
+#[test]
+fn sub_nanosecond_intervals() {
+    // 4 cells per nanosecond:
+    let mut lim = DirectRateLimiter::<LeakyBucket>::new(nonzero!(4u32), Duration::from_nanos(1));
+    let now = current_moment();
+    let mut accepted = 0;
+    for ns in 0..=1_000u64 {
+        while lim.check_at(now + Duration::from_nanos(ns)).is_ok() {
+            accepted += 1;
+        }
+    }
+    assert_eq!(4_000 + 4, accepted);
+
+    // The lock-free variant can't keep track of fractions of a
+    // nanosecond:
+    assert!(<AtomicLeakyBucket as Algorithm>::construct(
+        nonzero!(4u32),
+        nonzero!(1u32),
+        Duration::from_nanos(1)
+    )
+    .is_err());
+    assert!(<AtomicLeakyBucket as Algorithm>::construct(
+        nonzero!(4u32),
+        nonzero!(4u32),
+        Duration::from_nanos(1)
+    )
+    .is_ok());
+}
+
+#[test]
+fn rejects_unrepresentable_rates() {
+    assert!(<LeakyBucket as Algorithm>::construct(
+        nonzero!(1u32),
+        nonzero!(1u32),
+        Duration::new(0, 0)
+    )
+    .is_err());
+}
+
+/// Updates a fresh state with a check at `now` and then with `update`.
+fn updated_state<A: Algorithm<P>, P: Reference>(
+    lb: &A,
+    now: P,
+    update: impl Fn(&A::BucketState),
+) -> A::BucketState {
+    let state = A::BucketState::default();
+    lb.test_n_and_update(&state, 6, now).unwrap();
+    update(&state);
+    state
+}
+
+fn time_travelled_updates_agree_for<A: Algorithm<P>, P: Reference>(lb: A, now: P)
+where
+    A::BucketState: std::fmt::Debug,
+{
+    let past = now.saturating_sub(Duration::from_millis(300));
+    let checked = updated_state(&lb, now, |state| {
+        lb.test_n_and_update(state, 0, past).unwrap()
+    });
+    // Charges and refunds drain from the same time as checks do:
+    let charged = updated_state(&lb, now, |state| lb.charge_n(state, 0, 0, past));
+    assert_eq!(checked, charged);
+    let refunded = updated_state(&lb, now, |state| lb.refund_n(state, 0, past));
+    assert_eq!(checked, refunded);
+}
+
+#[test]
+fn time_travelled_updates_agree() {
+    let now = current_moment() + Duration::from_secs(10);
+    time_travelled_updates_agree_for(
+        LeakyBucket::construct(nonzero!(10u32), nonzero!(1u32), Duration::from_secs(1)).unwrap(),
+        now,
+    );
+    time_travelled_updates_agree_for(
+        AtomicLeakyBucket::construct(nonzero!(10u32), nonzero!(1u32), Duration::from_secs(1))
+            .unwrap(),
+        now,
+    );
+}
+
+#[test]
+fn reconfigure_keeps_huge_levels() {
+    // A cell weighs 10^27ns here, so multiplying its level by the new
+    // interval would overflow 128 bits:
+    let per = Duration::from_secs(1_000_000_000_000_000_000);
+    let mut lb = DirectRateLimiter::<Reconfigurable<LeakyBucket>>::new(nonzero!(1u32), per);
+    let mut atomic =
+        DirectRateLimiter::<Reconfigurable<AtomicLeakyBucket>>::new(nonzero!(1u32), per);
+    let mut plain = DirectRateLimiter::<LeakyBucket>::new(nonzero!(1u32), per);
+    let now = current_moment();
+    lb.check_at(now).unwrap();
+    atomic.check_at(now).unwrap();
+    plain.check_at(now).unwrap();
+
+    lb.reconfigure(nonzero!(1u32), nonzero!(1u32), per).unwrap();
+    atomic
+        .reconfigure(nonzero!(1u32), nonzero!(1u32), per)
+        .unwrap();
+    let later = now + Duration::from_secs(86_400);
+    let (snapshot, expected) = (lb.snapshot_at(later), plain.snapshot_at(later));
+    assert_eq!(
+        (expected.remaining(), expected.reset_at()),
+        (snapshot.remaining(), snapshot.reset_at())
+    );
+    assert!(lb.check_at(later).is_err());
+    assert!(atomic.check_at(later).is_err());
+}
@@ -0,0 +1,112 @@
+extern crate ratelimit_meter;
+#[macro_use]
+extern crate nonzero_ext;
+
+use ratelimit_meter::{
+    test_utilities::current_moment, DirectRateLimiter, InvalidQuota, LeakyBucket, Quota, GCRA,
+};
+use std::time::Duration;
+
+#[test]
+fn parses_quotas() {
+    assert_eq!(Ok(Quota::per_minute(nonzero!(100u32))), "100/min".parse());
+    assert_eq!(
+        Ok(Quota::per_second(nonzero!(3u32))),
+        "3 per second".parse()
+    );
+    assert_eq!(
+        Ok(Quota::new(nonzero!(5u32), Duration::from_secs(2)).with_burst(nonzero!(20u32))),
+        "5 per 2s burst 20".parse()
+    );
+    assert_eq!(
+        Ok(Quota::new(nonzero!(10u32), Duration::from_millis(500))
+            .with_cell_weight(nonzero!(2u32))
+            .with_burst(nonzero!(4u32))),
+        " 10 / 500ms  burst 4 weight 2 ".parse()
+    );
+    assert_eq!(Ok(Quota::per_hour(nonzero!(1u32))), "1 per 60min".parse());
+}
+
+#[test]
+fn rejects_invalid_quotas() {
+    let invalid = [
+        "",
+        "100",
+        "100/",
+        "0/s",
+        "-1/s",
+        "100 s",
+        "100/fortnight",
+        "100/s burst",
+        "100/s burst 0",
+        "100/s burst 2 burst 3",
+        "100/s extra",
+    ];
+    for quota in invalid.iter() {
+        let parsed: Result<Quota, InvalidQuota> = quota.parse();
+        assert!(parsed.is_err(), "{:?} parsed as {:?}", quota, parsed);
+    }
+}
+
+#[test]
+fn formats_canonically() {
+    let quotas = [
+        ("100/min", "100/min"),
+        ("100 per 60s", "100/min"),
+        ("5 per 2s burst 20", "5/2s burst 20"),
+        ("10/1500ms weight 2", "10/1500ms weight 2"),
+        ("7/100ms", "7/100ms"),
+        ("1/24h", "1/d"),
+        ("3/1us", "3/us"),
+    ];
+    for (input, canonical) in quotas.iter() {
+        let quota: Quota = input.parse().unwrap();
+        assert_eq!(*canonical, quota.to_string());
+        assert_eq!(Ok(quota), canonical.parse());
+    }
+}
+
+#[test]
+fn round_trips_edge_values() {
+    let max = nonzero!(u32::MAX);
+    let quotas = [
+        Quota::new(nonzero!(1u32), Duration::new(0, 0)),
+        Quota::new(nonzero!(1u32), Duration::from_nanos(1)),
+        Quota::new(nonzero!(3u32), Duration::from_nanos(5_000_000_001)),
+        Quota::new(max, Duration::new(u64::MAX, 999_999_999)),
+        Quota::new(max, Duration::from_secs(86_400 * 1_000_000))
+            .with_cell_weight(max)
+            .with_burst(max),
+    ];
+    for quota in quotas.iter() {
+        let formatted = quota.to_string();
+        assert_eq!(Ok(*quota), formatted.parse(), "{:?}", formatted);
+    }
+    assert_eq!("1/0ns", quotas[0].to_string());
+
+    // Time units beyond the longest duration don't parse:
+    let too_long: Result<Quota, InvalidQuota> = "1/18446744073709551616s".parse();
+    assert!(too_long.is_err());
+}
+
+#[test]
+fn builds_limiters() {
+    let quota: Quota = "2/s burst 5".parse().unwrap();
+    let now = current_moment();
+
+    let mut lim = DirectRateLimiter::<GCRA>::build_with_quota(quota)
+        .build()
+        .unwrap();
+    assert_eq!(Ok(()), lim.check_n_at(5, now));
+    assert!(lim.check_at(now).is_err());
+
+    let mut lim = DirectRateLimiter::<LeakyBucket>::with_quota(quota).unwrap();
+    assert_eq!(Ok(()), lim.check_n_at(5, now));
+    assert!(lim.check_at(now).is_err());
+
+    assert!(DirectRateLimiter::<GCRA>::with_quota(Quota::new(
+        nonzero!(1u32),
+        Duration::from_secs(0)
+    ))
+    .is_err());
+}
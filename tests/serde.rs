@@ -0,0 +1,54 @@
+#![cfg(feature = "serde")]
+
+extern crate ratelimit_meter;
+#[macro_use]
+extern crate nonzero_ext;
+extern crate serde_json;
+
+use ratelimit_meter::{
+    algorithms::{Algorithm, ExportedState},
+    DirectRateLimiter, LeakyBucket, Quota, GCRA,
+};
+use std::time::Duration;
+
+#[test]
+fn quota_round_trips() {
+    let quota = Quota::new(nonzero!(5u32), Duration::from_secs(2)).with_burst(nonzero!(20u32));
+    let json = serde_json::to_string(&quota).unwrap();
+    assert_eq!("\"5/2s burst 20\"", json);
+    assert_eq!(quota, serde_json::from_str::<Quota>(&json).unwrap());
+
+    assert!(serde_json::from_str::<Quota>("\"5 per fortnight\"").is_err());
+    assert!(serde_json::from_str::<Quota>("5").is_err());
+}
+
+#[test]
+fn algorithms_round_trip() {
+    let gcra =
+        <GCRA as Algorithm>::construct(nonzero!(100u32), nonzero!(2u32), Duration::from_secs(60))
+            .unwrap();
+    let json = serde_json::to_string(&gcra).unwrap();
+    assert_eq!("\"100/min weight 2\"", json);
+    let mut lim =
+        DirectRateLimiter::<GCRA>::with_algorithm(serde_json::from_str::<GCRA>(&json).unwrap());
+    assert_eq!(Ok(()), lim.check_n(50));
+    assert!(lim.check().is_err());
+
+    let lb: LeakyBucket = serde_json::from_str("\"10/s burst 5\"").unwrap();
+    assert_eq!("\"10/s burst 5\"", serde_json::to_string(&lb).unwrap());
+}
+
+#[test]
+fn rejects_inconsistent_parameters() {
+    // The cells are heavier than the bucket is large:
+    let err = serde_json::from_str::<GCRA>("\"1/s weight 2\"").unwrap_err();
+    assert!(err.to_string().contains("too small"), "{}", err);
+    assert!(serde_json::from_str::<LeakyBucket>("\"10/s weight 2 burst 1\"").is_err());
+}
+
+#[test]
+fn exported_states_round_trip() {
+    let state = ExportedState::new(Duration::new(1_500_000_000, 250));
+    let json = serde_json::to_string(&state).unwrap();
+    assert_eq!(state, serde_json::from_str::<ExportedState>(&json).unwrap());
+}
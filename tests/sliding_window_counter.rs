@@ -0,0 +1,192 @@
+extern crate ratelimit_meter;
+#[macro_use]
+extern crate nonzero_ext;
+
+use ratelimit_meter::{
+    algorithms::Algorithm,
+    test_utilities::{
+        current_moment,
+        fuzz::{assert_info_matches_snapshot, assert_peek_predicts_check},
+    },
+    DirectRateLimiter, NegativeMultiDecision, NonConformance, SlidingWindowCounter,
+};
+use std::time::Duration;
+
+#[test]
+fn weights_previous_window_by_overlap() {
+    let mut lim = DirectRateLimiter::<SlidingWindowCounter>::per_second(nonzero!(4u32));
+    let now = current_moment();
+    let ms = Duration::from_millis(1);
+    lim.check_n_at(4, now).unwrap();
+
+    // The previous window's 4 cells count fully at the start of the
+    // next window, and less the further into it a cell arrives:
+    assert!(lim.peek_at(now + ms * 1000).is_err());
+    assert_eq!(Ok(()), lim.peek_n_at(1, now + ms * 1250));
+    assert!(lim.peek_n_at(2, now + ms * 1250).is_err());
+    assert_eq!(Ok(()), lim.peek_n_at(3, now + ms * 1750));
+    assert!(lim.peek_n_at(4, now + ms * 1750).is_err());
+
+    // Fractions of a cell still count:
+    assert!(lim.peek_n_at(4, now + ms * 1999).is_err());
+    assert_eq!(Ok(()), lim.peek_n_at(4, now + ms * 2000));
+}
+
+#[test]
+fn windows_start_at_first_cell() {
+    let mut lim = DirectRateLimiter::<SlidingWindowCounter>::per_second(nonzero!(4u32));
+    let start = current_moment() + Duration::from_millis(300);
+    let ms = Duration::from_millis(1);
+    lim.check_n_at(4, start).unwrap();
+
+    // The first window lasts a full second from the first cell:
+    assert!(lim.check_at(start + ms * 999).is_err());
+    assert!(lim.check_at(start + ms * 1000).is_err());
+    assert_eq!(Ok(()), lim.check_n_at(2, start + ms * 1500));
+    assert!(lim.check_at(start + ms * 1500).is_err());
+}
+
+#[test]
+fn skipping_windows_keeps_alignment() {
+    let mut lim = DirectRateLimiter::<SlidingWindowCounter>::per_second(nonzero!(4u32));
+    let now = current_moment();
+    let ms = Duration::from_millis(1);
+    lim.check_n_at(4, now).unwrap();
+
+    // Several windows later, both counts are gone, and the current
+    // window started at a whole number of windows after the first:
+    assert_eq!(Ok(()), lim.check_n_at(4, now + ms * 5500));
+    assert_eq!(Ok(()), lim.check_n_at(2, now + ms * 6500));
+    assert!(lim.check_at(now + ms * 6500).is_err());
+}
+
+#[test]
+fn forgets_old_windows() {
+    let mut lim = DirectRateLimiter::<SlidingWindowCounter>::per_second(nonzero!(5u32));
+    let now = current_moment();
+    let ms = Duration::from_millis(1);
+    assert_eq!(Ok(()), lim.check_n_at(5, now));
+    assert_eq!(Ok(()), lim.check_n_at(5, now + ms * 2000));
+    assert_eq!(Ok(()), lim.check_n_at(5, now + ms * 7300));
+}
+
+#[test]
+fn rejects_zero_windows() {
+    assert!(<SlidingWindowCounter as Algorithm>::construct(
+        nonzero!(4u32),
+        nonzero!(1u32),
+        Duration::new(0, 0)
+    )
+    .is_err());
+}
+
+#[test]
+fn wait_time_in_current_window() {
+    let lim =
+        SlidingWindowCounter::construct(nonzero!(4u32), nonzero!(1u32), Duration::from_secs(1))
+            .unwrap();
+    let state = <SlidingWindowCounter as Algorithm>::BucketState::default();
+    let now = current_moment();
+    let ms = Duration::from_millis(1);
+    lim.test_n_and_update(&state, 4, now).unwrap();
+    lim.test_and_update(&state, now + ms * 1500).unwrap();
+
+    // 1 cell in the current window, 4 weighted ones in the
+    // previous. 2 more fit once the previous counts as 1:
+    match lim.test_n_and_update(&state, 2, now + ms * 1500) {
+        Err(NegativeMultiDecision::BatchNonConforming(2, nc)) => {
+            assert_eq!(ms * 250, nc.wait_time_from(now + ms * 1500));
+            assert_eq!(
+                Ok(()),
+                lim.test_n_and_update(&state, 2, nc.earliest_possible())
+            );
+        }
+        other => panic!("Did not expect {:?}", other),
+    }
+}
+
+#[test]
+fn wait_time_across_windows() {
+    let lim =
+        SlidingWindowCounter::construct(nonzero!(4u32), nonzero!(1u32), Duration::from_secs(1))
+            .unwrap();
+    let state = <SlidingWindowCounter as Algorithm>::BucketState::default();
+    let now = current_moment();
+    let ms = Duration::from_millis(1);
+    lim.test_n_and_update(&state, 4, now).unwrap();
+
+    // The current window is full; 2 cells fit half-way into the next window:
+    match lim.test_n_and_update(&state, 2, now + ms * 100) {
+        Err(NegativeMultiDecision::BatchNonConforming(2, nc)) => {
+            assert_eq!(ms * 1400, nc.wait_time_from(now + ms * 100));
+            assert_eq!(
+                Ok(()),
+                lim.test_n_and_update(&state, 2, nc.earliest_possible())
+            );
+        }
+        other => panic!("Did not expect {:?}", other),
+    }
+}
+
+#[test]
+fn refund_gives_capacity_back() {
+    let mut lim = DirectRateLimiter::<SlidingWindowCounter>::per_second(nonzero!(5u32));
+    let now = current_moment();
+    let ms = Duration::from_millis(1);
+    lim.check_n_at(5, now).unwrap();
+    lim.refund_n_at(2, now);
+    assert_eq!(Ok(()), lim.check_n_at(2, now));
+    assert!(lim.check_at(now).is_err());
+
+    // Refunds reach back into the previous window:
+    assert!(lim.check_at(now + ms * 1000).is_err());
+    lim.refund_n_at(10, now + ms * 1000);
+    assert_eq!(Ok(()), lim.check_n_at(5, now + ms * 1000));
+}
+
+#[test]
+fn peek_predicts_check() {
+    let mut lim = DirectRateLimiter::<SlidingWindowCounter>::per_second(nonzero!(5u32));
+    assert_peek_predicts_check(&mut lim, current_moment(), 7, Duration::from_millis(300));
+}
+
+#[test]
+fn snapshot_resets_once_both_windows_are_empty() {
+    let mut lim = DirectRateLimiter::<SlidingWindowCounter>::per_second(nonzero!(4u32));
+    let now = current_moment();
+    let ms = Duration::from_millis(1);
+    lim.check_n_at(4, now).unwrap();
+
+    // Cells in the current window still weigh in during the next
+    // one, so they only stop counting at its end:
+    let snapshot = lim.snapshot_at(now + ms * 500);
+    assert_eq!(
+        (4, 0, now + ms * 2000),
+        (snapshot.limit(), snapshot.remaining(), snapshot.reset_at())
+    );
+    // Half-way into the next window, they count half:
+    let snapshot = lim.snapshot_at(now + ms * 1500);
+    assert_eq!(
+        (2, now + ms * 2000),
+        (snapshot.remaining(), snapshot.reset_at())
+    );
+
+    // A cell in the next window pushes the reset out by a window:
+    lim.check_at(now + ms * 1500).unwrap();
+    assert_eq!(now + ms * 3000, lim.snapshot_at(now + ms * 1500).reset_at());
+    let snapshot = lim.snapshot_at(now + ms * 3000);
+    assert_eq!(
+        (4, now + ms * 3000),
+        (snapshot.remaining(), snapshot.reset_at())
+    );
+}
+
+#[test]
+fn info_matches_snapshot() {
+    let (mut lim, mut plain) = (
+        DirectRateLimiter::<SlidingWindowCounter>::per_second(nonzero!(4u32)),
+        DirectRateLimiter::<SlidingWindowCounter>::per_second(nonzero!(4u32)),
+    );
+    let ms = Duration::from_millis(1);
+    assert_info_matches_snapshot(&mut lim, &mut plain, current_moment(), 6, ms * 300);
+}
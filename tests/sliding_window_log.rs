@@ -0,0 +1,284 @@
+extern crate ratelimit_meter;
+#[macro_use]
+extern crate nonzero_ext;
+
+use ratelimit_meter::{
+    algorithms::Algorithm,
+    test_utilities::{current_moment, fuzz::assert_peek_predicts_check},
+    DirectRateLimiter, NegativeMultiDecision, NonConformance, SlidingWindowLog,
+};
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn accepts_first_cell() {
+    let mut lim = DirectRateLimiter::<SlidingWindowLog>::per_second(nonzero!(5u32));
+    assert_eq!(Ok(()), lim.check_at(current_moment()));
+}
+
+#[test]
+fn rejects_too_many() {
+    let mut lim = DirectRateLimiter::<SlidingWindowLog>::per_second(nonzero!(2u32));
+    let now = current_moment();
+    let ms = Duration::from_millis(1);
+    assert_eq!(Ok(()), lim.check_at(now));
+    assert_eq!(Ok(()), lim.check_at(now + ms * 500));
+    assert_ne!(Ok(()), lim.check_at(now + ms * 999));
+
+    // the first cell has left the window:
+    assert_eq!(Ok(()), lim.check_at(now + ms * 1000));
+    assert_ne!(Ok(()), lim.check_at(now + ms * 1499), "{:?}", lim);
+    assert_eq!(Ok(()), lim.check_at(now + ms * 1500));
+}
+
+#[test]
+fn never_allows_more_than_capacity() {
+    let mut lim = DirectRateLimiter::<SlidingWindowLog>::per_second(nonzero!(5u32));
+    let now = current_moment();
+    let ms = Duration::from_millis(1);
+
+    // Should not allow the first 15 cells on a capacity 5 bucket:
+    assert_ne!(Ok(()), lim.check_n_at(15, now));
+
+    // After 3 and 20 seconds, it should not allow 15 on that bucket either:
+    assert_ne!(Ok(()), lim.check_n_at(15, now + (ms * 3 * 1000)));
+    let result = lim.check_n_at(15, now + (ms * 20 * 1000));
+    match result {
+        Err(NegativeMultiDecision::InsufficientCapacity(n)) => assert_eq!(n, 15),
+        _ => panic!("Did not expect {:?}", result),
+    }
+}
+
+#[test]
+fn respects_cell_weight() {
+    let lim = SlidingWindowLog::construct(nonzero!(5u32), nonzero!(2u32), Duration::from_secs(1))
+        .unwrap();
+    let state = <SlidingWindowLog as Algorithm>::BucketState::default();
+    let now = current_moment();
+    assert_eq!(Ok(()), lim.test_n_and_update(&state, 2, now));
+    assert!(lim.test_and_update(&state, now).is_err());
+    assert_eq!(
+        Err(NegativeMultiDecision::InsufficientCapacity(3)),
+        lim.test_n_and_update(&state, 3, now)
+    );
+}
+
+#[test]
+fn no_window_exceeds_capacity() {
+    let mut lim = DirectRateLimiter::<SlidingWindowLog>::per_second(nonzero!(10u32));
+    let now = current_moment();
+    let ms = Duration::from_millis(1);
+    let mut allowed = vec![];
+    for i in 0..5000 {
+        let at = now + ms * i;
+        if lim.check_at(at).is_ok() {
+            allowed.push(i);
+        }
+    }
+    for (n, start) in allowed.iter().enumerate() {
+        let in_window = allowed[n..]
+            .iter()
+            .take_while(|&&at| at < start + 1000)
+            .count();
+        assert!(
+            in_window <= 10,
+            "{} cells in window at {}",
+            in_window,
+            start
+        );
+    }
+    assert_eq!(50, allowed.len());
+}
+
+#[test]
+fn batch_waits_for_oldest_cells() {
+    let lim = SlidingWindowLog::construct(nonzero!(3u32), nonzero!(1u32), Duration::from_secs(1))
+        .unwrap();
+    let state = <SlidingWindowLog as Algorithm>::BucketState::default();
+    let now = current_moment();
+    let ms = Duration::from_millis(1);
+    lim.test_and_update(&state, now).unwrap();
+    lim.test_and_update(&state, now + ms * 100).unwrap();
+    lim.test_and_update(&state, now + ms * 200).unwrap();
+
+    // Two cells fit once the two oldest cells have left the window:
+    match lim.test_n_and_update(&state, 2, now + ms * 300) {
+        Err(NegativeMultiDecision::BatchNonConforming(2, nc)) => {
+            assert_eq!(ms * 800, nc.wait_time_from(now + ms * 300));
+            assert_eq!(
+                Ok(()),
+                lim.test_n_and_update(&state, 2, nc.earliest_possible())
+            );
+        }
+        other => panic!("Did not expect {:?}", other),
+    }
+
+    // should always accommodate 0 cells:
+    assert_eq!(Ok(()), lim.test_n_and_update(&state, 0, now + ms * 1100));
+}
+
+#[test]
+fn correct_wait_time() {
+    let mut lim = DirectRateLimiter::<SlidingWindowLog>::per_second(nonzero!(5u32));
+    let mut now = current_moment();
+    let ms = Duration::from_millis(1);
+    let mut conforming = 0;
+    for _i in 0..20 {
+        now += ms;
+        let res = lim.check_at(now);
+        match res {
+            Ok(()) => {
+                conforming += 1;
+            }
+            Err(wait) => {
+                now += wait.wait_time_from(now);
+                assert_eq!(Ok(()), lim.check_at(now));
+                conforming += 1;
+            }
+        }
+    }
+    assert_eq!(20, conforming);
+}
+
+#[test]
+fn prevents_time_travel() {
+    let mut lim = DirectRateLimiter::<SlidingWindowLog>::per_second(nonzero!(5u32));
+    let now = current_moment() + Duration::from_secs(1);
+    let ms = Duration::from_millis(1);
+
+    assert!(lim.check_at(now).is_ok());
+    assert!(lim.check_at(now - ms).is_ok());
+    assert!(lim.check_at(now - ms * 500).is_ok());
+}
+
+#[test]
+fn actual_threadsafety() {
+    let mut lim = DirectRateLimiter::<SlidingWindowLog>::per_second(nonzero!(20u32));
+    let now = current_moment();
+    let ms = Duration::from_millis(1);
+    let mut children = vec![];
+
+    lim.check_at(now).unwrap();
+    for _i in 0..19 {
+        let mut lim = lim.clone();
+        children.push(thread::spawn(move || lim.check_at(now).unwrap()));
+    }
+    for child in children {
+        child.join().unwrap();
+    }
+    assert!(lim.check_at(now + ms * 2).is_err());
+    assert_eq!(Ok(()), lim.check_at(now + ms * 1000));
+}
+
+#[test]
+fn rejects_zero_windows() {
+    assert!(<SlidingWindowLog as Algorithm>::construct(
+        nonzero!(2u32),
+        nonzero!(1u32),
+        Duration::new(0, 0)
+    )
+    .is_err());
+}
+
+#[test]
+fn burst_must_match_capacity() {
+    let d = Duration::from_secs(1);
+    assert!(<SlidingWindowLog as Algorithm>::construct_with_burst(
+        nonzero!(5u32),
+        nonzero!(1u32),
+        d,
+        nonzero!(5u32)
+    )
+    .is_ok());
+    assert!(<SlidingWindowLog as Algorithm>::construct_with_burst(
+        nonzero!(5u32),
+        nonzero!(1u32),
+        d,
+        nonzero!(10u32)
+    )
+    .is_err());
+}
+
+#[test]
+fn charge_goes_into_debt() {
+    let lim = SlidingWindowLog::construct(nonzero!(3u32), nonzero!(1u32), Duration::from_secs(1))
+        .unwrap();
+    let state = <SlidingWindowLog as Algorithm>::BucketState::default();
+    let now = current_moment();
+    let ms = Duration::from_millis(1);
+    lim.charge_n(&state, 1, 2, now);
+    // Only two cells beyond capacity are charged:
+    lim.charge_n(&state, 10, 2, now + ms * 500);
+    assert_eq!(
+        now + ms * 1500,
+        lim.test_and_update(&state, now + ms * 1000)
+            .unwrap_err()
+            .earliest_possible()
+    );
+    assert_eq!(Ok(()), lim.test_and_update(&state, now + ms * 1500));
+}
+
+#[test]
+fn charge_keeps_one_entry_per_instant() {
+    let lim = SlidingWindowLog::construct(nonzero!(3u32), nonzero!(1u32), Duration::from_secs(1))
+        .unwrap();
+    let state = <SlidingWindowLog as Algorithm>::BucketState::default();
+    let now = current_moment();
+    let ms = Duration::from_millis(1);
+    // This would take up billions of log entries if each cell got its own:
+    lim.charge_n(&state, u32::MAX, u32::MAX, now);
+    lim.refund_n(&state, u32::MAX - 4, now + ms * 500);
+    assert_eq!(
+        now + ms * 1000,
+        lim.test_and_update(&state, now + ms * 500)
+            .unwrap_err()
+            .earliest_possible()
+    );
+    assert_eq!(Ok(()), lim.test_n_and_update(&state, 3, now + ms * 1000));
+}
+
+#[test]
+fn peek_predicts_check() {
+    let mut lim = DirectRateLimiter::<SlidingWindowLog>::per_second(nonzero!(5u32));
+    assert_peek_predicts_check(&mut lim, current_moment(), 7, Duration::from_millis(300));
+}
+
+#[test]
+fn snapshot_resets_when_the_newest_cell_leaves_the_window() {
+    let mut lim = DirectRateLimiter::<SlidingWindowLog>::per_second(nonzero!(5u32));
+    let now = current_moment();
+    let ms = Duration::from_millis(1);
+    lim.check_at(now).unwrap();
+    lim.check_at(now + ms * 200).unwrap();
+
+    // Capacity comes back cell by cell, but the window is only empty
+    // once the newest cell has left it:
+    for &(at, remaining) in &[
+        (now + ms * 300, 3),
+        (now + ms * 1000, 4),
+        (now + ms * 1199, 4),
+    ] {
+        let snapshot = lim.snapshot_at(at);
+        assert_eq!(
+            (5, remaining, now + ms * 1200),
+            (snapshot.limit(), snapshot.remaining(), snapshot.reset_at())
+        );
+    }
+    let snapshot = lim.snapshot_at(now + ms * 1200);
+    assert_eq!(
+        (5, now + ms * 1200),
+        (snapshot.remaining(), snapshot.reset_at())
+    );
+}
+
+#[test]
+fn check_up_to_n_accepts_what_fits() {
+    let mut lim = DirectRateLimiter::<SlidingWindowLog>::per_second(nonzero!(5u32));
+    let now = current_moment();
+    let ms = Duration::from_millis(1);
+    let (accepted, rest) = lim.check_up_to_n_at(7, now);
+    assert_eq!(5, accepted);
+    assert_eq!(now + ms * 1000, rest.unwrap().earliest_possible());
+    assert_eq!(0, lim.check_up_to_n_at(2, now + ms * 500).0);
+    assert_eq!((5, None), lim.check_up_to_n_at(5, now + ms * 1000));
+}